@@ -0,0 +1,40 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Error handling for beam code.
+ */
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BeamError {
+    /// An MWA tile has 16 dipoles, each with a delay.
+    #[error("Expected 16 dipole delays, but got {0}")]
+    WrongNumberOfDelays(usize),
+
+    /// A delay was bigger than an MWA beamformer can apply.
+    #[error("Dipole delay {0} is not between 0 and 31 (or 32 for a dead dipole)")]
+    BadDelay(u32),
+}
+
+#[derive(Error, Debug)]
+pub enum FeeBeamError {
+    /// The file doesn't have an HDF5 superblock.
+    #[error("{0} is not an HDF5 file")]
+    NotHdf5(PathBuf),
+
+    /// The file is HDF5, but the name of a dataset that MWA FEE beam files have
+    /// doesn't appear in it.
+    #[error("{file} doesn't mention the '{dataset}' dataset(s) of an MWA FEE beam file")]
+    MissingDataset {
+        file: PathBuf,
+        dataset: &'static str,
+    },
+
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+}
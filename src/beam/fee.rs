@@ -0,0 +1,182 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to find and check the HDF5 file of the 2016 MWA FEE beam.
+ *
+ * mongoose doesn't use the FEE beam itself, but the RTS needs the path to its
+ * file (hdf5Filename) when TileBeamType=1.
+ */
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::error::FeeBeamError;
+
+/// The usual name of the FEE beam file.
+pub const FEE_BEAM_FILE_NAME: &str = "mwa_full_embedded_element_pattern.h5";
+
+/// The environment variable that may contain the path to the FEE beam file.
+pub const FEE_BEAM_ENV_VAR: &str = "MWA_BEAM_FILE";
+
+/// The environment variable that may contain the directories to look for the
+/// FEE beam file in (separated like PATH), instead of the standard ones.
+pub const FEE_BEAM_DIRS_ENV_VAR: &str = "MONGOOSE_FEE_BEAM_DIRS";
+
+/// Directories where the FEE beam file is commonly installed.
+pub const STANDARD_FEE_BEAM_DIRS: [&str; 4] = [
+    "/pawsey/mwa",
+    "/usr/local/share/mwa",
+    "/usr/share/mwa",
+    "/opt/mwa",
+];
+
+/// Every HDF5 file has this signature at the start of its superblock.
+const HDF5_SIGNATURE: &[u8] = b"\x89HDF\r\n\x1a\n";
+
+/// The datasets that an MWA FEE beam file must have. Besides the spherical
+/// harmonic modes, there are X and Y datasets for each of the 16 dipoles at
+/// each frequency, e.g. "X1_51200000".
+const FEE_BEAM_DATASETS: [&str; 5] = ["modes", "X1_", "Y1_", "X16_", "Y16_"];
+
+/// The places the FEE beam file is looked for if it isn't specified. These are
+/// in the directories of MONGOOSE_FEE_BEAM_DIRS if it's set, otherwise
+/// `~/.local/share/mwa` and the `STANDARD_FEE_BEAM_DIRS`.
+pub fn standard_fee_beam_files() -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = match std::env::var_os(FEE_BEAM_DIRS_ENV_VAR) {
+        Some(d) => std::env::split_paths(&d).collect(),
+        None => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".local/share/mwa"))
+            .into_iter()
+            .chain(STANDARD_FEE_BEAM_DIRS.iter().map(PathBuf::from))
+            .collect(),
+    };
+    dirs.into_iter()
+        .map(|d| d.join(FEE_BEAM_FILE_NAME))
+        .collect()
+}
+
+/// Find the FEE beam file. The MWA_BEAM_FILE environment variable is used if
+/// it's set, otherwise the first of `files` that exists (e.g.
+/// `standard_fee_beam_files`). The file isn't checked; see
+/// `check_fee_beam_file`. If the file can't be found, `None` is returned.
+pub fn find_fee_beam_file(files: &[PathBuf]) -> Option<PathBuf> {
+    match std::env::var_os(FEE_BEAM_ENV_VAR) {
+        Some(f) if !f.is_empty() => Some(PathBuf::from(f)),
+        _ => files.iter().find(|f| f.exists()).cloned(),
+    }
+}
+
+/// Check that a file looks like an MWA FEE beam file: it must have an HDF5
+/// signature, and the names of the FEE beam's datasets must appear in it. The
+/// HDF5 structure isn't parsed, so this can't tell whether the datasets are
+/// intact; it only catches the wrong file being given.
+pub fn check_fee_beam_file(file: &Path) -> Result<(), FeeBeamError> {
+    let mut f = File::open(file)?;
+    if !has_hdf5_signature(&mut f)? {
+        return Err(FeeBeamError::NotHdf5(file.to_path_buf()));
+    }
+
+    // Look for the names of the datasets; HDF5 stores them uncompressed. Read in chunks that overlap by enough to
+    // not miss a name on a boundary.
+    f.seek(SeekFrom::Start(0))?;
+    let overlap = FEE_BEAM_DATASETS.iter().map(|d| d.len()).max().unwrap_or(0) - 1;
+    let mut missing: Vec<&'static str> = FEE_BEAM_DATASETS.to_vec();
+    let mut buf = vec![0; 1 << 20];
+    let mut filled = 0;
+    loop {
+        let n = f.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+        let chunk = &buf[..filled];
+        missing.retain(|d| !chunk.windows(d.len()).any(|w| w == d.as_bytes()));
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if filled == buf.len() {
+            buf.copy_within(filled - overlap.., 0);
+            filled = overlap;
+        }
+    }
+
+    Err(FeeBeamError::MissingDataset {
+        file: file.to_path_buf(),
+        dataset: missing[0],
+    })
+}
+
+/// HDF5 files may have a "user block" before the superblock, so the signature
+/// can be at byte 0, 512, 1024, 2048, etc.
+fn has_hdf5_signature(f: &mut File) -> Result<bool, std::io::Error> {
+    let len = f.metadata()?.len();
+    let mut offset = 0;
+    let mut sig = [0; 8];
+    while offset + HDF5_SIGNATURE.len() as u64 <= len {
+        f.seek(SeekFrom::Start(offset))?;
+        f.read_exact(&mut sig)?;
+        if sig == HDF5_SIGNATURE {
+            return Ok(true);
+        }
+        offset = if offset == 0 { 512 } else { offset * 2 };
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Write a file that looks enough like an MWA FEE beam file to pass
+    /// `check_fee_beam_file`.
+    pub(crate) fn write_fake_fee_beam_file(path: &Path) {
+        let mut f = File::create(path).unwrap();
+        f.write_all(HDF5_SIGNATURE).unwrap();
+        f.write_all(&[0; 100]).unwrap();
+        f.write_all(b"modes\0X1_51200000\0Y1_51200000\0X16_51200000\0Y16_51200000\0")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check_fee_beam_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join(FEE_BEAM_FILE_NAME);
+        write_fake_fee_beam_file(&file);
+        assert!(check_fee_beam_file(&file).is_ok());
+
+        // A user block before the superblock, and dataset names straddling
+        // the chunks that are read.
+        let mut f = File::create(&file).unwrap();
+        f.write_all(&[0; 512]).unwrap();
+        f.write_all(HDF5_SIGNATURE).unwrap();
+        f.write_all(&vec![0; (1 << 20) - 512 - 8 - 2]).unwrap();
+        f.write_all(b"modes\0X1_\0Y1_\0X16_\0Y16_").unwrap();
+        drop(f);
+        assert!(check_fee_beam_file(&file).is_ok());
+
+        // An HDF5 file that isn't the FEE beam.
+        let mut f = File::create(&file).unwrap();
+        f.write_all(HDF5_SIGNATURE).unwrap();
+        f.write_all(b"modes\0X1_\0Y1_\0").unwrap();
+        drop(f);
+        match check_fee_beam_file(&file) {
+            Err(FeeBeamError::MissingDataset { dataset, .. }) => assert_eq!(dataset, "X16_"),
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        // Not HDF5 at all.
+        std::fs::write(&file, "modes X1_ Y1_ X16_ Y16_").unwrap();
+        assert!(matches!(
+            check_fee_beam_file(&file),
+            Err(FeeBeamError::NotHdf5(_))
+        ));
+        assert!(matches!(
+            check_fee_beam_file(&dir.path().join("nothing.h5")),
+            Err(FeeBeamError::IO(_))
+        ));
+    }
+}
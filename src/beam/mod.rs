@@ -0,0 +1,243 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Primary beam code.
+ *
+ * The analytic MWA tile beam is the response of a 4x4 grid of ideal short
+ * dipoles above a ground screen, steered by the beamformer delays. It is much
+ * cheaper than the FEE beam, and is good enough to rank sources by their
+ * apparent brightness. The `fee` module finds and checks the FEE beam's file.
+ */
+
+pub mod error;
+pub mod fee;
+
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use error::BeamError;
+
+/// The separation between adjacent dipoles in an MWA tile [metres]
+pub const MWA_DIPOLE_SEPARATION: f64 = 1.1;
+
+/// The height of MWA dipoles above the ground screen [metres]
+pub const MWA_DIPOLE_HEIGHT: f64 = 0.278;
+
+/// The length of a single MWA beamformer delay step [seconds]
+pub const MWA_DELAY_STEP: f64 = 435e-12;
+
+/// A delay of 32 means that a dipole was turned off.
+pub const DEAD_DIPOLE_DELAY: u32 = 32;
+
+/// Get the indices of the dead dipoles (those with a delay of 32) in a tile's
+/// delays.
+pub fn dead_dipoles(delays: &[u32]) -> Vec<usize> {
+    delays
+        .iter()
+        .enumerate()
+        .filter(|(_, &d)| d == DEAD_DIPOLE_DELAY)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// The beamformer delays of a tile's X and Y dipoles.
+#[derive(Debug, Clone, Copy)]
+pub struct TileDelays<'a> {
+    /// Is the tile flagged (e.g. in the metafits)?
+    pub flagged: bool,
+    pub x: &'a [u32],
+    pub y: &'a [u32],
+}
+
+/// A tile with dead dipoles.
+#[derive(Debug, PartialEq)]
+pub struct DeadDipoleTile {
+    /// The index of the tile in the slice given to `dead_dipole_tiles`.
+    pub tile: usize,
+
+    /// The indices of the tile's dead X dipoles.
+    pub x: Vec<usize>,
+
+    /// The indices of the tile's dead Y dipoles.
+    pub y: Vec<usize>,
+}
+
+/// Get the unflagged tiles that have dead dipoles. Flagged tiles are ignored,
+/// as they won't be used anyway.
+pub fn dead_dipole_tiles(tiles: &[TileDelays]) -> Vec<DeadDipoleTile> {
+    tiles
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| !t.flagged)
+        .map(|(i, t)| DeadDipoleTile {
+            tile: i,
+            x: dead_dipoles(t.x),
+            y: dead_dipoles(t.y),
+        })
+        .filter(|t| !t.x.is_empty() || !t.y.is_empty())
+        .collect()
+}
+
+/// Get the Stokes I response of an analytic MWA tile beam toward an azimuth
+/// (east of north) and zenith angle [radians] at a frequency [Hz]. `delays` are
+/// the 16 beamformer delays, ordered from the north-west dipole, east along
+/// each row, then south.
+///
+/// The response is normalised to 1 at zenith for a zenith-pointed tile with
+/// all dipoles working. Dead dipoles (delays of 32) don't contribute, and
+/// directions below the horizon have no response.
+pub fn analytic_tile_response(
+    az: f64,
+    za: f64,
+    freq_hz: f64,
+    delays: &[u32],
+) -> Result<f64, BeamError> {
+    if delays.len() != 16 {
+        return Err(BeamError::WrongNumberOfDelays(delays.len()));
+    }
+    if let Some(&d) = delays.iter().find(|&&d| d > DEAD_DIPOLE_DELAY) {
+        return Err(BeamError::BadDelay(d));
+    }
+    if za >= FRAC_PI_2 {
+        return Ok(0.0);
+    }
+
+    let wavenumber = 2.0 * PI * freq_hz / crate::VELC;
+    let (s_za, c_za) = za.sin_cos();
+    let (s_az, c_az) = az.sin_cos();
+
+    // Sum the phases of the working dipoles.
+    let (mut re, mut im) = (0.0, 0.0);
+    for (i, &d) in delays.iter().enumerate() {
+        if d == DEAD_DIPOLE_DELAY {
+            continue;
+        }
+        let east = (i % 4) as f64 - 1.5;
+        let north = 1.5 - (i / 4) as f64;
+        let geometric = wavenumber * MWA_DIPOLE_SEPARATION * s_za * (east * s_az + north * c_az);
+        let instrumental = 2.0 * PI * freq_hz * MWA_DELAY_STEP * d as f64;
+        let (s, c) = (geometric - instrumental).sin_cos();
+        re += c;
+        im += s;
+    }
+    let array_factor = (re * re + im * im) / 256.0;
+
+    // The ground screen reflection, relative to zenith.
+    let ground_plane =
+        (wavenumber * MWA_DIPOLE_HEIGHT * c_za).sin() / (wavenumber * MWA_DIPOLE_HEIGHT).sin();
+
+    // The projected lengths of the east-west (X) and north-south (Y) dipoles.
+    let proj_x = 1.0 - (s_za * s_az).powi(2);
+    let proj_y = 1.0 - (s_za * c_az).powi(2);
+
+    Ok(array_factor * ground_plane.powi(2) * (proj_x + proj_y) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    const ZENITH: [u32; 16] = [0; 16];
+    // The delays of 1065880128, which is pointed east of zenith.
+    const EAST: [u32; 16] = [0, 2, 4, 6, 0, 2, 4, 6, 0, 2, 4, 6, 0, 2, 4, 6];
+
+    #[test]
+    fn test_zenith() {
+        for &freq in &[150e6, 182.415e6, 200e6] {
+            let r = analytic_tile_response(0.0, 0.0, freq, &ZENITH).unwrap();
+            assert_abs_diff_eq!(r, 1.0, epsilon = 1e-10);
+            // Away from zenith, the response drops.
+            let r = analytic_tile_response(0.0, 0.3, freq, &ZENITH).unwrap();
+            assert!(r < 1.0);
+        }
+
+        // A zenith-pointed tile is symmetric.
+        let r1 = analytic_tile_response(0.0, 0.2, 180e6, &ZENITH).unwrap();
+        let r2 = analytic_tile_response(PI, 0.2, 180e6, &ZENITH).unwrap();
+        assert_abs_diff_eq!(r1, r2, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_steering() {
+        // The beam of 1065880128 should peak near its pointing centre (az 90,
+        // alt 76.2838), not at zenith.
+        let freq = 182.415e6;
+        let pointing_za = (90.0_f64 - 76.2838).to_radians();
+        let peak = analytic_tile_response(FRAC_PI_2, pointing_za, freq, &EAST).unwrap();
+        let zenith = analytic_tile_response(0.0, 0.0, freq, &EAST).unwrap();
+        let west = analytic_tile_response(3.0 * FRAC_PI_2, pointing_za, freq, &EAST).unwrap();
+        assert!(peak > 0.9);
+        assert!(peak > zenith);
+        assert!(zenith > west);
+    }
+
+    #[test]
+    fn test_horizon_and_dead_dipoles() {
+        let r = analytic_tile_response(0.0, FRAC_PI_2 + 0.1, 180e6, &ZENITH).unwrap();
+        assert_abs_diff_eq!(r, 0.0);
+
+        let mut delays = ZENITH;
+        delays[5] = DEAD_DIPOLE_DELAY;
+        let r = analytic_tile_response(0.0, 0.0, 180e6, &delays).unwrap();
+        assert_abs_diff_eq!(r, (15.0 / 16.0_f64).powi(2), epsilon = 1e-10);
+
+        assert_eq!(dead_dipoles(&delays), vec![5]);
+        assert!(dead_dipoles(&EAST).is_empty());
+    }
+
+    #[test]
+    fn test_dead_dipole_tiles() {
+        let mut one_dead = EAST;
+        one_dead[3] = DEAD_DIPOLE_DELAY;
+        let mut two_dead = ZENITH;
+        two_dead[0] = DEAD_DIPOLE_DELAY;
+        two_dead[15] = DEAD_DIPOLE_DELAY;
+        let tile = |flagged, x, y| TileDelays { flagged, x, y };
+        let tiles = [
+            tile(false, &EAST, &EAST),
+            tile(false, &one_dead, &EAST),
+            tile(false, &ZENITH, &two_dead),
+            // Flagged tiles are ignored, even with dead dipoles.
+            tile(true, &one_dead, &one_dead),
+            tile(false, &two_dead, &one_dead),
+        ];
+        assert_eq!(
+            dead_dipole_tiles(&tiles),
+            vec![
+                DeadDipoleTile {
+                    tile: 1,
+                    x: vec![3],
+                    y: vec![]
+                },
+                DeadDipoleTile {
+                    tile: 2,
+                    x: vec![],
+                    y: vec![0, 15]
+                },
+                DeadDipoleTile {
+                    tile: 4,
+                    x: vec![0, 15],
+                    y: vec![3]
+                },
+            ]
+        );
+        assert!(dead_dipole_tiles(&tiles[..1]).is_empty());
+        assert!(dead_dipole_tiles(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_bad_delays() {
+        assert_eq!(
+            analytic_tile_response(0.0, 0.0, 180e6, &[0; 15]),
+            Err(BeamError::WrongNumberOfDelays(15))
+        );
+        let mut delays = ZENITH;
+        delays[0] = 33;
+        assert_eq!(
+            analytic_tile_response(0.0, 0.0, 180e6, &delays),
+            Err(BeamError::BadDelay(33))
+        );
+    }
+}
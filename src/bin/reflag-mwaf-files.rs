@@ -2,14 +2,28 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use anyhow::bail;
+use std::fs::File;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure};
+use fitsio::FitsFile;
 use structopt::{clap::AppSettings, StructOpt};
 
-use mongoose::cotter::Occupancy;
+use mongoose::cotter::{
+    bad_antennas_overall, bad_timesteps_overall, flag_timesteps, Occupancy, ReflagPolicy,
+};
+use mongoose::rts::{write_flagged_tiles, FLAGGED_TILES_FILE_NAME};
 
 /// Detect channels with high occupancy and flag them entirely. The input files
 /// are named 1?????????_??.mwaf in the current directory, and the resulting
 /// flags are written to RTS_1?????????_??.mwaf.
+///
+/// With --observation, all of the files of an obsid are reflagged together, so
+/// channels that are bad in most coarse bands are flagged in all of them. Bad
+/// timesteps can also be totally flagged in the new files. Tiles with high
+/// occupancy over all of the files can also be flagged; they are written to
+/// flagged_tiles.txt for the RTS.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "reflag-mwaf-files", global_settings = &[AppSettings::ColoredHelp, AppSettings::ArgRequiredElseHelp])]
 struct Opts {
@@ -17,6 +31,47 @@ struct Opts {
     /// channel. Must be between 0 and 1.
     #[structopt(short, long, default_value = "0.8")]
     threshold: f64,
+
+    /// Reflag all of the mwaf files of an obsid together, rather than each
+    /// file independently. Channels that exceed --threshold in more than
+    /// --global-threshold of the coarse bands are flagged in every band.
+    #[structopt(short, long)]
+    observation: bool,
+
+    /// With --observation, the fraction of coarse bands in which a channel
+    /// must exceed --threshold before it is flagged in every band. Must be
+    /// between 0 and 1.
+    #[structopt(long, default_value = "0.5")]
+    global_threshold: f64,
+
+    /// The number of fine channels at each edge of every coarse band to always
+    /// flag.
+    #[structopt(long, default_value = "0")]
+    edge_channels: usize,
+
+    /// Always flag the centre (DC) fine channel of every coarse band.
+    #[structopt(long)]
+    centre_channel: bool,
+
+    /// Totally flag timesteps that are flagged more than this fraction over the
+    /// files being reflagged together (each file, or each obsid with
+    /// --observation). Must be between 0 and 1. Without this, no timesteps are
+    /// flagged.
+    #[structopt(long)]
+    timestep_threshold: Option<f64>,
+
+    /// Flag tiles whose baselines are flagged more than this fraction of the
+    /// time over all of the mwaf files. Must be between 0 and 1. Without this,
+    /// no tiles are flagged.
+    #[structopt(long)]
+    tile_threshold: Option<f64>,
+
+    /// Also flag the bad tiles in a copy of this metafits file, by setting the
+    /// Flag of their inputs in the TILEDATA HDU. The copy is written to the
+    /// current directory, with "RTS_" as a prefix. The metafits must be of the
+    /// same obsid as the mwaf files. Requires --tile-threshold.
+    #[structopt(long, parse(from_os_str))]
+    metafits: Option<PathBuf>,
 }
 
 fn main() -> Result<(), anyhow::Error> {
@@ -27,8 +82,27 @@ fn main() -> Result<(), anyhow::Error> {
     } else if opts.threshold > 1.0 {
         bail!("The threshold cannot be bigger than 1.");
     }
+    ensure!(
+        opts.global_threshold >= 0.0 && opts.global_threshold <= 1.0,
+        "The global threshold must be between 0 and 1."
+    );
+    if let Some(t) = opts.timestep_threshold {
+        ensure!(
+            (0.0..=1.0).contains(&t),
+            "The timestep threshold must be between 0 and 1."
+        );
+    }
+    if let Some(t) = opts.tile_threshold {
+        ensure!(
+            t > 0.0 && t <= 1.0,
+            "The tile threshold must be bigger than 0 and at most 1."
+        );
+    } else if opts.metafits.is_some() {
+        bail!("--metafits requires --tile-threshold.");
+    }
 
-    // Get all of the mwaf files.
+    // Get all of the mwaf files. Sorting them puts each obsid's files in coarse
+    // band order.
     let mwaf_files = {
         let mut mwaf_files = vec![];
         let glob = globset::Glob::new("./1?????????_??.mwaf")?.compile_matcher();
@@ -38,6 +112,7 @@ fn main() -> Result<(), anyhow::Error> {
                 mwaf_files.push(entry);
             }
         }
+        mwaf_files.sort_unstable();
         mwaf_files
     };
 
@@ -46,12 +121,187 @@ fn main() -> Result<(), anyhow::Error> {
         bail!("No files found matching: ./1?????????_??.mwaf");
     }
 
+    // Put the files of each obsid together. The obsid comes from the files
+    // themselves (GPSTIME), as their names can't be trusted.
+    let (mwaf_files, occupancies): (Vec<PathBuf>, Vec<Occupancy>) = {
+        let mut files = mwaf_files
+            .into_iter()
+            .map(|f| {
+                let occ = Occupancy::new(&f)?;
+                Ok((f, occ))
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        files.sort_by_key(|(_, occ)| occ.obsid);
+        files.into_iter().unzip()
+    };
+
+    // The flagged tiles apply to a whole observation, so all of the files (and
+    // the metafits) must be from the same one. Check this before anything is
+    // written.
+    if opts.tile_threshold.is_some() {
+        let mut obsids: Vec<u64> = occupancies.iter().map(|o| o.obsid).collect();
+        obsids.sort_unstable();
+        obsids.dedup();
+        ensure!(
+            obsids.len() == 1,
+            "Can't flag tiles for mwaf files from more than one obsid ({})",
+            obsids
+                .iter()
+                .map(|o| o.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if let Some(metafits) = &opts.metafits {
+            let metafits_obsid = metafits_obsid(metafits)?;
+            ensure!(
+                metafits_obsid == obsids[0],
+                "{} is for obsid {}, but the mwaf files are for obsid {}",
+                metafits.display(),
+                metafits_obsid,
+                obsids[0]
+            );
+        }
+    }
+
+    let policy = ReflagPolicy {
+        band_threshold: opts.threshold,
+        global_threshold: if opts.observation {
+            Some(opts.global_threshold)
+        } else {
+            None
+        },
+        num_edge_channels: opts.edge_channels,
+        flag_centre_channel: opts.centre_channel,
+    };
+    let groups = if opts.observation {
+        obsid_groups(&occupancies)
+    } else {
+        (0..mwaf_files.len()).map(|i| i..i + 1).collect()
+    };
+
     // "Reflag" the mwaf file in a new file with "RTS_" as a prefix.
-    for mwaf_file in mwaf_files {
-        let occ = Occupancy::new(&mwaf_file)?;
-        let rts_mwaf = format!("RTS_{}", mwaf_file.strip_prefix("./")?.display());
-        occ.reflag(&rts_mwaf, opts.threshold as f64)?;
+    for group in groups {
+        let channels = policy.channels_to_flag(&occupancies[group.clone()])?;
+        let timesteps = match opts.timestep_threshold {
+            Some(t) => bad_timesteps_overall(&occupancies[group.clone()], t)?,
+            None => vec![],
+        };
+        for ((mwaf_file, occ), chans) in mwaf_files[group.clone()]
+            .iter()
+            .zip(occupancies[group.clone()].iter())
+            .zip(channels.iter())
+        {
+            let rts_mwaf = format!("RTS_{}", mwaf_file.strip_prefix("./")?.display());
+            occ.reflag_channels(&rts_mwaf, chans)?;
+            if !timesteps.is_empty() {
+                flag_timesteps(&rts_mwaf, &timesteps)?;
+            }
+        }
+        if let Some(t) = opts.timestep_threshold {
+            let label = if opts.observation {
+                occupancies[group.start].obsid.to_string()
+            } else {
+                mwaf_files[group.start]
+                    .strip_prefix("./")?
+                    .display()
+                    .to_string()
+            };
+            println!(
+                "{}: Flagged {} timesteps with more than {}% of their data flagged: {:?}",
+                label,
+                timesteps.len(),
+                t * 100.0,
+                timesteps
+            );
+        }
+
+        if opts.observation {
+            let everywhere: Vec<usize> = channels[0]
+                .iter()
+                .filter(|c| channels.iter().all(|b| b.contains(c)))
+                .cloned()
+                .collect();
+            println!(
+                "{}: Flagged channels {:?} in all {} coarse bands",
+                occupancies[group.start].obsid,
+                everywhere,
+                group.len()
+            );
+        }
+    }
+
+    if let Some(tile_threshold) = opts.tile_threshold {
+        // mwaf antenna indices are the "Antenna" numbers in the metafits,
+        // which is what the RTS expects.
+        let tiles: Vec<u32> = bad_antennas_overall(&occupancies, tile_threshold)?
+            .into_iter()
+            .map(|a| a as u32)
+            .collect();
+        let mut f = File::create(FLAGGED_TILES_FILE_NAME)?;
+        write_flagged_tiles(&mut f, &tiles)?;
+        println!(
+            "Flagged {} tiles with more than {}% of their data flagged: {:?}",
+            tiles.len(),
+            tile_threshold * 100.0,
+            tiles
+        );
+
+        if let Some(metafits) = &opts.metafits {
+            let rts_metafits = flag_metafits_tiles(metafits, &tiles)?;
+            println!("Flagged the tiles in {}", rts_metafits.display());
+        }
     }
 
     Ok(())
 }
+
+/// Get the ranges of the occupancies, sorted by obsid, that belong to each
+/// obsid.
+fn obsid_groups(occupancies: &[Occupancy]) -> Vec<Range<usize>> {
+    let mut groups: Vec<Range<usize>> = vec![];
+    for (i, occ) in occupancies.iter().enumerate() {
+        match groups.last_mut() {
+            Some(g) if occupancies[g.start].obsid == occ.obsid => g.end = i + 1,
+            _ => groups.push(i..i + 1),
+        }
+    }
+    groups
+}
+
+/// Get the obsid of a metafits file (GPSTIME).
+fn metafits_obsid(metafits: &Path) -> Result<u64, anyhow::Error> {
+    let mut meta = FitsFile::open(metafits)?;
+    let hdu = meta.primary_hdu()?;
+    let gps_time: i64 = hdu.read_key(&mut meta, "GPSTIME")?;
+    Ok(gps_time as u64)
+}
+
+/// Copy a metafits file to the current directory with "RTS_" as a prefix, and
+/// set the Flag of both inputs of each of `tiles` (their "Antenna" numbers) in
+/// the copy's TILEDATA HDU. Other flags are left alone. The path of the copy
+/// is returned.
+fn flag_metafits_tiles(metafits: &Path, tiles: &[u32]) -> Result<PathBuf, anyhow::Error> {
+    let file_name = match metafits.file_name() {
+        Some(n) => n.to_string_lossy(),
+        None => bail!("{} is not a metafits file", metafits.display()),
+    };
+    let rts_metafits = PathBuf::from(format!("RTS_{}", file_name));
+    // Copy the contents rather than the file, so that the copy is writable even
+    // if the original isn't.
+    std::io::copy(
+        &mut File::open(metafits)?,
+        &mut File::create(&rts_metafits)?,
+    )?;
+
+    let mut meta = FitsFile::edit(&rts_metafits)?;
+    let hdu = meta.hdu("TILEDATA")?;
+    let antennas: Vec<i32> = hdu.read_col(&mut meta, "Antenna")?;
+    let mut flags: Vec<i32> = hdu.read_col(&mut meta, "Flag")?;
+    for (flag, antenna) in flags.iter_mut().zip(antennas.iter()) {
+        if tiles.contains(&(*antenna as u32)) {
+            *flag = 1;
+        }
+    }
+    hdu.write_col(&mut meta, "Flag", &flags)?;
+    Ok(rts_metafits)
+}
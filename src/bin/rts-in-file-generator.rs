@@ -2,26 +2,30 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-// TODO: Use QUACKTIM/GOODTIME for StartProcessingAt
-
+use std::ffi::OsString;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, ensure};
-use mwalib::mwalibContext;
-use structopt::{clap::AppSettings, StructOpt};
-
+use mwalib::{mwalibContext, MWA_LATITUDE_RADIANS, MWA_LONGITUDE_RADIANS};
+use serde::Serialize;
+use structopt::{
+    clap::{AppSettings, ErrorKind},
+    StructOpt,
+};
+
+use mongoose::beam::fee::{check_fee_beam_file, find_fee_beam_file, standard_fee_beam_files};
+use mongoose::beam::{dead_dipole_tiles, TileDelays};
 use mongoose::rts::*;
+use mongoose::srclist::rts::parse_source_list;
 
-#[derive(Debug, Default)]
-struct Timing {
-    corr_dump_time: f64,
-    corr_dumps_per_cadence_patch: u32,
-    num_integration_bins_patch: u32,
-    corr_dumps_per_cadence_peel: u32,
-    num_integration_bins_peel: u32,
-}
+/// The width of an MWA coarse channel [Hz]. Only used if there's no metafits.
+const MWA_COARSE_CHANNEL_WIDTH_HZ: u32 = 1_280_000;
+
+/// Warn if more than this fraction of an observation's tiles have dead
+/// dipoles.
+const DEFAULT_MAX_DEAD_DIPOLE_FRACTION: f64 = 0.2;
 
 /// Generate a .in file suitable for RTS usage
 #[derive(StructOpt, Debug)]
@@ -35,20 +39,8 @@ enum Opts {
         #[structopt(flatten)]
         common: Common,
 
-        /// The number of "primary calibrators" to use (NumberOfCalibrators).
-        /// This should always be 1 for a patch.
-        #[structopt(long, default_value = "1")]
-        num_primary_cals: u32,
-
-        /// Specify the number of times to run the CML loop (NumberOfIterations).
-        /// This should be 1 for a patch.
-        #[structopt(long, default_value = "1")]
-        num_iterations: u32,
-
-        /// Write the visibilities processed by the RTS to uvfits files
-        /// (writeVisToUVFITS).
-        #[structopt(long)]
-        write_vis_to_uvfits: bool,
+        #[structopt(flatten)]
+        patch: PatchArgs,
     },
 
     /// Run the RTS in "peel" mode (direction-dependent calibration)
@@ -59,99 +51,545 @@ enum Opts {
         #[structopt(flatten)]
         common: Common,
 
-        /// The number of source calibrators to use (NumberOfIonoCalibrators).
-        #[structopt(short, long, default_value = "1000")]
-        num_cals: u32,
-
-        /// The number of sources to peel (NumberOfSourcesToPeel). If not
-        /// specified, defaults to num_cals.
-        #[structopt(long)]
-        num_peel: Option<u32>,
-
-        /// The number of "primary calibrators" to use (NumberOfCalibrators). If
-        /// this is bigger than num-cals, then it will be truncated to match
-        /// num-cals.
-        #[structopt(long, default_value = "5")]
-        num_primary_cals: u32,
-
-        /// Specify the number of times to run the CML loop (NumberOfIterations).
-        #[structopt(long, default_value = "14")]
-        num_iterations: u32,
-
-        /// Don't write the visibilities processed by the RTS to uvfits files
-        /// (writeVisToUVFITS).
-        #[structopt(long)]
-        dont_write_vis_to_uvfits: bool,
+        #[structopt(flatten)]
+        peel: PeelArgs,
+    },
+
+    /// Write both patch and peel .in files
+    ///
+    /// The observation is only read once, so the settings that are shared
+    /// between the two files are guaranteed to be consistent. Options specific
+    /// to one mode are prefixed with "patch-" or "peel-".
+    Both {
+        #[structopt(flatten)]
+        common: Common,
+
+        #[structopt(flatten)]
+        both: BothArgs,
+
+        /// Where to write the patch .in file.
+        #[structopt(long, parse(from_str), default_value = "rts_patch.in")]
+        patch_output: PathBuf,
+
+        /// Where to write the peel .in file.
+        #[structopt(long, parse(from_str), default_value = "rts_peel.in")]
+        peel_output: PathBuf,
+    },
+
+    /// Write patch and peel .in files for many observations
+    ///
+    /// Each observation's base directory is the directory containing its
+    /// metafits file, unless --base-dir is given. Observations that fail
+    /// validation (e.g. have dead dipoles in their delays, an unsupported
+    /// integration time or missing mwaf files) are skipped and summarised at
+    /// the end.
+    Batch {
+        #[structopt(flatten)]
+        common: Common,
+
+        #[structopt(flatten)]
+        both: BothArgs,
+
+        #[structopt(flatten)]
+        batch: BatchArgs,
     },
+
+    /// Set up a complete RTS job: patch and peel .in files, as well as setup
+    /// and run scripts
+    ///
+    /// The run script runs the RTS on the patch and then peel .in files. The
+    /// setup script re-creates the job directory with the same arguments. The
+    /// scheduler directives at the top of the scripts can be changed with
+    /// --scheduler-template.
+    Job {
+        #[structopt(flatten)]
+        common: Common,
+
+        #[structopt(flatten)]
+        both: BothArgs,
+
+        #[structopt(flatten)]
+        job: JobArgs,
+    },
+
+    /// Compare two RTS .in files, printing the RTS keys that differ
+    ///
+    /// The ordering of keys, comments and the "Generated by mongoose" header
+    /// are ignored. The exit code is 1 if the files differ.
+    Diff {
+        /// The first .in file.
+        #[structopt(parse(from_str))]
+        first: PathBuf,
+
+        /// The second .in file.
+        #[structopt(parse(from_str))]
+        second: PathBuf,
+    },
+}
+
+impl Opts {
+    /// The common arguments of the subcommand, if it has them.
+    fn common(&self) -> Option<&Common> {
+        match self {
+            Self::Patch { common, .. }
+            | Self::Peel { common, .. }
+            | Self::Both { common, .. }
+            | Self::Batch { common, .. }
+            | Self::Job { common, .. } => Some(common),
+            Self::Diff { .. } => None,
+        }
+    }
+}
+
+/// Arguments specific to the "patch" mode.
+#[derive(StructOpt, Debug)]
+struct PatchArgs {
+    /// The number of "primary calibrators" to use (NumberOfCalibrators).
+    /// This should always be 1 for a patch.
+    #[structopt(long, default_value = "1")]
+    num_primary_cals: u32,
+
+    /// Specify the number of times to run the CML loop (NumberOfIterations).
+    /// By default, this is the number of cadences that fit in the good data of
+    /// the observation, or 1 if no metafits is given.
+    #[structopt(long)]
+    num_iterations: Option<u32>,
+
+    /// Write the visibilities processed by the RTS to uvfits files
+    /// (writeVisToUVFITS).
+    #[structopt(long, overrides_with = "no-write-vis-to-uvfits")]
+    write_vis_to_uvfits: bool,
+
+    /// Turn off --write-vis-to-uvfits, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "no-write-vis-to-uvfits",
+        long,
+        overrides_with = "write-vis-to-uvfits"
+    )]
+    _no_write_vis_to_uvfits: bool,
+}
+
+/// Arguments specific to the "peel" mode.
+#[derive(StructOpt, Debug)]
+struct PeelArgs {
+    /// The number of source calibrators to use (NumberOfIonoCalibrators).
+    #[structopt(short, long, default_value = "1000")]
+    num_cals: u32,
+
+    /// The number of sources to peel (NumberOfSourcesToPeel). If not
+    /// specified, defaults to num_cals.
+    #[structopt(long)]
+    num_peel: Option<u32>,
+
+    /// The number of "primary calibrators" to use (NumberOfCalibrators). If
+    /// this is bigger than num-cals, then it will be truncated to match
+    /// num-cals.
+    #[structopt(long, default_value = "5")]
+    num_primary_cals: u32,
+
+    /// Specify the number of times to run the CML loop (NumberOfIterations).
+    /// By default, this is the number of cadences that fit in the good data of
+    /// the observation, or 14 if no metafits is given.
+    #[structopt(long)]
+    num_iterations: Option<u32>,
+
+    /// Don't write the visibilities processed by the RTS to uvfits files
+    /// (writeVisToUVFITS).
+    #[structopt(long, overrides_with = "write-vis-to-uvfits")]
+    dont_write_vis_to_uvfits: bool,
+
+    /// Turn off --dont-write-vis-to-uvfits, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "write-vis-to-uvfits",
+        long,
+        overrides_with = "dont-write-vis-to-uvfits"
+    )]
+    _write_vis_to_uvfits: bool,
+}
+
+/// Arguments for modes that write both patch and peel .in files.
+#[derive(StructOpt, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct BothArgs {
+    /// The source list to use for patching. Default: --srclist
+    #[structopt(long, parse(from_str))]
+    patch_srclist: Option<PathBuf>,
+
+    /// The source list to use for peeling. Default: --srclist
+    #[structopt(long, parse(from_str))]
+    peel_srclist: Option<PathBuf>,
+
+    /// The number of "primary calibrators" to use when patching
+    /// (NumberOfCalibrators).
+    #[structopt(long, default_value = "1")]
+    patch_num_primary_cals: u32,
+
+    /// The number of times to run the CML loop when patching
+    /// (NumberOfIterations). By default, this is derived from the observation.
+    #[structopt(long)]
+    patch_num_iterations: Option<u32>,
+
+    /// Write the visibilities processed by the RTS when patching to uvfits
+    /// files (writeVisToUVFITS).
+    #[structopt(long, overrides_with = "no-patch-write-vis-to-uvfits")]
+    patch_write_vis_to_uvfits: bool,
+
+    /// Turn off --patch-write-vis-to-uvfits, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "no-patch-write-vis-to-uvfits",
+        long,
+        overrides_with = "patch-write-vis-to-uvfits"
+    )]
+    #[serde(skip)]
+    _no_patch_write_vis_to_uvfits: bool,
+
+    /// The number of source calibrators to use when peeling
+    /// (NumberOfIonoCalibrators).
+    #[structopt(short, long, default_value = "1000")]
+    num_cals: u32,
+
+    /// The number of sources to peel (NumberOfSourcesToPeel). If not
+    /// specified, defaults to num_cals.
+    #[structopt(long)]
+    num_peel: Option<u32>,
+
+    /// The number of "primary calibrators" to use when peeling
+    /// (NumberOfCalibrators). If this is bigger than num-cals, then it will be
+    /// truncated to match num-cals.
+    #[structopt(long, default_value = "5")]
+    peel_num_primary_cals: u32,
+
+    /// The number of times to run the CML loop when peeling
+    /// (NumberOfIterations). By default, this is derived from the observation.
+    #[structopt(long)]
+    peel_num_iterations: Option<u32>,
+
+    /// Don't write the visibilities processed by the RTS when peeling to uvfits
+    /// files (writeVisToUVFITS).
+    #[structopt(long, overrides_with = "peel-write-vis-to-uvfits")]
+    peel_dont_write_vis_to_uvfits: bool,
+
+    /// Turn off --peel-dont-write-vis-to-uvfits, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "peel-write-vis-to-uvfits",
+        long,
+        overrides_with = "peel-dont-write-vis-to-uvfits"
+    )]
+    #[serde(skip)]
+    _peel_write_vis_to_uvfits: bool,
+}
+
+impl BothArgs {
+    /// Get the patch and peel source lists, which both default to --srclist.
+    /// Each file is only read once, even if both modes use it.
+    fn srclists<'a>(
+        &'a self,
+        common: &'a Common,
+    ) -> Result<(Option<Srclist<'a>>, Option<Srclist<'a>>), anyhow::Error> {
+        let srclist = common.srclist.as_deref();
+        let patch_srclist = self.patch_srclist.as_deref().or(srclist);
+        let peel_srclist = self.peel_srclist.as_deref().or(srclist);
+        for srclist in [patch_srclist, peel_srclist].iter().copied().flatten() {
+            ensure!(
+                srclist.exists(),
+                "Specified source list file ({:?}) does not exist!",
+                srclist
+            );
+        }
+        let patch_srclist = patch_srclist.map(Srclist::read).transpose()?;
+        let peel_srclist = match (&patch_srclist, peel_srclist) {
+            (Some(patch), Some(peel)) if patch.path == peel => Some(*patch),
+            (_, peel) => peel.map(Srclist::read).transpose()?,
+        };
+        Ok((patch_srclist, peel_srclist))
+    }
+
+    fn patch_args(&self) -> PatchArgs {
+        PatchArgs {
+            num_primary_cals: self.patch_num_primary_cals,
+            num_iterations: self.patch_num_iterations,
+            write_vis_to_uvfits: self.patch_write_vis_to_uvfits,
+            _no_write_vis_to_uvfits: false,
+        }
+    }
+
+    fn peel_args(&self) -> PeelArgs {
+        PeelArgs {
+            num_cals: self.num_cals,
+            num_peel: self.num_peel,
+            num_primary_cals: self.peel_num_primary_cals,
+            num_iterations: self.peel_num_iterations,
+            dont_write_vis_to_uvfits: self.peel_dont_write_vis_to_uvfits,
+            _write_vis_to_uvfits: false,
+        }
+    }
+}
+
+/// Arguments specific to the "batch" mode.
+#[derive(StructOpt, Debug)]
+struct BatchArgs {
+    /// The metafits files of the observations. Directories are searched for
+    /// metafits files, and file names may be glob patterns (e.g.
+    /// "data/*.metafits").
+    #[structopt(name = "METAFITS", parse(from_str), required = true)]
+    metafits_files: Vec<PathBuf>,
+
+    /// Where to write each patch .in file. "{obsid}" is replaced with the
+    /// observation's ID.
+    #[structopt(long, default_value = "{obsid}/rts_patch.in")]
+    patch_template: String,
+
+    /// Where to write each peel .in file. "{obsid}" is replaced with the
+    /// observation's ID.
+    #[structopt(long, default_value = "{obsid}/rts_peel.in")]
+    peel_template: String,
+}
+
+/// Arguments specific to the "job" mode.
+#[derive(StructOpt, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct JobArgs {
+    /// The directory to write the job's files to. It is created if it doesn't
+    /// exist.
+    #[structopt(short = "d", long, parse(from_str), default_value = ".")]
+    job_dir: PathBuf,
+
+    /// A file containing the scheduler directives to put at the top of the
+    /// setup and run scripts (including the "#!" line), and the command that
+    /// runs the RTS. The lines containing "{in_file}" are the command, which
+    /// the run script uses for each .in file. "{obsid}", "{job_name}" and
+    /// "{num_nodes}" are replaced with their values for each script (or .in
+    /// file). By default, SLURM directives and srun are used.
+    #[structopt(long, parse(from_str))]
+    scheduler_template: Option<PathBuf>,
+}
+
+/// The scheduler template used when a --scheduler-template isn't given.
+const DEFAULT_SCHEDULER_TEMPLATE: &str = r#"#!/bin/bash -l
+#SBATCH --job-name={job_name}
+#SBATCH --output={job_name}-%A.out
+#SBATCH --nodes={num_nodes}
+#SBATCH --ntasks-per-node=1
+srun -n {num_nodes} rts_gpu {in_file}
+"#;
+
+/// The mode-specific arguments used to generate a .in file.
+#[derive(Clone, Copy, Debug)]
+enum ModeArgs<'a> {
+    Patch(&'a PatchArgs),
+    Peel(&'a PeelArgs),
+}
+
+impl<'a> ModeArgs<'a> {
+    fn mode(&self) -> RtsMode {
+        match self {
+            Self::Patch(_) => RtsMode::Patch,
+            Self::Peel(p) => RtsMode::Peel {
+                num_cals: p.num_cals,
+                // If num_peel was specified, use it. Otherwise, just use the
+                // specified `num_cals`.
+                num_peel: p.num_peel.unwrap_or(p.num_cals),
+            },
+        }
+    }
+
+    fn num_primary_cals(&self) -> u32 {
+        match self {
+            Self::Patch(p) => p.num_primary_cals,
+            Self::Peel(p) => p.num_primary_cals.min(p.num_cals),
+        }
+    }
+
+    fn num_iterations(&self) -> Option<u32> {
+        match self {
+            Self::Patch(p) => p.num_iterations,
+            Self::Peel(p) => p.num_iterations,
+        }
+    }
+
+    /// The number of iterations to use when it can't be determined from the
+    /// observation.
+    fn default_num_iterations(&self) -> u32 {
+        match self {
+            Self::Patch(_) => 1,
+            Self::Peel(_) => 14,
+        }
+    }
+
+    fn write_vis_to_uvfits(&self) -> bool {
+        match self {
+            Self::Patch(p) => p.write_vis_to_uvfits,
+            Self::Peel(p) => !p.dont_write_vis_to_uvfits,
+        }
+    }
 }
 
 /// Arguments that can be used in either the "patch" or "peel" modes of RTS
 /// jobs.
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Clone, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
 struct Common {
+    /// A TOML file containing any of these arguments, keyed by their long names
+    /// (e.g. base-dir = "..", use-fee-beam = true). Arguments for a single
+    /// subcommand can be put in its own table (e.g. [peel]). Arguments given on
+    /// the command line take precedence; flags set in the file can be turned
+    /// off with their negations (e.g. --no-use-fee-beam, --validate). Relative
+    /// paths in the file are relative to the file itself. The user's config
+    /// file (~/.config/mongoose/config.toml) is read the same way, after this
+    /// one.
+    #[structopt(long, parse(from_str))]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
     // File related.
     /// The directory containing input data, including gpubox files and cotter
-    /// mwaf files (formatted RTS_<obsid>_xy.mwaf).
+    /// mwaf files (formatted RTS_<obsid>_xy.mwaf). Required.
     #[structopt(short, long, parse(from_str))]
-    base_dir: PathBuf,
+    base_dir: Option<PathBuf>,
 
     /// The base of the input filenames (BaseFilename). By default, this matches
-    /// gpubox files.
-    #[structopt(long, default_value = "*_gpubox")]
-    base_filename: String,
+    /// gpubox files ("*_gpubox").
+    #[structopt(long)]
+    base_filename: Option<String>,
 
     /// The path to the obsid's metafits file. If this isn't supplied, then many
     /// other variables must be supplied.
     #[structopt(short, long, parse(from_str))]
     metafits: Option<PathBuf>,
 
-    /// The path to the source-list sky-model file.
+    /// Also read the gpubox files in the base directory (those matching the
+    /// base filename) for accurate time information. Only useful for
+    /// determining StartProcessingAt; by default, only the metafits is used.
+    #[structopt(long, overrides_with = "no-read-gpubox-times")]
+    read_gpubox_times: bool,
+
+    /// Turn off --read-gpubox-times, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "no-read-gpubox-times",
+        long,
+        overrides_with = "read-gpubox-times"
+    )]
+    #[serde(skip)]
+    _no_read_gpubox_times: bool,
+
+    /// The path to the source-list sky-model file. Required.
     #[structopt(short, long, parse(from_str))]
-    srclist: PathBuf,
+    srclist: Option<PathBuf>,
 
     /// Don't use cotter flags in mwaf files (ImportCotterFlags). Default is to
     /// use flags.
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "cotter-flags")]
     no_cotter_flags: bool,
 
+    /// Turn off --no-cotter-flags, e.g. if it's set in a --config file.
+    #[structopt(name = "cotter-flags", long, overrides_with = "no-cotter-flags")]
+    #[serde(skip)]
+    _cotter_flags: bool,
+
     /// Run the RTS's CheckForRFI routine (doRFIflagging).
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "no-rts-rfi-flagging")]
     rts_rfi_flagging: bool,
 
+    /// Turn off --rts-rfi-flagging, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "no-rts-rfi-flagging",
+        long,
+        overrides_with = "rts-rfi-flagging"
+    )]
+    #[serde(skip)]
+    _no_rts_rfi_flagging: bool,
+
     /// Add a node number (01 to 24) to the base filename
     /// (AddNodeNumberToFilename). Used primarily (?) with input uvfits files.
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "no-add-node-number")]
     add_node_number: bool,
 
+    /// Turn off --add-node-number, e.g. if it's set in a --config file.
+    #[structopt(name = "no-add-node-number", long, overrides_with = "add-node-number")]
+    #[serde(skip)]
+    _no_add_node_number: bool,
+
     /// Don't correct visibilities for cable delays and PFB gains
     /// (doMWArxCorrections)
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "rx-correct")]
     dont_rx_correct: bool,
 
+    /// Turn off --dont-rx-correct, e.g. if it's set in a --config file.
+    #[structopt(name = "rx-correct", long, overrides_with = "dont-rx-correct")]
+    #[serde(skip)]
+    _rx_correct: bool,
+
     /// Don't apply cable corrections and digital gains based on metafits
     /// (doRawDataCorrections)
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "correct-raw-data")]
     dont_correct_raw_data: bool,
 
+    /// Turn off --dont-correct-raw-data, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "correct-raw-data",
+        long,
+        overrides_with = "dont-correct-raw-data"
+    )]
+    #[serde(skip)]
+    _correct_raw_data: bool,
+
     /// Don't read visibilities directly from gpubox files written by correlator
     /// (ReadGpuboxDirect)
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "read-gpubox-direct")]
     dont_read_gpubox_direct: bool,
 
+    /// Turn off --dont-read-gpubox-direct, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "read-gpubox-direct",
+        long,
+        overrides_with = "dont-read-gpubox-direct"
+    )]
+    #[serde(skip)]
+    _read_gpubox_direct: bool,
+
     /// When reading from uvfits, don't use a single file per coarse band
     /// (ReadAllFromSingleFile)
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "read-all-from-single-file")]
     dont_read_all_from_single_file: bool,
 
+    /// Turn off --dont-read-all-from-single-file, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "read-all-from-single-file",
+        long,
+        overrides_with = "dont-read-all-from-single-file"
+    )]
+    #[serde(skip)]
+    _read_all_from_single_file: bool,
+
     /// Use the 2016 FEE beam (TileBeamType=1).
-    #[structopt(short = "f", long)]
+    #[structopt(short = "f", long, overrides_with = "no-use-fee-beam")]
     use_fee_beam: bool,
 
-    /// The path to the FEE beam HDF5 file. If it's not specified, but
-    /// --use-fee-beam is, inspect the MWA_BEAM_FILE environment variable.
+    /// Turn off --use-fee-beam, e.g. if it's set in a --config file.
+    #[structopt(name = "no-use-fee-beam", long, overrides_with = "use-fee-beam")]
+    #[serde(skip)]
+    _no_use_fee_beam: bool,
+
+    /// The path to the FEE beam HDF5 file. If it's not specified (here or in a
+    /// config file), but --use-fee-beam is, use the MWA_BEAM_FILE environment
+    /// variable, then look in standard locations (e.g. /usr/local/share/mwa).
+    /// Even with --no-validate, the file must look like an MWA FEE beam file
+    /// (an HDF5 file that mentions the FEE beam's datasets).
     #[structopt(long)]
     fee_beam_file: Option<PathBuf>,
 
+    /// Write the indices of the tiles with dead dipoles (delays of 32 in the
+    /// metafits) to this file, in the format of the RTS's flagged_tiles.txt.
+    /// "{obsid}" is replaced with the observation ID. Requires --metafits.
+    /// Tiles with dead dipoles are always reported.
+    #[structopt(long)]
+    dead_dipole_tile_flags: Option<PathBuf>,
+
+    /// Warn if more than this fraction of the unflagged tiles have dead
+    /// dipoles. Default: 0.2
+    #[structopt(long)]
+    max_dead_dipole_fraction: Option<f64>,
+
     // Observation related.
     /// The observation ID.
     #[structopt(long)]
@@ -162,14 +600,21 @@ struct Common {
     #[structopt(long)]
     corr_dump_time: Option<f64>,
 
+    /// The length of each calibration interval in seconds. This must be a
+    /// multiple of the integration time. Default: 64 for patching, 8 for
+    /// peeling.
+    #[structopt(long)]
+    cadence: Option<f64>,
+
     /// Number of correlator dumps to be included in each calibration interval
-    /// (CorrDumpsPerCadence).
+    /// (CorrDumpsPerCadence). By default, this is the cadence divided by the
+    /// integration time.
     #[structopt(long)]
     corr_dumps_per_cadence: Option<u32>,
 
     /// The number of integration bins to use in baseline averaging
     /// (NumberOfIntegrationBins). By default, this is determined by whether
-    /// we're patching or peeling and the integration time of the observation.
+    /// we're patching or peeling and CorrDumpsPerCadence.
     ///
     /// The bins are set according to powers of two, so if
     /// CorrDumpsPerCadence=32, and NumberOfIntegrationBins=5, then the bins
@@ -178,12 +623,16 @@ struct Common {
     num_integration_bins: Option<u32>,
 
     /// Frequency at middle of lowest fine channel in observation, in MHz
-    /// (ObservationFrequencyBase). This is calculated by: obs_centre_freq -
-    /// obs_bandwidth / 2 - fine_chan_width / 2
+    /// (ObservationFrequencyBase). By default, this is derived from the start
+    /// frequency of the observation's lowest coarse channel in the metafits.
+    /// For picket-fence observations, the base frequency of each contiguous
+    /// group of channels is offset from this.
     #[structopt(long)]
     base_freq: Option<f64>,
 
     /// Specify the number of fine channels per coarse band (NumberOfChannels).
+    /// By default, this is the coarse channel width divided by the fine channel
+    /// width.
     #[structopt(long)]
     num_fine_chans: Option<u32>,
 
@@ -220,331 +669,1336 @@ struct Common {
     dec_pointing_centre: Option<f64>,
 
     /// The number of channels to average during calibration (FscrunchChan).
-    #[structopt(long, default_value = "2")]
-    fscrunch: u8,
+    /// Default: 2
+    #[structopt(long)]
+    fscrunch: Option<u8>,
 
     /// By default, sourcelist vetoing removes sources from the sourcelist which
     /// are predicted to fall very close to the null of one of the coarse bands.
     /// Enabling this option turns vetoing off. (DisableSourcelistVetos)
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "no-disable-srclist-vetos")]
     disable_srclist_vetos: bool,
 
+    /// Turn off --disable-srclist-vetos, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "no-disable-srclist-vetos",
+        long,
+        overrides_with = "disable-srclist-vetos"
+    )]
+    #[serde(skip)]
+    _no_disable_srclist_vetos: bool,
+
+    /// The correlator dump (timestep) to start processing from
+    /// (StartProcessingAt). By default, the data before the metafits' GOODTIME
+    /// (i.e. the QUACKTIM) is skipped. Without a metafits, this is 0.
+    #[structopt(long)]
+    start_processing_at: Option<u32>,
+
+    /// Read visibilities from packets sent by the correlator (UsePacketInput).
+    #[structopt(long, overrides_with = "no-use-packet-input")]
+    use_packet_input: bool,
+
+    /// Turn off --use-packet-input, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "no-use-packet-input",
+        long,
+        overrides_with = "use-packet-input"
+    )]
+    #[serde(skip)]
+    _no_use_packet_input: bool,
+
+    /// Read visibilities with a separate thread (UseThreadedVI).
+    #[structopt(long, overrides_with = "no-use-threaded-vi")]
+    use_threaded_vi: bool,
+
+    /// Turn off --use-threaded-vi, e.g. if it's set in a --config file.
+    #[structopt(name = "no-use-threaded-vi", long, overrides_with = "use-threaded-vi")]
+    #[serde(skip)]
+    _no_use_threaded_vi: bool,
+
+    // Magic.
+    /// The maximum frequency in MHz used to set the size of uv cells for
+    /// gridding and the maximum decorrelation when binning baselines
+    /// (MaxFrequency). Default: 200
+    #[structopt(long)]
+    max_frequency: Option<f64>,
+
+    /// The number of tiles in the array (ArrayNumberOfStations). The default is
+    /// determined by the metafits file; without one, it is 128.
+    #[structopt(long)]
+    array_num_stations: Option<u32>,
+
+    /// The latitude of the array in degrees (ArrayPositionLat). Default: the
+    /// MWA's latitude (-26.70331940). Specify this when using a non-MWA array
+    /// without a metafits file.
+    #[structopt(long)]
+    array_position_lat: Option<f64>,
+
+    /// The longitude of the array in degrees (ArrayPositionLong). Default: the
+    /// MWA's longitude (116.67081524). Specify this when using a non-MWA array
+    /// without a metafits file.
+    #[structopt(long)]
+    array_position_long: Option<f64>,
+
+    /// The minimum baseline length used in calibration, in wavelengths
+    /// (calBaselineMin). Default: 20
+    #[structopt(long)]
+    cal_baseline_min: Option<f64>,
+
+    /// The width of the taper applied to baselines shorter than
+    /// calBaselineMin, in wavelengths (calShortBaselineTaper). Default: 40
+    #[structopt(long)]
+    cal_short_baseline_taper: Option<f64>,
+
+    /// The oversampling of imaging pixels (ImageOversampling). Default: 3
+    #[structopt(long)]
+    image_oversampling: Option<f64>,
+
+    /// Store pixel beam weights along with intensity (StorePixelMatrices).
+    /// Images will be 4X greater data volume.
+    #[structopt(long, overrides_with = "no-store-pixel-matrices")]
+    store_pixel_matrices: bool,
+
+    /// Turn off --store-pixel-matrices, e.g. if it's set in a --config file.
+    #[structopt(
+        name = "no-store-pixel-matrices",
+        long,
+        overrides_with = "store-pixel-matrices"
+    )]
+    #[serde(skip)]
+    _no_store_pixel_matrices: bool,
+
+    /// Don't check that the files the RTS will open (gpubox files, mwaf files,
+    /// the FEE beam file, etc.) exist before writing .in files.
+    #[structopt(long, overrides_with = "validate")]
+    no_validate: bool,
+
+    /// Turn off --no-validate, e.g. if it's set in a --config file.
+    #[structopt(name = "validate", long, overrides_with = "no-validate")]
+    #[serde(skip)]
+    _validate: bool,
+
     /// Save the output of this program to a specified location. If not
     /// specified, the .in file contents are printed to stdout.
     #[structopt(short, long)]
     output_file: Option<PathBuf>,
 }
 
-impl Opts {
-    fn rts_params(self) -> Result<RtsParams, anyhow::Error> {
-        let common = match &self {
-            Self::Patch { common, .. } => common,
-            Self::Peel { common, .. } => common,
-        };
+/// The subcommands whose arguments can be set in their own table of a config
+/// file, e.g. "[peel]".
+const CONFIG_SUBCOMMANDS: &[&str] = &["patch", "peel", "both", "batch", "job"];
+
+/// The arguments that take paths. Relative paths in a config file are relative
+/// to the file.
+const PATH_ARGS: &[&str] = &[
+    "base-dir",
+    "metafits",
+    "srclist",
+    "fee-beam-file",
+    "dead-dipole-tile-flags",
+    "output-file",
+    "patch-srclist",
+    "peel-srclist",
+    "patch-output",
+    "peel-output",
+    "job-dir",
+    "scheduler-template",
+];
+
+/// The flag that turns off a flag, e.g. "no-use-fee-beam" for "use-fee-beam",
+/// "validate" for "no-validate" and "peel-write-vis-to-uvfits" for
+/// "peel-dont-write-vis-to-uvfits".
+fn negated_flag(flag: &str) -> String {
+    let (mode, flag) = match flag.find('-') {
+        Some(i) if ["patch", "peel"].contains(&&flag[..i]) => flag.split_at(i + 1),
+        _ => ("", flag),
+    };
+    for prefix in &["no-", "dont-"] {
+        if let Some(f) = flag.strip_prefix(*prefix) {
+            return format!("{}{}", mode, f);
+        }
+    }
+    format!("no-{}{}", mode, flag)
+}
 
-        let fee_beam_file: Option<PathBuf> = if common.use_fee_beam {
-            match &common.fee_beam_file {
-                Some(f) => Some(f.clone()),
-                // Try to get the file from MWA_BEAM_FILE
-                None => match std::env::var("MWA_BEAM_FILE") {
-                    Ok(f) => Some(PathBuf::from(f)),
-                    Err(_) => bail!("--use-fee-beam was specified, but no --fee-beam-file was supplied, and couldn't access the MWA_BEAM_FILE variable."),
-                },
+/// Turn a setting (e.g. from a config file) into command-line arguments.
+/// Relative paths are relative to `dir`. `None` is returned if the value can't
+/// be an argument.
+fn setting_args(key: &str, value: &toml::Value, dir: &Path) -> Option<Vec<OsString>> {
+    let values = match value {
+        // Flags are off unless they're given.
+        toml::Value::Boolean(true) => return Some(vec![OsString::from(format!("--{}", key))]),
+        toml::Value::Boolean(false) => return Some(vec![]),
+        toml::Value::Array(a) => a.iter().collect(),
+        v => vec![v],
+    };
+    values
+        .into_iter()
+        .map(|v| {
+            let v = match v {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                _ => return None,
+            };
+            let mut arg = OsString::from(format!("--{}=", key));
+            if PATH_ARGS.contains(&key) {
+                arg.push(dir.join(v));
+            } else {
+                arg.push(v);
             }
-        } else {
-            // We were told not to use the FEE beam, so there's no FEE beam
-            // file.
-            None
-        };
+            Some(arg)
+        })
+        .collect()
+}
 
-        // mwalib gets accurate time information from the gpubox files in
-        // addition to the metafits file (like what the true start time should
-        // be, given that not all gpubox files start at the same time). But,
-        // we're not using any time information here, so there's no need to
-        // handle gpubox files.
-        let context = if let Some(m) = &common.metafits {
-            Some(mwalibContext::new(&m, &[])?)
-        } else {
-            None
-        };
+/// Parse the command-line arguments. If a --config file was given, its settings
+/// are turned into arguments for anything that wasn't given on the command line,
+/// so the file's keys and values are checked like any other arguments.
+fn parse_args() -> Result<Opts, anyhow::Error> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let mut opts = Opts::from_clap(&Opts::clap().get_matches_from(&args));
+    // Settings on the command line take precedence over those in the --config
+    // file, which take precedence over those in the user's config file. The
+    // user's config file applies to every subcommand, so its top-level
+    // settings that the subcommand doesn't have are skipped, rather than being
+    // errors.
+    let mut config_files = vec![];
+    if let Some(common) = opts.common() {
+        config_files.extend(common.config.iter().map(|f| (f.clone(), false)));
+        config_files.extend(user_config_file().filter(|f| f.exists()).map(|f| (f, true)));
+    }
 
-        let obsid = match common.obsid {
-            Some(o) => o,
-            None => match &context.as_ref().map(|c| c.obsid) {
-                Some(o) => *o,
-                None => {
-                    bail!("Neither --obsid nor --metafits were specified; cannot get the obsid.")
-                }
-            },
+    for (config_file, is_user_config) in config_files {
+        let matches = Opts::clap().get_matches_from_safe(&args)?;
+        let (subcommand, sub_matches) = match matches.subcommand() {
+            (s, Some(m)) => (s, m),
+            _ => break,
         };
-
-        let mode = match &self {
-            Self::Patch { .. } => RtsMode::Patch,
-            Self::Peel {
-                num_cals, num_peel, ..
-            } => RtsMode::Peel {
-                num_cals: *num_cals,
-                num_peel: if let Some(p) = num_peel {
-                    // If num_peel was specified, use it.
-                    *p
-                } else {
-                    // Otherwise, just use the specified `num_cals`.
-                    *num_cals
-                },
-            },
+        args.extend(config_args(
+            &config_file,
+            subcommand,
+            |arg| sub_matches.occurrences_of(arg) > 0,
+            |arg| !is_user_config || subcommand_has_arg(subcommand, arg),
+        )?);
+        opts = match Opts::from_iter_safe(&args) {
+            Ok(o) => o,
+            Err(e) => bail!(
+                "Couldn't use the settings in {}: {}",
+                config_file.display(),
+                e.message
+            ),
         };
+    }
+    Ok(opts)
+}
 
-        // Set up the timing stuff. Fill things automatically first, if
-        // possible, then overwrite settings with anything user-specified.
-        let mut timing: Timing = match &context.as_ref().map(|c| c.integration_time_milliseconds) {
-            Some(500) => Timing {
-                corr_dump_time: 0.5,
-                corr_dumps_per_cadence_patch: 128,
-                num_integration_bins_patch: 7,
-                corr_dumps_per_cadence_peel: 16,
-                num_integration_bins_peel: 5,
-            },
-            Some(1000) => Timing {
-                corr_dump_time: 1.0,
-                corr_dumps_per_cadence_patch: 64,
-                num_integration_bins_patch: 7,
-                corr_dumps_per_cadence_peel: 8,
-                num_integration_bins_peel: 3,
-            },
-            Some(2000) => Timing {
-                corr_dump_time: 2.0,
-                corr_dumps_per_cadence_patch: 32,
-                num_integration_bins_patch: 6,
-                corr_dumps_per_cadence_peel: 4,
-                num_integration_bins_peel: 3,
-            },
-            _ => Timing::default(),
+/// The path to the user's mongoose config file,
+/// `$XDG_CONFIG_HOME/mongoose/config.toml` (or
+/// `$HOME/.config/mongoose/config.toml`). The file might not exist.
+fn user_config_file() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("mongoose").join("config.toml"))
+}
+
+/// Does `subcommand` have the argument `--<arg>`? clap reports unknown
+/// arguments before anything else (like missing required arguments), so this
+/// is the case unless parsing the argument alone fails with that error.
+fn subcommand_has_arg(subcommand: &str, arg: &str) -> bool {
+    let args = [
+        "rts-in-file-generator".to_string(),
+        subcommand.to_string(),
+        format!("--{}", arg),
+    ];
+    match Opts::clap().get_matches_from_safe(&args) {
+        Ok(_) => true,
+        Err(e) => e.kind != ErrorKind::UnknownArgument,
+    }
+}
+
+/// Turn the settings in a config file into command-line arguments for
+/// `subcommand`. Top-level settings apply to every subcommand, and are
+/// overridden by those in the subcommand's table. Settings for arguments that
+/// `given` says were already given (or whose negations were given) are skipped,
+/// as are top-level settings that `applies` says don't apply to `subcommand`.
+fn config_args<F: Fn(&str) -> bool, G: Fn(&str) -> bool>(
+    config_file: &Path,
+    subcommand: &str,
+    given: F,
+    applies: G,
+) -> Result<Vec<OsString>, anyhow::Error> {
+    ensure!(
+        config_file.exists(),
+        "Specified config file ({}) does not exist!",
+        config_file.display()
+    );
+    let mut config: toml::value::Table =
+        match toml::from_str(&std::fs::read_to_string(config_file)?) {
+            Ok(c) => c,
+            Err(e) => bail!(
+                "Couldn't parse config file {}: {}",
+                config_file.display(),
+                e
+            ),
         };
-        if let Some(c) = common.corr_dump_time {
-            timing.corr_dump_time = c;
+    let config_dir = config_file.parent().unwrap_or_else(|| Path::new("."));
+
+    // Separate the subcommand tables from the top-level settings.
+    let mut settings = toml::value::Table::new();
+    let mut subcommand_settings = toml::value::Table::new();
+    for (key, value) in config.iter_mut() {
+        match value {
+            toml::Value::Table(t) => {
+                ensure!(
+                    CONFIG_SUBCOMMANDS.contains(&key.as_str()),
+                    "Config file {} has a [{}] table, but that isn't a subcommand; expected one of {}",
+                    config_file.display(),
+                    key,
+                    CONFIG_SUBCOMMANDS.join(", ")
+                );
+                if key == subcommand {
+                    subcommand_settings = std::mem::take(t);
+                }
+            }
+            v => {
+                if applies(key) {
+                    settings.insert(key.clone(), v.clone());
+                }
+            }
         }
-        if let Some(c) = common.corr_dumps_per_cadence {
-            timing.corr_dumps_per_cadence_patch = c;
-            timing.corr_dumps_per_cadence_peel = c;
+    }
+    settings.extend(subcommand_settings);
+
+    let mut args = vec![];
+    for (key, value) in &settings {
+        if given(key) || given(&negated_flag(key)) {
+            continue;
         }
-        if let Some(c) = common.num_integration_bins {
-            timing.num_integration_bins_patch = c;
-            timing.num_integration_bins_peel = c;
+        match setting_args(key, value, config_dir) {
+            Some(a) => args.extend(a),
+            None => bail!(
+                "Config file {}: '{}' has an unsupported value ({})",
+                config_file.display(),
+                key,
+                value
+            ),
         }
+    }
+    Ok(args)
+}
 
-        // Check that all `timing` fields are non zero.
-        if timing.corr_dump_time == 0.0
-            || timing.corr_dumps_per_cadence_patch == 0
-            || timing.corr_dumps_per_cadence_peel == 0
-            || timing.num_integration_bins_patch == 0
-            || timing.num_integration_bins_peel == 0
-        {
-            bail!("At least one of the timing fields was zero:\n{:?}\n\nIf you didn't specify any, it's possible that mongoose does not currently handle this integration time{}", timing, match context {
-                Some(c) => format!(" ({}s)", c.integration_time_milliseconds as f64 / 1e3),
-                None => "".to_string(),
-            })
-        }
+/// The RTS parameters of an observation that don't depend on whether we're
+/// patching or peeling. These are derived once, then specialised for each
+/// mode, so that patch and peel .in files are always consistent.
+struct SharedParams {
+    /// One set of parameters per contiguous group of coarse channels. The
+    /// mode-specific fields are placeholders.
+    params: Vec<RtsParams>,
 
-        let (corr_dump_time, corr_dumps_per_cadence, num_integration_bins) = match mode {
-            RtsMode::Patch => (
-                timing.corr_dump_time,
-                timing.corr_dumps_per_cadence_patch,
-                timing.num_integration_bins_patch,
-            ),
-            RtsMode::Peel { .. } => (
-                timing.corr_dump_time,
-                timing.corr_dumps_per_cadence_peel,
-                timing.num_integration_bins_peel,
-            ),
+    /// The integration time of the data [milliseconds].
+    integration_time_ms: u64,
+
+    /// The amount of data after StartProcessingAt [milliseconds], if known.
+    data_ms: Option<u64>,
+
+    /// The observation's metafits, if we have one.
+    context: Option<mwalibContext>,
+}
+
+/// Get the RTS parameters for an observation in a particular mode. There is
+/// one set of parameters per contiguous group of coarse channels.
+fn rts_params(common: &Common, mode_args: ModeArgs) -> Result<Vec<RtsParams>, anyhow::Error> {
+    let srclist = common.srclist.as_deref().map(Srclist::read).transpose()?;
+    shared_params(common)?.for_mode(common, mode_args, srclist)
+}
+
+/// Get the RTS parameters for an observation that don't depend on the mode.
+fn shared_params(common: &Common) -> Result<SharedParams, anyhow::Error> {
+    let fee_beam_file: Option<PathBuf> = if common.use_fee_beam {
+        let search_files = standard_fee_beam_files();
+        let f = match &common.fee_beam_file {
+            Some(f) => f.clone(),
+            // Try MWA_BEAM_FILE and the standard locations.
+            None => match find_fee_beam_file(&search_files) {
+                Some(f) => f,
+                None => bail!(
+                    "--use-fee-beam was specified, but no --fee-beam-file was supplied (on the command line or in a config file), MWA_BEAM_FILE isn't set, and the FEE beam file isn't in any of:\n    {}",
+                    search_files
+                        .iter()
+                        .map(|f| f.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join("\n    ")
+                ),
+            },
         };
+        // The RTS can't use the FEE beam without its file, so check the file
+        // even if we were told not to validate.
+        if let Err(e) = check_fee_beam_file(&f) {
+            bail!("Can't use the FEE beam file {}: {}", f.display(), e);
+        }
+        Some(f)
+    } else {
+        // We were told not to use the FEE beam, so there's no FEE beam
+        // file.
+        None
+    };
 
-        let num_fine_channels = if let Some(n) = common.num_fine_chans {
-            n
-        } else {
-            ensure!(context.is_some(), "Neither --num-fine-chans nor --metafits were specified; cannot get the number of fine channels.");
-            let c = context.as_ref().unwrap();
-            match c.fine_channel_width_hz {
-                40000 => 32,  // 40 kHz
-                20000 => 64,  // 20 kHz
-                10000 => 128, // 10 kHz
-                v => {
-                    bail!(
-                        "Unhandled number of channels for fine-channel bandwidth {}kHz!",
-                        v as f64 / 1e3
-                    );
-                }
+    let base_dir = match &common.base_dir {
+        Some(d) => d.clone(),
+        None => {
+            bail!("No --base-dir was specified, either on the command line or in a config file.")
+        }
+    };
+    let base_filename = common
+        .base_filename
+        .clone()
+        .unwrap_or_else(|| "*_gpubox".to_string());
+
+    // mwalib gets accurate time information from the gpubox files in
+    // addition to the metafits file (like what the true start time should
+    // be, given that not all gpubox files start at the same time). The
+    // metafits alone is usually good enough, so only read the gpubox files if
+    // we were asked to.
+    let context = match &common.metafits {
+        Some(m) => {
+            let gpubox_files = if common.read_gpubox_times {
+                gpubox_files(&base_dir, &base_filename)?
+            } else {
+                vec![]
+            };
+            Some(mwalibContext::new(m, &gpubox_files)?)
+        }
+        None => None,
+    };
+
+    let obsid = match common.obsid {
+        Some(o) => o,
+        None => match &context.as_ref().map(|c| c.obsid) {
+            Some(o) => *o,
+            None => {
+                bail!("Neither --obsid nor --metafits were specified; cannot get the obsid.")
             }
+        },
+    };
+
+    match &context {
+        Some(c) => report_dead_dipole_tiles(common, c)?,
+        None => ensure!(
+            common.dead_dipole_tile_flags.is_none(),
+            "--dead-dipole-tile-flags was specified without --metafits; cannot get the tiles' dipole delays."
+        ),
+    }
+
+    let integration_time_ms = match (common.corr_dump_time, &context) {
+        (Some(t), _) => (t * 1e3).round() as u64,
+        (None, Some(c)) => c.integration_time_milliseconds,
+        (None, None) => bail!(
+            "Neither --corr-dump-time nor --metafits were specified; cannot get the integration time."
+        ),
+    };
+
+    let fine_channel_width_mhz = if let Some(n) = common.fine_chan_width {
+        n
+    } else {
+        ensure!(context.is_some(), "Neither --fine-chan-width nor --metafits were specified; cannot get the fine channel width.");
+        let c = context.as_ref().unwrap();
+        c.fine_channel_width_hz as f64 / 1e6
+    };
+
+    // The number of fine channels is however many fit in a coarse channel.
+    let num_fine_channels = if let Some(n) = common.num_fine_chans {
+        n
+    } else {
+        let coarse_channel_width_hz = match &context {
+            Some(c) => c.coarse_channel_width_hz,
+            None => MWA_COARSE_CHANNEL_WIDTH_HZ,
         };
+        let fine_channel_width_hz = (fine_channel_width_mhz * 1e6).round() as u32;
+        ensure!(
+            fine_channel_width_hz > 0 && coarse_channel_width_hz % fine_channel_width_hz == 0,
+            "The coarse channel width ({}kHz) is not a multiple of the fine channel width ({}kHz); specify --num-fine-chans",
+            coarse_channel_width_hz as f64 / 1e3,
+            fine_channel_width_mhz * 1e3
+        );
+        coarse_channel_width_hz / fine_channel_width_hz
+    };
 
-        // Could merge this with the block above, but that would be more effort
-        // than I'm willing to expend right now.
-        let fine_channel_width_mhz = if let Some(n) = common.fine_chan_width {
-            n
-        } else {
-            ensure!(context.is_some(), "Neither --fine-chan-width nor --metafits were specified; cannot get the fine channel width.");
+    // Use the forced value, if provided.
+    let obs_image_centre_ra = match common.force_ra {
+        Some(r) => r,
+        // Use RAPHASE if it is available.
+        None => {
+            ensure!(
+                context.is_some(),
+                "Neither --force-ra nor --metafits were specified; cannot get the RA pointing."
+            );
             let c = context.as_ref().unwrap();
-            c.fine_channel_width_hz as f64 / 1e6
-        };
+            match c.ra_phase_center_degrees {
+                Some(v) => v,
+                // Otherwise, just use RA.
+                None => c.ra_tile_pointing_degrees,
+            }
+        }
+    } / 15.0;
 
-        // The magical base frequency is equal to:
-        // (centre_freq - coarse_channel_bandwidth/2 - fine_channel_bandwidth/2)
-        let base_freq = if let Some(f) = common.base_freq {
-            f
-        } else {
+    let obs_image_centre_dec = match common.force_dec {
+        Some(r) => r,
+        None => {
             ensure!(
                 context.is_some(),
-                "Neither --base-freq nor --metafits were specified; cannot get the base frequency."
+                "Neither --force-dec nor --metafits were specified; cannot get the Dec pointing."
             );
             let c = context.as_ref().unwrap();
-            (c.metafits_centre_freq_hz
-                - c.observation_bandwidth_hz / 2
-                - c.fine_channel_width_hz / 2) as f64
-                / 1e6
-        };
+            match c.dec_phase_center_degrees {
+                Some(v) => v,
+                None => c.dec_tile_pointing_degrees,
+            }
+        }
+    };
+
+    let (obs_pointing_centre_ha, obs_pointing_centre_dec) = match (
+        &common.metafits,
+        common.ha_pointing_centre,
+        common.dec_pointing_centre,
+    ) {
+        // If we have a metafits, there's no need to populate these fields. The
+        // RTS will get them from the metafits file.
+        (Some(_), _, _) => (None, None),
+        (None, Some(ha), Some(dec)) => (Some(ha), Some(dec)),
+        // We need to bail if the pointing centre wasn't supplied when a
+        // metafits also wasn't supplied.
+        (None, _, _) => bail!(
+            "When not using a metafits file, both --ha-pointing-centre and --dec-pointing-centre must be specified."
+        ),
+    };
 
-        // Use the forced value, if provided.
-        let obs_image_centre_ra = match common.force_ra {
-            Some(r) => r,
-            // Use RAPHASE if it is available.
+    // Use the array layout of the observation, if we have it. Without a
+    // metafits, assume MWA Phase I unless told otherwise.
+    let array_num_stations = match (common.array_num_stations, &context) {
+        (Some(n), _) => n,
+        (None, Some(c)) => c.num_antennas as u32,
+        (None, None) => DEFAULT_ARRAY_NUM_STATIONS,
+    };
+    let (array_position_lat, array_position_long) = match &context {
+        Some(_) => (
+            MWA_LATITUDE_RADIANS.to_degrees(),
+            MWA_LONGITUDE_RADIANS.to_degrees(),
+        ),
+        None => (DEFAULT_ARRAY_POSITION_LAT, DEFAULT_ARRAY_POSITION_LONG),
+    };
+    let array_position_lat = common.array_position_lat.unwrap_or(array_position_lat);
+    let array_position_long = common.array_position_long.unwrap_or(array_position_long);
+
+    // Skip the data before GOODTIME; the RTS counts correlator dumps from the
+    // start of the data.
+    let start_processing_at = match (common.start_processing_at, &context) {
+        (Some(s), _) => s,
+        (None, Some(c)) => {
+            let quack_time_ms = c
+                .good_time_unix_milliseconds
+                .saturating_sub(c.start_unix_time_milliseconds);
+            // Round up, so that no flagged data is used.
+            (quack_time_ms as f64 / c.integration_time_milliseconds as f64).ceil() as u32
+        }
+        (None, None) => 0,
+    };
+
+    let data_ms = context.as_ref().map(|c| {
+        c.end_unix_time_milliseconds
+            .saturating_sub(c.start_unix_time_milliseconds)
+            .saturating_sub(start_processing_at as u64 * c.integration_time_milliseconds)
+    });
+
+    // The RTS can only handle contiguous coarse channels, so split the
+    // channels into contiguous groups; each group gets its own .in file.
+    let subband_groups = match (&context, &common.subband_ids) {
+        (Some(c), subband_ids) => {
+            let channels: Vec<CoarseChannel> = c
+                .coarse_channels
+                .iter()
+                .map(|cc| CoarseChannel {
+                    receiver_channel_number: cc.receiver_channel_number as _,
+                    gpubox_number: cc.gpubox_number as _,
+                    start_hz: cc.channel_start_hz,
+                })
+                .collect();
+            if let Some(ids) = subband_ids {
+                for id in ids {
+                    ensure!(
+                        channels.iter().any(|c| c.gpubox_number == *id),
+                        "Subband ID {} is not in the metafits",
+                        id
+                    );
+                }
+            }
+            subband_groups(
+                &channels,
+                subband_ids.as_deref(),
+                c.coarse_channel_width_hz,
+                common.base_freq,
+            )
+        }
+        (None, Some(ids)) => match common.base_freq {
+            Some(base_freq) => vec![SubbandGroup {
+                subband_ids: ids.clone(),
+                base_freq,
+            }],
+            None => bail!(
+                "Neither --base-freq nor --metafits were specified; cannot get the base frequency."
+            ),
+        },
+        (None, None) => {
+            bail!("Neither --subband-ids nor --metafits were specified; cannot get the subbands.")
+        }
+    };
+
+    // The mode-specific fields are filled by `SharedParams::for_mode`.
+    let params = RtsParams {
+        mode: RtsMode::Patch,
+        base_dir,
+        base_filename,
+        metafits: common.metafits.clone(),
+        use_cotter_flags: !common.no_cotter_flags,
+        source_catalogue_file: PathBuf::new(),
+        do_rfi_flagging: common.rts_rfi_flagging,
+        do_rx_corrections: !common.dont_rx_correct,
+        do_raw_data_corrections: !common.dont_correct_raw_data,
+        read_gpubox_direct: !common.dont_read_gpubox_direct,
+        read_all_from_single_file: !common.dont_read_all_from_single_file,
+        add_node_number_to_filename: common.add_node_number,
+        use_packet_input: common.use_packet_input,
+        use_threaded_vi: common.use_threaded_vi,
+        fee_beam_file,
+        obsid,
+        obs_image_centre_ra,
+        obs_image_centre_dec,
+        obs_pointing_centre_ha,
+        obs_pointing_centre_dec,
+        start_processing_at,
+        corr_dump_time: integration_time_ms as f64 / 1e3,
+        corr_dumps_per_cadence: 0,
+        num_integration_bins: 0,
+        num_iterations: 0,
+        fine_channel_width_mhz,
+        num_fine_channels,
+        f_scrunch: common.fscrunch.unwrap_or(2),
+        // Filled for each group of subbands below.
+        base_freq: 0.0,
+        subband_ids: vec![],
+        num_primary_cals: 0,
+        disable_srclist_vetos: common.disable_srclist_vetos,
+        write_vis_to_uvfits: false,
+        max_frequency: common.max_frequency.unwrap_or(DEFAULT_MAX_FREQUENCY),
+        array_num_stations,
+        array_position_lat,
+        array_position_long,
+        cal_baseline_min: common.cal_baseline_min.unwrap_or(DEFAULT_CAL_BASELINE_MIN),
+        cal_short_baseline_taper: common
+            .cal_short_baseline_taper
+            .unwrap_or(DEFAULT_CAL_SHORT_BASELINE_TAPER),
+        image_oversampling: common
+            .image_oversampling
+            .unwrap_or(DEFAULT_IMAGE_OVERSAMPLING),
+        store_pixel_matrices: common.store_pixel_matrices,
+        other_keys: vec![],
+    };
+    Ok(SharedParams {
+        params: subband_groups
+            .into_iter()
+            .map(|g| RtsParams {
+                base_freq: g.base_freq,
+                subband_ids: g.subband_ids,
+                ..params.clone()
+            })
+            .collect(),
+        integration_time_ms,
+        data_ms,
+        context,
+    })
+}
+
+impl SharedParams {
+    /// Fill the mode-specific parameters.
+    fn for_mode(
+        &self,
+        common: &Common,
+        mode_args: ModeArgs,
+        srclist: Option<Srclist>,
+    ) -> Result<Vec<RtsParams>, anyhow::Error> {
+        let mode = mode_args.mode();
+
+        // Set up the timing stuff. Derive things from the integration time
+        // first, then overwrite settings with anything user-specified.
+        let mut timing = match common.corr_dumps_per_cadence {
+            Some(c) => RtsTiming::with_corr_dumps_per_cadence(mode, self.integration_time_ms, c),
             None => {
+                let cadence = common.cadence.unwrap_or(match mode {
+                    RtsMode::Patch => DEFAULT_PATCH_CADENCE,
+                    RtsMode::Peel { .. } => DEFAULT_PEEL_CADENCE,
+                });
+                RtsTiming::new(
+                    mode,
+                    self.integration_time_ms,
+                    (cadence * 1e3).round() as u64,
+                )?
+            }
+        };
+        if let Some(n) = common.num_integration_bins {
+            timing.num_integration_bins = n;
+        }
+
+        // Check that all `timing` fields are non zero.
+        if timing.corr_dump_time == 0.0
+            || timing.corr_dumps_per_cadence == 0
+            || timing.num_integration_bins == 0
+        {
+            bail!("At least one of the timing fields was zero:\n{:?}", timing)
+        }
+
+        // Each iteration of the RTS processes one cadence of data. Use as many
+        // as can fit in the data after StartProcessingAt.
+        let num_iterations = match (mode_args.num_iterations(), self.data_ms) {
+            (Some(n), _) => n,
+            (None, Some(data_ms)) => {
+                let cadence_ms = timing.corr_dump_time * 1e3 * timing.corr_dumps_per_cadence as f64;
+                let num_iterations = (data_ms as f64 / cadence_ms).floor() as u32;
+                let leftover_ms = data_ms as f64 - num_iterations as f64 * cadence_ms;
                 ensure!(
-                    context.is_some(),
-                    "Neither --force-ra nor --metafits were specified; cannot get the RA pointing."
+                    num_iterations > 0,
+                    "The observation has only {}s of good data, which isn't enough for a single {}s cadence",
+                    data_ms as f64 / 1e3,
+                    cadence_ms / 1e3
                 );
-                let c = context.as_ref().unwrap();
-                match c.ra_phase_center_degrees {
-                    Some(v) => v,
-                    // Otherwise, just use RA.
-                    None => c.ra_tile_pointing_degrees,
+                if leftover_ms > 0.0 {
+                    eprintln!(
+                        "WARNING: The {}s of good data is not a multiple of the {}s cadence; the last {}s will not be processed",
+                        data_ms as f64 / 1e3,
+                        cadence_ms / 1e3,
+                        leftover_ms / 1e3
+                    );
                 }
+                num_iterations
             }
-        } / 15.0;
+            (None, None) => mode_args.default_num_iterations(),
+        };
 
-        let obs_image_centre_dec = match common.force_dec {
-            Some(r) => r,
+        let srclist = match srclist {
+            Some(s) => s,
             None => {
-                ensure!(
-                    context.is_some(),
-                    "Neither --force-dec nor --metafits were specified; cannot get the Dec pointing."
-                );
-                let c = context.as_ref().unwrap();
-                match c.dec_phase_center_degrees {
-                    Some(v) => v,
-                    None => c.dec_tile_pointing_degrees,
+                bail!("No --srclist was specified, either on the command line or in a config file.")
+            }
+        };
+        let source_catalogue_file = srclist.path.to_path_buf();
+
+        // The RTS uses the brightest sources first, so asking for more sources
+        // than the list has isn't fatal, but it's probably a mistake.
+        if let RtsMode::Peel { num_cals, num_peel } = mode {
+            for (arg, n) in &[("--num-cals", num_cals), ("--num-peel", num_peel)] {
+                if *n as usize > srclist.num_sources {
+                    eprintln!(
+                        "WARNING: {} is {}, but {} only has {} sources",
+                        arg,
+                        n,
+                        source_catalogue_file.display(),
+                        srclist.num_sources
+                    );
                 }
             }
+        }
+
+        Ok(self
+            .params
+            .iter()
+            .map(|p| RtsParams {
+                mode,
+                source_catalogue_file: source_catalogue_file.clone(),
+                corr_dump_time: timing.corr_dump_time,
+                corr_dumps_per_cadence: timing.corr_dumps_per_cadence,
+                num_integration_bins: timing.num_integration_bins,
+                num_iterations,
+                num_primary_cals: mode_args.num_primary_cals(),
+                write_vis_to_uvfits: mode_args.write_vis_to_uvfits(),
+                ..p.clone()
+            })
+            .collect())
+    }
+}
+
+/// Report the tiles of an observation that have dead dipoles, and write them
+/// to a tile flags file if asked to. Tiles that are flagged in the metafits are
+/// ignored.
+fn report_dead_dipole_tiles(common: &Common, context: &mwalibContext) -> Result<(), anyhow::Error> {
+    let delays: Vec<TileDelays> = context
+        .antennas
+        .iter()
+        .map(|ant| TileDelays {
+            flagged: ant.x_pol.flagged || ant.y_pol.flagged,
+            x: &ant.x_pol.delays,
+            y: &ant.y_pol.delays,
+        })
+        .collect();
+    let num_tiles = delays.iter().filter(|t| !t.flagged).count();
+    let dead = dead_dipole_tiles(&delays);
+    let tiles: Vec<_> = dead
+        .iter()
+        .map(|d| context.antennas[d.tile].antenna)
+        .collect();
+    let reports: Vec<String> = dead
+        .iter()
+        .map(|d| {
+            let ant = &context.antennas[d.tile];
+            format!(
+                "{} (antenna {}): X dipoles {:?}, Y dipoles {:?}",
+                ant.tile_name, ant.antenna, d.x, d.y
+            )
+        })
+        .collect();
+
+    if !tiles.is_empty() {
+        eprintln!(
+            "{} of {} unflagged tiles in obsid {} have dead dipoles:\n    {}",
+            tiles.len(),
+            num_tiles,
+            context.obsid,
+            reports.join("\n    ")
+        );
+        let max_fraction = common
+            .max_dead_dipole_fraction
+            .unwrap_or(DEFAULT_MAX_DEAD_DIPOLE_FRACTION);
+        let fraction = tiles.len() as f64 / num_tiles as f64;
+        if fraction > max_fraction {
+            eprintln!(
+                "WARNING: {:.0}% of the tiles in obsid {} have dead dipoles (more than {:.0}%); the RTS assumes that all tiles have the same beam, so calibration may suffer",
+                fraction * 100.0,
+                context.obsid,
+                max_fraction * 100.0
+            );
+        }
+    }
+
+    if let Some(path) = &common.dead_dipole_tile_flags {
+        let path = PathBuf::from(
+            path.to_string_lossy()
+                .replace("{obsid}", &context.obsid.to_string()),
+        );
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut f = File::create(&path)?;
+        write_flagged_tiles(&mut f, &tiles)?;
+    }
+    Ok(())
+}
+
+/// An RTS source list that has been checked.
+#[derive(Clone, Copy)]
+struct Srclist<'a> {
+    path: &'a Path,
+
+    /// The number of sources in the source list.
+    num_sources: usize,
+}
+
+impl<'a> Srclist<'a> {
+    /// Check that a file is an RTS source list with at least one source.
+    fn read(path: &'a Path) -> Result<Self, anyhow::Error> {
+        let sources = match parse_source_list(&std::fs::read_to_string(path)?) {
+            Ok(s) => s,
+            Err(e) => bail!("{} is not a valid RTS source list: {}", path.display(), e),
         };
+        ensure!(
+            !sources.is_empty(),
+            "{} does not contain any sources",
+            path.display()
+        );
+        Ok(Srclist {
+            path,
+            num_sources: sources.len(),
+        })
+    }
+}
+
+/// Number an output file, e.g. "rts_patch.in" becomes "rts_patch_01.in".
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!("_{:02}", n));
+    if let Some(ext) = path.extension() {
+        file_name.push(".");
+        file_name.push(ext);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Find the gpubox files in `base_dir` whose names start with `base_filename`,
+/// e.g. "*_gpubox" matches "1065880128_20131015134830_gpubox01_00.fits".
+fn gpubox_files(base_dir: &Path, base_filename: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let glob = globset::Glob::new(&format!("{}*.fits", base_filename))?.compile_matcher();
+    let mut gpubox_files = vec![];
+    for entry in std::fs::read_dir(base_dir)? {
+        let path = entry?.path();
+        if path.file_name().map(|f| glob.is_match(f)) == Some(true) {
+            gpubox_files.push(path);
+        }
+    }
+    ensure!(
+        !gpubox_files.is_empty(),
+        "--read-gpubox-times was specified, but no gpubox files matching {}/{}*.fits were found",
+        base_dir.display(),
+        base_filename
+    );
+    gpubox_files.sort();
+    Ok(gpubox_files)
+}
+
+/// Print the RTS keys that differ between two .in files. Keys that mongoose
+/// doesn't know about and values it can't read are reported on stderr, with
+/// their line numbers. Returns whether the files differ.
+fn diff(first: &Path, second: &Path) -> Result<bool, anyhow::Error> {
+    let first_text = std::fs::read_to_string(first)?;
+    let second_text = std::fs::read_to_string(second)?;
+    let diffs = match diff_in_files(&first_text, &second_text) {
+        Ok(d) => d,
+        Err(e) => bail!(
+            "Couldn't compare {} and {}: {}",
+            first.display(),
+            second.display(),
+            e
+        ),
+    };
+    for (path, text) in &[(first, &first_text), (second, &second_text)] {
+        match text.parse::<RtsParams>() {
+            Ok(params) => {
+                for kv in params.other_keys {
+                    eprintln!(
+                        "{}: Line {}: RTS key '{}' is not known to mongoose",
+                        path.display(),
+                        kv.line,
+                        kv.key
+                    );
+                }
+            }
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+    if diffs.is_empty() {
+        return Ok(false);
+    }
+
+    println!("--- {}", first.display());
+    println!("+++ {}", second.display());
+    for d in diffs {
+        if let Some(v) = d.first {
+            println!("-{}={}", d.key, v);
+        }
+        if let Some(v) = d.second {
+            println!("+{}={}", d.key, v);
+        }
+    }
+    Ok(true)
+}
 
-        let (obs_pointing_centre_ha, obs_pointing_centre_dec) = {
-            let common = match &self {
-                Opts::Patch { common, .. } => common,
-                Opts::Peel { common, .. } => common,
+/// Write .in files for `params` to `path`. If there's more than one set of
+/// parameters (because the coarse channels aren't contiguous), the files are
+/// numbered. The paths written to are returned.
+fn write_in_files(params: &[RtsParams], path: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let paths: Vec<PathBuf> = match params {
+        [_] => vec![path.to_path_buf()],
+        _ => {
+            eprintln!(
+                "The coarse channels are not contiguous; writing {} .in files",
+                params.len()
+            );
+            (1..=params.len()).map(|i| numbered_path(path, i)).collect()
+        }
+    };
+    for (params, path) in params.iter().zip(paths.iter()) {
+        let mut file = File::create(path)?;
+        write!(&mut file, "{}", params)?;
+        if paths.len() > 1 {
+            eprintln!("{}: SubBandIDs {:?}", path.display(), params.subband_ids);
+        }
+    }
+    Ok(paths)
+}
+
+/// Find the metafits files specified for a batch. Directories are searched for
+/// metafits files, and glob patterns in file names are expanded.
+fn find_metafits_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut metafits_files = vec![];
+    for path in paths {
+        let (dir, glob) = if path.is_dir() {
+            (path.as_path(), globset::Glob::new("*.metafits")?)
+        } else if path.exists() {
+            metafits_files.push(path.clone());
+            continue;
+        } else {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let dir = match path.parent() {
+                Some(p) if p != Path::new("") => p,
+                _ => Path::new("."),
             };
-            match (
-                &common.metafits,
-                common.ha_pointing_centre,
-                common.dec_pointing_centre,
-            ) {
-                // If we have a metafits, there's no need to populate these
-                // fields. The RTS will get them from the metafits file.
-                (Some(_), _, _) => (None, None),
-                (None, Some(ha), Some(dec)) => (Some(ha), Some(dec)),
-                // We need to bail if the pointing centre wasn't supplied when a
-                // metafits also wasn't supplied.
-                (None, _, _) => bail!("When not using a metafits file, both --ha-pointing-centre and --dec-pointing-centre must be specified.")
+            (dir, globset::Glob::new(&file_name)?)
+        };
+        let glob = glob.compile_matcher();
+        let mut matches = vec![];
+        if dir.is_dir() {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?.path();
+                if entry.file_name().map(|f| glob.is_match(f)) == Some(true) {
+                    matches.push(entry);
+                }
             }
+        }
+        ensure!(
+            !matches.is_empty(),
+            "No metafits files found matching {}",
+            path.display()
+        );
+        matches.sort();
+        metafits_files.append(&mut matches);
+    }
+    Ok(metafits_files)
+}
+
+/// Write the patch and peel .in files for a single observation of a batch.
+/// The paths written to are returned.
+fn batch_obs(
+    common: &Common,
+    both: &BothArgs,
+    batch: &BatchArgs,
+    (patch_srclist, peel_srclist): (Option<Srclist>, Option<Srclist>),
+    metafits: &Path,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut common = common.clone();
+    common.metafits = Some(metafits.to_path_buf());
+    if common.base_dir.is_none() {
+        let dir = match metafits.parent() {
+            Some(p) if p != Path::new("") => p,
+            _ => Path::new("."),
         };
+        common.base_dir = Some(dir.canonicalize()?);
+    }
 
-        let subband_ids = match &common.subband_ids {
-            Some(s) => s.clone(),
-            None => {
-                ensure!(
-                    context.is_some(),
-                    "Neither --subband-ids nor --metafits were specified; cannot get the subbands."
-                );
-                let c = context.as_ref().unwrap();
-                let mut cc: Vec<u8> = c
-                    .coarse_channels
-                    .iter()
-                    .map(|cc| cc.gpubox_number as _)
-                    .collect();
-                cc.sort_unstable();
-                cc
+    let shared = shared_params(&common)?;
+    let context = match &shared.context {
+        Some(c) => c,
+        None => bail!("Couldn't read {}", metafits.display()),
+    };
+    let obsid = context.obsid;
+    let mut problems = vec![];
+    // A delay of 32 means that a dipole was turned off for the observation.
+    if context.delays.contains(&32) {
+        problems.push(format!("bad delays ({:?})", context.delays));
+    }
+    let patch = shared.for_mode(&common, ModeArgs::Patch(&both.patch_args()), patch_srclist)?;
+    let peel = shared.for_mode(&common, ModeArgs::Peel(&both.peel_args()), peel_srclist)?;
+    if !common.no_validate {
+        problems.append(&mut file_problems(patch.iter().chain(peel.iter())));
+    }
+    ensure!(problems.is_empty(), problems.join("; "));
+
+    let mut written = vec![];
+    for (params, template) in &[(patch, &batch.patch_template), (peel, &batch.peel_template)] {
+        let path = PathBuf::from(template.replace("{obsid}", &obsid.to_string()));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        written.append(&mut write_in_files(params, &path)?);
+    }
+    Ok(written)
+}
+
+/// Write the patch and peel .in files for many observations, then summarise
+/// the observations that failed.
+fn batch(common: &Common, both: &BothArgs, batch: &BatchArgs) -> Result<(), anyhow::Error> {
+    ensure!(
+        common.metafits.is_none() && common.obsid.is_none(),
+        "--metafits and --obsid can't be used in a batch; give the metafits files as arguments"
+    );
+    ensure!(
+        common.output_file.is_none(),
+        "--output-file can't be used in a batch; use --patch-template and --peel-template"
+    );
+    // Check the source lists before processing any observations.
+    let srclists = both.srclists(common)?;
+
+    let metafits_files = find_metafits_files(&batch.metafits_files)?;
+    let mut failures = vec![];
+    for metafits in &metafits_files {
+        match batch_obs(common, both, batch, srclists, metafits) {
+            Ok(written) => {
+                for f in written {
+                    println!("{}", f.display());
+                }
             }
-        };
+            Err(e) => failures.push((metafits, e)),
+        }
+    }
 
-        Ok(RtsParams {
-            mode,
-            base_dir: common.base_dir.clone(),
-            base_filename: common.base_filename.clone(),
-            metafits: common.metafits.clone(),
-            use_cotter_flags: !common.no_cotter_flags,
-            source_catalogue_file: common.srclist.clone(),
-            do_rfi_flagging: common.rts_rfi_flagging,
-            do_rx_corrections: !common.dont_rx_correct,
-            do_raw_data_corrections: !common.dont_correct_raw_data,
-            read_gpubox_direct: !common.dont_read_gpubox_direct,
-            read_all_from_single_file: !common.dont_read_all_from_single_file,
-            add_node_number_to_filename: common.add_node_number,
-            fee_beam_file,
-            obsid,
-            obs_image_centre_ra,
-            obs_image_centre_dec,
-            obs_pointing_centre_ha,
-            obs_pointing_centre_dec,
-            corr_dump_time,
-            corr_dumps_per_cadence,
-            num_integration_bins,
-            num_iterations: match &self {
-                Opts::Patch { num_iterations, .. } => *num_iterations,
-                Opts::Peel { num_iterations, .. } => *num_iterations,
-            },
-            fine_channel_width_mhz,
-            num_fine_channels,
-            f_scrunch: common.fscrunch,
-            base_freq,
-            subband_ids,
-            num_primary_cals: match &self {
-                Opts::Patch {
-                    num_primary_cals, ..
-                } => *num_primary_cals,
-                Opts::Peel {
-                    num_cals,
-                    num_primary_cals,
-                    ..
-                } => *num_primary_cals.min(num_cals),
-            },
-            disable_srclist_vetos: common.disable_srclist_vetos,
-            write_vis_to_uvfits: match &self {
-                Opts::Patch {
-                    write_vis_to_uvfits,
-                    ..
-                } => *write_vis_to_uvfits,
-                Opts::Peel {
-                    dont_write_vis_to_uvfits,
-                    ..
-                } => !*dont_write_vis_to_uvfits,
-            },
-        })
+    eprintln!(
+        "{} of {} observations succeeded",
+        metafits_files.len() - failures.len(),
+        metafits_files.len()
+    );
+    for (metafits, e) in &failures {
+        eprintln!("    {}: {}", metafits.display(), e);
     }
+    ensure!(
+        failures.is_empty(),
+        "{} observations failed",
+        failures.len()
+    );
+    Ok(())
+}
+
+/// Quote an argument for a shell script, if necessary.
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_=./,:@%+".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r#"'\''"#))
+    }
+}
+
+/// Get the problems with the files that the RTS will open for each set of
+/// parameters, without duplicates.
+fn file_problems<'a, I: Iterator<Item = &'a RtsParams>>(params: I) -> Vec<String> {
+    let mut problems = vec![];
+    for problem in params.flat_map(|p| p.validate()) {
+        let problem = problem.to_string();
+        if !problems.contains(&problem) {
+            problems.push(problem);
+        }
+    }
+    problems
+}
+
+/// Check that the files the RTS will open exist, reporting all of the problems
+/// at once.
+fn validate<'a, I: Iterator<Item = &'a RtsParams>>(params: I) -> Result<(), anyhow::Error> {
+    let problems = file_problems(params);
+    ensure!(
+        problems.is_empty(),
+        "The RTS would not be able to run:\n    {}\n(Use --no-validate to write the .in files anyway)",
+        problems.join("\n    ")
+    );
+    Ok(())
+}
+
+/// Get the patch and peel RTS parameters for an observation.
+fn both_params(
+    common: &Common,
+    both: &BothArgs,
+) -> Result<(Vec<RtsParams>, Vec<RtsParams>), anyhow::Error> {
+    let (patch_srclist, peel_srclist) = both.srclists(common)?;
+    let shared = shared_params(common)?;
+    let patch = shared.for_mode(common, ModeArgs::Patch(&both.patch_args()), patch_srclist)?;
+    let peel = shared.for_mode(common, ModeArgs::Peel(&both.peel_args()), peel_srclist)?;
+    if !common.no_validate {
+        validate(patch.iter().chain(peel.iter()))?;
+    }
+    Ok((patch, peel))
+}
+
+/// Write the patch and peel .in files, as well as setup and run scripts, to
+/// the job directory.
+fn job(common: &Common, both: &BothArgs, job: &JobArgs) -> Result<(), anyhow::Error> {
+    ensure!(
+        common.output_file.is_none(),
+        "--output-file can't be used when setting up a job; use --job-dir"
+    );
+    let template = match &job.scheduler_template {
+        Some(t) => match std::fs::read_to_string(t) {
+            Ok(t) => t,
+            Err(e) => bail!(
+                "Couldn't read the scheduler template {}: {}",
+                t.display(),
+                e
+            ),
+        },
+        None => DEFAULT_SCHEDULER_TEMPLATE.to_string(),
+    };
+    // Split the template into the scheduler directives and the command that
+    // runs the RTS.
+    let (launch, directives): (Vec<&str>, Vec<&str>) =
+        template.lines().partition(|l| l.contains("{in_file}"));
+    ensure!(
+        !launch.is_empty(),
+        "The scheduler template has no command to run the RTS; it needs a line containing {{in_file}}"
+    );
+
+    let (patch_params, peel_params) = both_params(common, both)?;
+
+    std::fs::create_dir_all(&job.job_dir)?;
+    let job_dir = job.job_dir.canonicalize()?;
+    let patch_files = write_in_files(&patch_params, &job_dir.join("rts_patch.in"))?;
+    let peel_files = write_in_files(&peel_params, &job_dir.join("rts_peel.in"))?;
+
+    // The RTS uses one node per subband, plus a master node.
+    let num_nodes = |params: &RtsParams| params.subband_ids.len() + 1;
+    let obsid = patch_params[0].obsid;
+    let fill = |lines: &[&str], job_name: &str, num_nodes: usize| {
+        lines
+            .iter()
+            .map(|l| {
+                format!("{}\n", l)
+                    .replace("{obsid}", &obsid.to_string())
+                    .replace("{job_name}", job_name)
+                    .replace("{num_nodes}", &num_nodes.to_string())
+            })
+            .collect::<String>()
+    };
+
+    // The setup script re-runs this job with the resolved arguments, i.e. with
+    // absolute paths, and the settings of any config file and the FEE beam file
+    // that was found.
+    let mut settings = match toml::Value::try_from(JobSettings { common, both, job }) {
+        Ok(toml::Value::Table(t)) => t,
+        _ => bail!("Couldn't get the arguments of this job"),
+    };
+    settings.insert(
+        "job-dir".to_string(),
+        toml::Value::String(job_dir.display().to_string()),
+    );
+    if let Some(f) = &patch_params[0].fee_beam_file {
+        settings.insert(
+            "fee-beam-file".to_string(),
+            toml::Value::String(f.display().to_string()),
+        );
+    }
+    let cwd = std::env::current_dir()?;
+    let mut command = vec![
+        shell_quote(&std::env::current_exe()?.display().to_string()),
+        "job".to_string(),
+    ];
+    for (key, value) in &settings {
+        match setting_args(key, value, &cwd) {
+            Some(args) => command.extend(args.iter().map(|a| shell_quote(&a.to_string_lossy()))),
+            None => bail!("Couldn't write the --{} argument of this job", key),
+        }
+    }
+    let setup = format!(
+        r#"{header}
+# Re-create this RTS job.
+set -eux
+{command}
+
+# Ensure permissions are sensible!
+find {job_dir} -user $USER -type d -exec chmod g+rwx,o+rx,o-w {{}} \;
+find {job_dir} -user $USER -type f -exec chmod g+rw,o+r,o-w {{}} \;
+
+echo "rts_setup.sh finished successfully."
+"#,
+        header = fill(&directives, &format!("se_{}", obsid), 1),
+        command = command.join(" "),
+        job_dir = shell_quote(&job_dir.display().to_string()),
+    );
+
+    let run_job_name = format!("rts_{}", obsid);
+    let mut run_lines = String::new();
+    for (params, path) in patch_params
+        .iter()
+        .zip(patch_files.iter())
+        .chain(peel_params.iter().zip(peel_files.iter()))
+    {
+        let in_file = shell_quote(&path.file_name().unwrap().to_string_lossy());
+        run_lines +=
+            &fill(&launch, &run_job_name, num_nodes(params)).replace("{in_file}", &in_file);
+    }
+    let max_num_nodes = patch_params
+        .iter()
+        .chain(peel_params.iter())
+        .map(num_nodes)
+        .max()
+        .unwrap();
+    let run = format!(
+        r#"{header}
+set -eux
+cd {job_dir}
+
+{run_lines}
+echo "rts_run.sh finished successfully."
+"#,
+        header = fill(&directives, &run_job_name, max_num_nodes),
+        job_dir = shell_quote(&job_dir.display().to_string()),
+        run_lines = run_lines,
+    );
+
+    for (name, contents) in &[("rts_setup.sh", setup), ("rts_run.sh", run)] {
+        let path = job_dir.join(name);
+        let mut file = File::create(&path)?;
+        write!(&mut file, "{}", contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = file.metadata()?.permissions();
+            perms.set_mode(perms.mode() | 0o111);
+            file.set_permissions(perms)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The arguments of a job, keyed by their long names.
+#[derive(Serialize)]
+struct JobSettings<'a> {
+    #[serde(flatten)]
+    common: &'a Common,
+    #[serde(flatten)]
+    both: &'a BothArgs,
+    #[serde(flatten)]
+    job: &'a JobArgs,
 }
 
 fn main() -> Result<(), anyhow::Error> {
-    let mut opts = Opts::from_args();
-    let mut common = match &mut opts {
-        Opts::Patch { common, .. } => common,
-        Opts::Peel { common, .. } => common,
+    let mut opts = parse_args()?;
+    // What to do once the common arguments are sorted out.
+    enum Action<'a> {
+        InFile(ModeArgs<'a>),
+        Both(&'a BothArgs, &'a Path, &'a Path),
+        Batch(&'a BothArgs, &'a BatchArgs),
+        Job(&'a BothArgs, &'a JobArgs),
+    }
+    let (common, action) = match &mut opts {
+        Opts::Patch { common, patch } => (common, Action::InFile(ModeArgs::Patch(patch))),
+        Opts::Peel { common, peel } => (common, Action::InFile(ModeArgs::Peel(peel))),
+        Opts::Both {
+            common,
+            both,
+            patch_output,
+            peel_output,
+        } => (common, Action::Both(both, patch_output, peel_output)),
+        Opts::Batch {
+            common,
+            both,
+            batch,
+        } => (common, Action::Batch(both, batch)),
+        Opts::Job { common, both, job } => (common, Action::Job(both, job)),
+        Opts::Diff { first, second } => {
+            // Like diff, exit with 1 if the files differ.
+            if diff(first, second)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
     };
 
     // Sanity checks.
     // Test that the base directory exists, and make the path absolute.
-    common.base_dir = match common.base_dir.canonicalize() {
-        Ok(d) => d,
-        Err(_) => bail!(
-            "Specified base directory ({}) does not exist!",
-            common.base_dir.display()
-        ),
-    };
+    if let Some(base_dir) = &mut common.base_dir {
+        *base_dir = match base_dir.canonicalize() {
+            Ok(d) => d,
+            Err(_) => bail!(
+                "Specified base directory ({}) does not exist!",
+                base_dir.display()
+            ),
+        };
+    }
 
     // Test that the metafits file exists.
     if let Some(m) = &common.metafits {
@@ -556,18 +2010,43 @@ fn main() -> Result<(), anyhow::Error> {
     }
 
     // Test that the srclist file exists.
-    ensure!(
-        common.srclist.exists(),
-        "Specified source list file ({:?}) does not exist!",
-        common.srclist
-    );
+    if let Some(s) = &common.srclist {
+        ensure!(
+            s.exists(),
+            "Specified source list file ({:?}) does not exist!",
+            s
+        );
+    }
 
+    let mode_args = match action {
+        Action::InFile(m) => m,
+        Action::Both(both, patch_output, peel_output) => {
+            ensure!(
+                common.output_file.is_none(),
+                "--output-file can't be used when writing both .in files; use --patch-output and --peel-output"
+            );
+            let (patch, peel) = both_params(common, both)?;
+            write_in_files(&patch, patch_output)?;
+            write_in_files(&peel, peel_output)?;
+            return Ok(());
+        }
+        Action::Batch(both, b) => return batch(common, both, b),
+        Action::Job(both, j) => return job(common, both, j),
+    };
+    let params = rts_params(common, mode_args)?;
+    if !common.no_validate {
+        validate(params.iter())?;
+    }
     match &common.output_file {
         Some(f) => {
-            let mut file = File::create(&f)?;
-            write!(&mut file, "{}", opts.rts_params()?)?;
+            write_in_files(&params, f)?;
         }
-        None => print!("{}", opts.rts_params()?),
+        None => match params.as_slice() {
+            [params] => print!("{}", params),
+            _ => bail!(
+                "The coarse channels are not contiguous, so multiple .in files are needed; specify --output-file"
+            ),
+        },
     }
 
     Ok(())
@@ -0,0 +1,196 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure};
+use mwalib::mwalibContext;
+use structopt::{clap::AppSettings, StructOpt};
+
+use mongoose::beam::analytic_tile_response;
+use mongoose::coords::HADec;
+use mongoose::srclist::{parse_source_list, rts::write_source_list, Source, SourceListType};
+
+/// Rank the sources of a source list by their apparent brightness in an
+/// observation's primary beam (the analytic MWA tile beam), and write the
+/// brightest sources as RTS source lists for the RTS's patch and peel steps.
+///
+/// The RTS patches with only one calibrator, so all of the sources in the
+/// patch list are combined into a single source, named after the brightest.
+/// The sources in the peel list are kept separate.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "srclist-by-beam", global_settings = &[AppSettings::ColoredHelp, AppSettings::ArgRequiredElseHelp])]
+struct Opts {
+    /// The source list to select sources from. Its format is guessed from its
+    /// extension (.yaml or .yml for hyperdrive, .skymodel for AO); anything
+    /// else is read as an RTS source list.
+    #[structopt(short, long, parse(from_str))]
+    srclist: PathBuf,
+
+    /// The path to the observation's metafits file.
+    #[structopt(short, long, parse(from_str))]
+    metafits: PathBuf,
+
+    /// The number of sources to put in the patch source list.
+    #[structopt(short = "n", long, default_value = "1000")]
+    num_patch: usize,
+
+    /// The number of sources to put in the peel source list.
+    #[structopt(long, default_value = "2000")]
+    num_peel: usize,
+
+    /// Only use sources within this many degrees of the pointing centre.
+    #[structopt(long, default_value = "30")]
+    cutoff: f64,
+
+    /// Where to write the patch source list. Default:
+    /// <srclist name>_patch<num-patch>.txt in the current directory
+    #[structopt(long, parse(from_str))]
+    patch_output: Option<PathBuf>,
+
+    /// Where to write the peel source list. Default:
+    /// <srclist name>_peel<num-peel>.txt in the current directory
+    #[structopt(long, parse(from_str))]
+    peel_output: Option<PathBuf>,
+}
+
+/// The angular separation between two positions [radians].
+fn angular_separation(ra1: f64, dec1: f64, ra2: f64, dec2: f64) -> f64 {
+    // The haversine formula is well behaved for small separations.
+    let hav = ((dec2 - dec1) / 2.0).sin().powi(2)
+        + dec1.cos() * dec2.cos() * ((ra2 - ra1) / 2.0).sin().powi(2);
+    2.0 * hav.sqrt().min(1.0).asin()
+}
+
+/// The Stokes I flux density of a source as seen through the primary beam,
+/// averaged over the observation's coarse channels [Jy].
+fn apparent_flux_density(source: &Source, context: &mwalibContext) -> Result<f64, anyhow::Error> {
+    let lst = context.lst_degrees.to_radians();
+    let mut total = 0.0;
+    for comp in &source.components {
+        let azza =
+            HADec::from_radec(comp.ra.to_radians(), comp.dec.to_radians(), lst).to_azza_mwa();
+        for cc in &context.coarse_channels {
+            let freq = cc.channel_centre_hz as f64;
+            let beam = analytic_tile_response(azza.az, azza.za, freq, &context.delays)?;
+            total += beam * comp.flux_type.estimate_stokes_i(freq);
+        }
+    }
+    Ok(total / context.coarse_channels.len() as f64)
+}
+
+/// Write a source list, then print where it was written.
+fn write(path: &Path, sources: &[Source]) -> Result<(), anyhow::Error> {
+    let mut f = BufWriter::new(File::create(path)?);
+    write_source_list(&mut f, sources)?;
+    f.flush()?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let opts = Opts::from_args();
+    ensure!(
+        opts.num_patch > 0 && opts.num_peel > 0,
+        "The patch and peel source lists need at least one source each"
+    );
+    ensure!(
+        opts.cutoff > 0.0,
+        "The cutoff must be bigger than 0 degrees"
+    );
+    ensure!(
+        opts.srclist.exists(),
+        "Specified source list file ({}) does not exist!",
+        opts.srclist.display()
+    );
+    ensure!(
+        opts.metafits.exists(),
+        "Specified metafits file ({}) does not exist!",
+        opts.metafits.display()
+    );
+    let context = mwalibContext::new(&opts.metafits, &[])?;
+    let sl_type = SourceListType::from_path(&opts.srclist).unwrap_or(SourceListType::Rts);
+    let sources = match parse_source_list(&std::fs::read_to_string(&opts.srclist)?, sl_type) {
+        Ok(s) => s,
+        Err(e) => bail!(
+            "{} is not a valid {} source list: {}",
+            opts.srclist.display(),
+            sl_type,
+            e
+        ),
+    };
+
+    // Rank the sources near the pointing centre by their apparent brightness.
+    // A source's position is that of its first component.
+    let pointing_ra = context.ra_tile_pointing_degrees.to_radians();
+    let pointing_dec = context.dec_tile_pointing_degrees.to_radians();
+    let mut ranked = vec![];
+    for source in sources {
+        let first = &source.components[0];
+        let sep = angular_separation(
+            pointing_ra,
+            pointing_dec,
+            first.ra.to_radians(),
+            first.dec.to_radians(),
+        );
+        if sep.to_degrees() > opts.cutoff {
+            continue;
+        }
+        let flux = apparent_flux_density(&source, &context)?;
+        if flux > 0.0 {
+            ranked.push((flux, source));
+        }
+    }
+    ensure!(
+        !ranked.is_empty(),
+        "None of the sources in {} are within {} degrees of the pointing centre",
+        opts.srclist.display(),
+        opts.cutoff
+    );
+    ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    let ranked: Vec<Source> = ranked.into_iter().map(|(_, s)| s).collect();
+    for (list, requested) in &[("patch", opts.num_patch), ("peel", opts.num_peel)] {
+        if ranked.len() < *requested {
+            eprintln!(
+                "WARNING: {} sources were requested for the {} list, but only {} are within {} degrees of the pointing centre",
+                requested,
+                list,
+                ranked.len(),
+                opts.cutoff
+            );
+        }
+    }
+
+    let stem = opts
+        .srclist
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    // The patch list is a single source containing every component.
+    let patch = Source {
+        name: ranked[0].name.clone(),
+        components: ranked
+            .iter()
+            .take(opts.num_patch)
+            .flat_map(|s| s.components.iter().cloned())
+            .collect(),
+    };
+    let patch_output = opts
+        .patch_output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{}_patch{}.txt", stem, opts.num_patch)));
+    write(&patch_output, &[patch])?;
+
+    let peel_output = opts
+        .peel_output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{}_peel{}.txt", stem, opts.num_peel)));
+    write(&peel_output, &ranked[..opts.num_peel.min(ranked.len())])?;
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure};
+use structopt::{clap::AppSettings, StructOpt};
+
+use mongoose::srclist::{parse_source_list, write_source_list, SourceListType};
+
+/// Convert a source list between the RTS, hyperdrive (YAML, also used by WODEN)
+/// and AO ("skymodel") formats.
+///
+/// Unless specified, the formats are guessed from the file extensions: .txt is
+/// RTS, .yaml or .yml is hyperdrive and .skymodel is AO. Flux densities are
+/// kept as lists, power laws or curved power laws where the output format
+/// allows; the RTS format only has lists, so power laws are written as flux
+/// densities at several frequencies. AO source lists can't have shapelets.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "srclist-convert", global_settings = &[AppSettings::ColoredHelp, AppSettings::ArgRequiredElseHelp])]
+struct Opts {
+    /// The source list to convert.
+    #[structopt(parse(from_str))]
+    input: PathBuf,
+
+    /// Where to write the converted source list.
+    #[structopt(parse(from_str))]
+    output: PathBuf,
+
+    /// The format of the input source list (rts, hyperdrive or ao).
+    #[structopt(short, long)]
+    input_type: Option<SourceListType>,
+
+    /// The format of the output source list (rts, hyperdrive or ao).
+    #[structopt(short, long)]
+    output_type: Option<SourceListType>,
+}
+
+/// Get the format of a source list, either as specified or from its extension.
+fn sl_type(
+    specified: Option<SourceListType>,
+    path: &Path,
+    arg: &str,
+) -> Result<SourceListType, anyhow::Error> {
+    match specified.or_else(|| SourceListType::from_path(path)) {
+        Some(t) => Ok(t),
+        None => bail!(
+            "Couldn't determine the format of {} from its extension; please specify {}",
+            path.display(),
+            arg
+        ),
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let opts = Opts::from_args();
+    ensure!(
+        opts.input.exists(),
+        "Specified source list file ({}) does not exist!",
+        opts.input.display()
+    );
+    let input_type = sl_type(opts.input_type, &opts.input, "--input-type")?;
+    let output_type = sl_type(opts.output_type, &opts.output, "--output-type")?;
+
+    let sources = match parse_source_list(&std::fs::read_to_string(&opts.input)?, input_type) {
+        Ok(s) => s,
+        Err(e) => bail!(
+            "{} is not a valid {} source list: {}",
+            opts.input.display(),
+            input_type,
+            e
+        ),
+    };
+
+    // Don't leave a partially-written file behind if a source can't be written
+    // in the output format.
+    let mut buf = vec![];
+    if let Err(e) = write_source_list(&mut buf, &sources, output_type) {
+        bail!("Couldn't write a {} source list: {}", output_type, e);
+    }
+    let mut f = BufWriter::new(File::create(&opts.output)?);
+    f.write_all(&buf)?;
+    f.flush()?;
+    println!(
+        "Converted {} sources to {}",
+        sources.len(),
+        opts.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,105 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to handle hour angle and declination coordinates.
+ */
+
+use std::f64::consts::PI;
+
+/// Hour angle and declination coordinates.
+#[derive(Debug)]
+pub struct HADec {
+    /// Hour angle [radians]
+    pub ha: f64,
+    /// Declination [radians]
+    pub dec: f64,
+}
+
+/// Azimuth and zenith angle coordinates.
+#[derive(Debug)]
+pub struct AzZA {
+    /// Azimuth, east of north [radians]
+    pub az: f64,
+    /// Zenith angle [radians]
+    pub za: f64,
+}
+
+impl HADec {
+    /// Get the hour angle and declination of an RA and Dec at a local sidereal
+    /// time. All units are radians.
+    pub fn from_radec(ra: f64, dec: f64, lst: f64) -> Self {
+        Self { ha: lst - ra, dec }
+    }
+
+    /// Convert to azimuth and zenith angle for an observer at a latitude
+    /// [radians]. Directions below the horizon have zenith angles bigger than
+    /// pi/2.
+    pub fn to_azza(&self, latitude: f64) -> AzZA {
+        let (s_ha, c_ha) = self.ha.sin_cos();
+        let (s_dec, c_dec) = self.dec.sin_cos();
+        let (s_lat, c_lat) = latitude.sin_cos();
+        // The components of the direction towards east, north and up.
+        let east = -c_dec * s_ha;
+        let north = s_dec * c_lat - c_dec * s_lat * c_ha;
+        let up = s_dec * s_lat + c_dec * c_lat * c_ha;
+        AzZA {
+            az: east.atan2(north).rem_euclid(2.0 * PI),
+            za: east.hypot(north).atan2(up),
+        }
+    }
+
+    /// Convert to azimuth and zenith angle for the MWA's latitude.
+    pub fn to_azza_mwa(&self) -> AzZA {
+        self.to_azza(mwalib::MWA_LATITUDE_RADIANS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    #[test]
+    fn test_to_azza() {
+        let lat = mwalib::MWA_LATITUDE_RADIANS;
+        // A source on the meridian at the observer's latitude is at zenith.
+        let azza = HADec { ha: 0.0, dec: lat }.to_azza(lat);
+        assert_abs_diff_eq!(azza.za, 0.0, epsilon = 1e-10);
+
+        // North and south of zenith on the meridian.
+        let azza = HADec {
+            ha: 0.0,
+            dec: lat + 10_f64.to_radians(),
+        }
+        .to_azza(lat);
+        assert_abs_diff_eq!(azza.az, 0.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(azza.za, 10_f64.to_radians(), epsilon = 1e-10);
+        let azza = HADec {
+            ha: 0.0,
+            dec: lat - 10_f64.to_radians(),
+        }
+        .to_azza(lat);
+        assert_abs_diff_eq!(azza.az, PI, epsilon = 1e-10);
+
+        // A source with a negative hour angle is rising in the east.
+        let azza = HADec::from_radec(0.5, lat, 0.4).to_azza(lat);
+        assert!(azza.az > 0.0 && azza.az < PI);
+        assert!(azza.za > 0.0);
+    }
+
+    #[test]
+    fn test_to_azza_mwa_pointing() {
+        // The pointing centre of 1065880128, from its metafits file.
+        let azza = HADec::from_radec(
+            3.166573685252408_f64.to_radians(),
+            -25.96194063106248_f64.to_radians(),
+            348.061732169994_f64.to_radians(),
+        )
+        .to_azza_mwa();
+        // The metafits az/alt are for the apparent position rather than J2000.
+        assert_abs_diff_eq!(azza.az.to_degrees(), 90.0, epsilon = 0.5);
+        assert_abs_diff_eq!(azza.za.to_degrees(), 90.0 - 76.2838, epsilon = 0.5);
+    }
+}
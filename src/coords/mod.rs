@@ -7,7 +7,9 @@
  */
 
 mod enh;
+mod hadec;
 mod xyz;
 
 pub use enh::*;
+pub use hadec::*;
 pub use xyz::*;
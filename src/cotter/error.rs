@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Error handling for cotter mwaf files.
+ */
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MwafError {
+    /// A header key couldn't be read.
+    #[error("{file}: Couldn't read header key {key}: {err}")]
+    Key {
+        file: PathBuf,
+        key: &'static str,
+        err: String,
+    },
+
+    /// The flags table doesn't have a row for every baseline of every scan.
+    #[error("{file}: Expected {expected} rows of flags ({num_scans} scans of {num_baselines} baselines), but got {rows}")]
+    WrongNumberOfRows {
+        file: PathBuf,
+        rows: usize,
+        num_scans: usize,
+        num_baselines: usize,
+        expected: usize,
+    },
+
+    /// The rows of the flags table aren't wide enough for the channels.
+    #[error(
+        "{file}: Expected rows of {expected} bytes for {num_channels} channels, but got {width}"
+    )]
+    WrongRowWidth {
+        file: PathBuf,
+        width: usize,
+        num_channels: usize,
+        expected: usize,
+    },
+
+    /// The flags to be written don't match the header.
+    #[error(
+        "Flags have shape {got:?}, but the header needs {expected:?} (scans, baselines, channels)"
+    )]
+    WrongFlagsShape {
+        got: (usize, usize, usize),
+        expected: (usize, usize, usize),
+    },
+
+    /// An error associated with fitsio.
+    #[error("{0}")]
+    Fitsio(#[from] fitsio::errors::Error),
+
+    /// An error when converting a Rust string to a C string.
+    #[error("{0}")]
+    BadString(#[from] std::ffi::NulError),
+
+    /// An IO error.
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+}
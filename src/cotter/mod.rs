@@ -0,0 +1,645 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to handle cotter mwaf (flag) files.
+ */
+
+pub mod error;
+mod mwaf;
+
+pub use mwaf::{baseline_to_antennas, pack_flags, unpack_flags, write_mwaf, MwafFile, MwafHeader};
+
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use fitsio::FitsFile;
+use ndarray::Axis;
+
+#[derive(Debug)]
+pub struct Occupancy {
+    /// The file that these statistics are derived from.
+    pub mwaf_file: PathBuf,
+    /// The observation ID of the mwaf file (GPSTIME).
+    pub obsid: u64,
+    /// The number of times a specific frequency channel was flagged.
+    pub flag_counts_per_channel: Vec<u32>,
+    /// The fraction of which a specific frequency channel was flagged.
+    pub flag_fraction_per_channel: Vec<f64>,
+    /// The total number of samples. This can be used to work out the occupancy
+    /// as a fraction.
+    pub total_samples_per_channel: u32,
+
+    /// The number of flags in each timestep (scan), over all baselines and
+    /// channels.
+    pub flag_counts_per_timestep: Vec<u32>,
+    /// The fraction of each timestep that was flagged.
+    pub flag_fraction_per_timestep: Vec<f64>,
+    /// The total number of samples in a timestep.
+    pub total_samples_per_timestep: u32,
+
+    /// The number of flags on each baseline, over all timesteps and channels.
+    /// Baselines are ordered as in the mwaf file (see `baseline_to_antennas`).
+    pub flag_counts_per_baseline: Vec<u32>,
+    /// The fraction of each baseline that was flagged.
+    pub flag_fraction_per_baseline: Vec<f64>,
+    /// The total number of samples on a baseline.
+    pub total_samples_per_baseline: u32,
+
+    /// The number of flags on the baselines of each antenna (tile), including
+    /// its auto-correlation.
+    pub flag_counts_per_antenna: Vec<u32>,
+    /// The fraction of each antenna's baselines that was flagged.
+    pub flag_fraction_per_antenna: Vec<f64>,
+    /// The total number of samples on an antenna's baselines.
+    pub total_samples_per_antenna: u32,
+}
+
+impl Occupancy {
+    pub fn new<T: AsRef<Path>>(mwaf_file: &T) -> Result<Self, anyhow::Error> {
+        Self::from_mwaf(&MwafFile::read(mwaf_file)?)
+    }
+
+    /// Get the occupancy of an mwaf file that has already been read.
+    pub fn from_mwaf(mwaf: &MwafFile) -> Result<Self, anyhow::Error> {
+        let (num_scans, num_baselines, num_chans) = mwaf.flags.dim();
+        let num_antennas = mwaf.header.num_antennas;
+
+        // Collapse the flags into total numbers of flags per channel, timestep
+        // and baseline in a single pass.
+        let mut chan_counts: Vec<u32> = vec![0; num_chans];
+        let mut timestep_counts: Vec<u32> = vec![0; num_scans];
+        let mut baseline_counts: Vec<u32> = vec![0; num_baselines];
+        for (t_count, scan_flags) in timestep_counts.iter_mut().zip(mwaf.flags.outer_iter()) {
+            for (b_count, bl_flags) in baseline_counts.iter_mut().zip(scan_flags.outer_iter()) {
+                for (c_count, &flag) in chan_counts.iter_mut().zip(bl_flags.iter()) {
+                    if flag {
+                        *c_count += 1;
+                        *t_count += 1;
+                        *b_count += 1;
+                    }
+                }
+            }
+        }
+
+        // Each antenna is in one baseline with every antenna, including
+        // itself.
+        let mut antenna_counts: Vec<u32> = vec![0; num_antennas];
+        for (bl, count) in baseline_counts.iter().enumerate() {
+            let (ant1, ant2) = match baseline_to_antennas(bl, num_antennas) {
+                Some(a) => a,
+                None => bail!(
+                    "{}: Baseline {} is out of range for {} antennas (NANTENNA)",
+                    mwaf.path.display(),
+                    bl,
+                    num_antennas
+                ),
+            };
+            antenna_counts[ant1] += count;
+            if ant1 != ant2 {
+                antenna_counts[ant2] += count;
+            }
+        }
+
+        // Now normalise the totals, so they can be analysed as fractions.
+        let fractions = |counts: &[u32], total_samples: u32| -> Vec<f64> {
+            counts
+                .iter()
+                .map(|c| *c as f64 / total_samples as f64)
+                .collect()
+        };
+        let total_samples_per_channel = (num_scans * num_baselines) as u32;
+        let total_samples_per_timestep = (num_baselines * num_chans) as u32;
+        let total_samples_per_baseline = (num_scans * num_chans) as u32;
+        let total_samples_per_antenna = (num_antennas * num_scans * num_chans) as u32;
+
+        Ok(Self {
+            mwaf_file: mwaf.path.canonicalize()?,
+            obsid: mwaf.header.gps_time,
+            flag_fraction_per_channel: fractions(&chan_counts, total_samples_per_channel),
+            flag_counts_per_channel: chan_counts,
+            total_samples_per_channel,
+            flag_fraction_per_timestep: fractions(&timestep_counts, total_samples_per_timestep),
+            flag_counts_per_timestep: timestep_counts,
+            total_samples_per_timestep,
+            flag_fraction_per_baseline: fractions(&baseline_counts, total_samples_per_baseline),
+            flag_counts_per_baseline: baseline_counts,
+            total_samples_per_baseline,
+            flag_fraction_per_antenna: fractions(&antenna_counts, total_samples_per_antenna),
+            flag_counts_per_antenna: antenna_counts,
+            total_samples_per_antenna,
+        })
+    }
+
+    /// Get the channels whose flag fraction exceeds `threshold`.
+    pub fn bad_channels(&self, threshold: f64) -> Vec<usize> {
+        above_threshold(&self.flag_fraction_per_channel, threshold)
+    }
+
+    /// Get the timesteps whose flag fraction exceeds `threshold`.
+    pub fn bad_timesteps(&self, threshold: f64) -> Vec<usize> {
+        above_threshold(&self.flag_fraction_per_timestep, threshold)
+    }
+
+    /// Get the antennas whose flag fraction exceeds `threshold`.
+    pub fn bad_antennas(&self, threshold: f64) -> Vec<usize> {
+        above_threshold(&self.flag_fraction_per_antenna, threshold)
+    }
+
+    /// Add header keys detailing which channels should be totally flagged.
+    /// Derived from an old "reflag_mwaf_files.py" script.
+    pub fn reflag<T: AsRef<Path>>(
+        &self,
+        new_mwaf_file: &T,
+        threshold: f64,
+    ) -> Result<(), anyhow::Error> {
+        self.reflag_channels(new_mwaf_file, &self.bad_channels(threshold))
+    }
+
+    // TODO: Error handling.
+    /// Copy the mwaf file to `new_mwaf_file`, adding header keys that tell the
+    /// RTS to totally flag `channels`.
+    pub fn reflag_channels<T: AsRef<Path>>(
+        &self,
+        new_mwaf_file: &T,
+        channels: &[usize],
+    ) -> Result<(), anyhow::Error> {
+        // Copy the original mwaf file to the new specified file.
+        std::fs::copy(&self.mwaf_file, &new_mwaf_file)?;
+        let mut rts_fits = FitsFile::edit(&new_mwaf_file)?;
+
+        // For every channel, write a new header key. I have no idea why it's
+        // done this way, but this is the way the old python script did it...
+        for (n_reflag, &c) in channels.iter().enumerate() {
+            rts_fits.hdu(1)?.write_key(
+                &mut rts_fits,
+                &format!("REFLG_{:02}", n_reflag),
+                c as u32,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How to choose the channels to flag in each coarse band of an observation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflagPolicy {
+    /// Flag channels whose flag fraction in a band exceeds this, in that band.
+    pub band_threshold: f64,
+
+    /// Flag channels in every band if they exceed `band_threshold` in more
+    /// than this fraction of the bands. If this is `None`, the bands are
+    /// treated independently.
+    pub global_threshold: Option<f64>,
+
+    /// The number of channels at each edge of every band to always flag.
+    pub num_edge_channels: usize,
+
+    /// Always flag the centre (DC) channel of every band.
+    pub flag_centre_channel: bool,
+}
+
+impl ReflagPolicy {
+    /// Get the channels to flag in each of the bands described by
+    /// `occupancies`. The bands must all have the same number of channels.
+    pub fn channels_to_flag(
+        &self,
+        occupancies: &[Occupancy],
+    ) -> Result<Vec<Vec<usize>>, anyhow::Error> {
+        let num_chans = match occupancies.first() {
+            Some(o) => o.flag_fraction_per_channel.len(),
+            None => return Ok(vec![]),
+        };
+        for occ in occupancies {
+            if occ.flag_fraction_per_channel.len() != num_chans {
+                bail!(
+                    "{} has {} channels, but {} has {}",
+                    occ.mwaf_file.display(),
+                    occ.flag_fraction_per_channel.len(),
+                    occupancies[0].mwaf_file.display(),
+                    num_chans
+                );
+            }
+        }
+
+        let per_band: Vec<Vec<usize>> = occupancies
+            .iter()
+            .map(|o| o.bad_channels(self.band_threshold))
+            .collect();
+
+        // Work out which channels get flagged in every band.
+        let mut everywhere = vec![];
+        let num_edge_channels = self.num_edge_channels.min(num_chans);
+        everywhere.extend(0..num_edge_channels);
+        everywhere.extend(num_chans - num_edge_channels..num_chans);
+        if self.flag_centre_channel {
+            everywhere.push(num_chans / 2);
+        }
+        if let Some(global_threshold) = self.global_threshold {
+            for c in 0..num_chans {
+                let num_bad = per_band.iter().filter(|b| b.contains(&c)).count();
+                if num_bad as f64 / occupancies.len() as f64 > global_threshold {
+                    everywhere.push(c);
+                }
+            }
+        }
+
+        Ok(per_band
+            .into_iter()
+            .map(|mut b| {
+                b.extend_from_slice(&everywhere);
+                b.sort_unstable();
+                b.dedup();
+                b
+            })
+            .collect())
+    }
+}
+
+/// Get the antennas whose flag fraction exceeds `threshold` when their flags
+/// are counted over all of the given occupancies, e.g. all of the coarse bands
+/// of an observation. All of the occupancies must have the same antennas.
+pub fn bad_antennas_overall(
+    occupancies: &[Occupancy],
+    threshold: f64,
+) -> Result<Vec<usize>, anyhow::Error> {
+    above_threshold_overall(occupancies, threshold, "antennas", |o| {
+        (&o.flag_counts_per_antenna, o.total_samples_per_antenna)
+    })
+}
+
+/// Get the timesteps whose flag fraction exceeds `threshold` when their flags
+/// are counted over all of the given occupancies, e.g. all of the coarse bands
+/// of an observation. All of the occupancies must have the same timesteps.
+pub fn bad_timesteps_overall(
+    occupancies: &[Occupancy],
+    threshold: f64,
+) -> Result<Vec<usize>, anyhow::Error> {
+    above_threshold_overall(occupancies, threshold, "timesteps", |o| {
+        (&o.flag_counts_per_timestep, o.total_samples_per_timestep)
+    })
+}
+
+/// Get the indices whose flag fraction exceeds `threshold` when the flag counts
+/// that `breakdown` gets from each occupancy are summed. `what` names the
+/// indices in errors.
+fn above_threshold_overall<F: Fn(&Occupancy) -> (&[u32], u32)>(
+    occupancies: &[Occupancy],
+    threshold: f64,
+    what: &str,
+    breakdown: F,
+) -> Result<Vec<usize>, anyhow::Error> {
+    let num = match occupancies.first() {
+        Some(o) => breakdown(o).0.len(),
+        None => return Ok(vec![]),
+    };
+    let mut counts: Vec<u64> = vec![0; num];
+    let mut total_samples: u64 = 0;
+    for occ in occupancies {
+        let (occ_counts, occ_total_samples) = breakdown(occ);
+        if occ_counts.len() != num {
+            bail!(
+                "{} has {} {}, but {} has {}",
+                occ.mwaf_file.display(),
+                occ_counts.len(),
+                what,
+                occupancies[0].mwaf_file.display(),
+                num
+            );
+        }
+        for (total, &c) in counts.iter_mut().zip(occ_counts.iter()) {
+            *total += c as u64;
+        }
+        total_samples += occ_total_samples as u64;
+    }
+
+    let fractions: Vec<f64> = counts
+        .iter()
+        .map(|&c| c as f64 / total_samples as f64)
+        .collect();
+    Ok(above_threshold(&fractions, threshold))
+}
+
+/// Flag every baseline and channel of `timesteps` in an mwaf file, in place.
+/// Any other header cards (e.g. REFLG keys) are kept.
+pub fn flag_timesteps<T: AsRef<Path>>(
+    mwaf_file: &T,
+    timesteps: &[usize],
+) -> Result<(), anyhow::Error> {
+    let mut mwaf = MwafFile::read(mwaf_file)?;
+    for &t in timesteps {
+        if t >= mwaf.header.num_scans {
+            bail!(
+                "{}: Can't flag timestep {}; there are only {} (NSCANS)",
+                mwaf.path.display(),
+                t,
+                mwaf.header.num_scans
+            );
+        }
+        mwaf.flags.index_axis_mut(Axis(0), t).fill(true);
+    }
+    mwaf.write(mwaf_file)?;
+    Ok(())
+}
+
+/// Get the indices of the fractions that exceed `threshold`.
+fn above_threshold(fractions: &[f64], threshold: f64) -> Vec<usize> {
+    fractions
+        .iter()
+        .enumerate()
+        .filter(|(_, &f)| f > threshold)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mwalib::*; // For fits-reading macros.
+
+    #[test]
+    fn test_1065880128() {
+        // The mwaf file is zipped to save space in git. Unzip it to a temporary spot.
+        let mwaf = mwaf::tests::mwaf_1065880128();
+
+        let result = Occupancy::new(&mwaf);
+        assert!(result.is_ok());
+        let occ = result.unwrap();
+
+        let expected = vec![
+            1849343, 1849343, 155462, 152424, 150517, 149608, 149075, 149136, 149204, 149260,
+            149317, 149354, 149279, 149515, 149632, 149908, 1849343, 149780, 149466, 149242,
+            149163, 148877, 148873, 148811, 148693, 148713, 148771, 149406, 150996, 152602,
+            1849343, 1849343,
+        ];
+        for (res, exp) in occ.flag_counts_per_channel.iter().zip(expected.iter()) {
+            assert_eq!(res, exp);
+        }
+
+        // The other breakdowns count the same flags.
+        assert_eq!(occ.flag_counts_per_timestep.len(), 224);
+        assert_eq!(occ.flag_counts_per_baseline.len(), 8256);
+        assert_eq!(occ.flag_counts_per_antenna.len(), 128);
+        assert_eq!(occ.total_samples_per_antenna, 128 * 224 * 32);
+        let total: u32 = expected.iter().sum();
+        assert_eq!(occ.flag_counts_per_timestep.iter().sum::<u32>(), total);
+        assert_eq!(occ.flag_counts_per_baseline.iter().sum::<u32>(), total);
+        // Cross-correlations count towards both of their antennas.
+        let autos: u32 = (0..128)
+            .map(|a| occ.flag_counts_per_baseline[a * (2 * 128 - a + 1) / 2])
+            .sum();
+        assert_eq!(
+            occ.flag_counts_per_antenna.iter().sum::<u32>(),
+            2 * total - autos
+        );
+        // Tiles 82 and 123 and the last 7 timesteps are completely flagged.
+        assert_eq!(occ.bad_antennas(0.5), vec![82, 123]);
+        assert_eq!(occ.bad_timesteps(0.5), (217..224).collect::<Vec<_>>());
+
+        // "Reflag" the mwaf file in a new temp file.
+        let reflagged_mwaf = tempfile::NamedTempFile::new().unwrap();
+        occ.reflag(&reflagged_mwaf, 0.8).unwrap();
+
+        // Ensure that the edge and centre channels got picked up.
+        let mut f = fits_open!(&reflagged_mwaf).unwrap();
+        let hdu = fits_open_hdu!(&mut f, 1).unwrap();
+        // The keys need a type. serde_yaml implements PartialEq<Value> for the
+        // integer types, so comparing an untyped key with an integer literal
+        // is ambiguous.
+        let mut reflg = |key: &str| -> u32 { get_required_fits_key!(&mut f, &hdu, key).unwrap() };
+        assert_eq!(0, reflg("REFLG_00"));
+        assert_eq!(1, reflg("REFLG_01"));
+        assert_eq!(16, reflg("REFLG_02"));
+        assert_eq!(30, reflg("REFLG_03"));
+        assert_eq!(31, reflg("REFLG_04"));
+        // The REFLG_05 key shouldn't exist.
+        let reflg_05: Result<u32, _> = get_required_fits_key!(&mut f, &hdu, "REFLG_05");
+        assert!(reflg_05.is_err());
+    }
+
+    /// Make an occupancy with only per-channel statistics.
+    fn channel_occupancy(fractions: &[f64]) -> Occupancy {
+        Occupancy {
+            mwaf_file: PathBuf::from("1065880128_01.mwaf"),
+            obsid: 1065880128,
+            flag_counts_per_channel: fractions.iter().map(|f| (f * 100.0) as u32).collect(),
+            flag_fraction_per_channel: fractions.to_vec(),
+            total_samples_per_channel: 100,
+            flag_counts_per_timestep: vec![],
+            flag_fraction_per_timestep: vec![],
+            total_samples_per_timestep: 0,
+            flag_counts_per_baseline: vec![],
+            flag_fraction_per_baseline: vec![],
+            total_samples_per_baseline: 0,
+            flag_counts_per_antenna: vec![],
+            flag_fraction_per_antenna: vec![],
+            total_samples_per_antenna: 0,
+        }
+    }
+
+    #[test]
+    fn test_reflag_policy() {
+        // Channel 3 is bad in 3 of 4 bands, and channel 5 in only 1.
+        let bands = vec![
+            channel_occupancy(&[0.1, 0.1, 0.1, 0.9, 0.1, 0.1, 0.1, 0.1]),
+            channel_occupancy(&[0.1, 0.1, 0.1, 0.9, 0.1, 0.9, 0.1, 0.1]),
+            channel_occupancy(&[0.1, 0.1, 0.1, 0.9, 0.1, 0.1, 0.1, 0.1]),
+            channel_occupancy(&[0.1; 8]),
+        ];
+        let mut policy = ReflagPolicy {
+            band_threshold: 0.8,
+            global_threshold: None,
+            num_edge_channels: 0,
+            flag_centre_channel: false,
+        };
+        assert_eq!(
+            policy.channels_to_flag(&bands).unwrap(),
+            vec![vec![3], vec![3, 5], vec![3], vec![]]
+        );
+
+        policy.global_threshold = Some(0.5);
+        assert_eq!(
+            policy.channels_to_flag(&bands).unwrap(),
+            vec![vec![3], vec![3, 5], vec![3], vec![3]]
+        );
+        policy.global_threshold = Some(0.8);
+        assert_eq!(
+            policy.channels_to_flag(&bands).unwrap(),
+            vec![vec![3], vec![3, 5], vec![3], vec![]]
+        );
+
+        policy.global_threshold = Some(0.5);
+        policy.num_edge_channels = 1;
+        policy.flag_centre_channel = true;
+        assert_eq!(
+            policy.channels_to_flag(&bands).unwrap(),
+            vec![
+                vec![0, 3, 4, 7],
+                vec![0, 3, 4, 5, 7],
+                vec![0, 3, 4, 7],
+                vec![0, 3, 4, 7]
+            ]
+        );
+
+        // Too many edge channels flags everything.
+        policy.num_edge_channels = 10;
+        assert_eq!(
+            policy.channels_to_flag(&bands[..1]).unwrap(),
+            vec![(0..8).collect::<Vec<_>>()]
+        );
+
+        assert!(policy.channels_to_flag(&[]).unwrap().is_empty());
+        let mismatched = vec![channel_occupancy(&[0.1; 8]), channel_occupancy(&[0.1; 4])];
+        assert!(policy.channels_to_flag(&mismatched).is_err());
+    }
+
+    #[test]
+    fn test_too_many_baselines() {
+        // 2 antennas only have 3 baselines, but there are 4.
+        let mwaf = MwafFile {
+            path: PathBuf::from("1065880128_01.mwaf"),
+            header: MwafHeader {
+                version: "1.0".to_string(),
+                gps_time: 1065880128,
+                num_channels: 4,
+                num_antennas: 2,
+                num_scans: 2,
+                num_pols: 1,
+                gpubox_number: 1,
+                cotter_version: None,
+                cotter_version_date: None,
+            },
+            flags: ndarray::Array3::from_elem((2, 4, 4), false),
+            other_primary_cards: vec![],
+            other_table_cards: vec![],
+        };
+        let err = Occupancy::from_mwaf(&mwaf).unwrap_err().to_string();
+        assert!(
+            err.contains("Baseline 3 is out of range for 2 antennas"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_breakdowns() {
+        // 3 antennas, so baselines are (0, 0), (0, 1), (0, 2), (1, 1), (1, 2),
+        // (2, 2).
+        let header = MwafHeader {
+            version: "1.0".to_string(),
+            gps_time: 1065880128,
+            num_channels: 4,
+            num_antennas: 3,
+            num_scans: 2,
+            num_pols: 1,
+            gpubox_number: 1,
+            cotter_version: None,
+            cotter_version_date: None,
+        };
+        // Flag all of antenna 2 in the first timestep, and one sample of (0,
+        // 1) in the second.
+        let mut flags = ndarray::Array3::from_elem(header.flags_shape(), false);
+        for &bl in &[2, 4, 5] {
+            flags.slice_mut(ndarray::s![0, bl, ..]).fill(true);
+        }
+        flags[[1, 1, 0]] = true;
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("1065880128_01.mwaf");
+        write_mwaf(&file, &header, flags.view()).unwrap();
+
+        let occ = Occupancy::new(&file).unwrap();
+        assert_eq!(occ.flag_counts_per_channel, vec![4, 3, 3, 3]);
+        assert_eq!(occ.total_samples_per_channel, 12);
+        assert_eq!(occ.flag_counts_per_timestep, vec![12, 1]);
+        assert_eq!(occ.total_samples_per_timestep, 24);
+        assert_eq!(occ.flag_fraction_per_timestep, vec![0.5, 1.0 / 24.0]);
+        assert_eq!(occ.flag_counts_per_baseline, vec![0, 1, 4, 0, 4, 4]);
+        assert_eq!(occ.total_samples_per_baseline, 8);
+        assert_eq!(occ.flag_counts_per_antenna, vec![5, 5, 12]);
+        assert_eq!(occ.total_samples_per_antenna, 24);
+
+        assert_eq!(occ.bad_timesteps(0.4), vec![0]);
+        assert_eq!(occ.bad_antennas(0.4), vec![2]);
+        assert_eq!(occ.bad_antennas(0.1), vec![0, 1, 2]);
+        assert!(occ.bad_antennas(0.5).is_empty());
+
+        // Over several bands, the flags of each band count equally. Antenna 2
+        // is half-flagged in this band and clean in another, so its overall
+        // flag fraction is 0.25.
+        let clean_file = dir.path().join("1065880128_02.mwaf");
+        let clean = ndarray::Array3::from_elem(header.flags_shape(), false);
+        write_mwaf(&clean_file, &header, clean.view()).unwrap();
+        let clean = Occupancy::new(&clean_file).unwrap();
+        let bad = bad_antennas_overall(&[occ, clean], 0.2).unwrap();
+        assert_eq!(bad, vec![2]);
+        let occ = Occupancy::new(&file).unwrap();
+        let clean = Occupancy::new(&clean_file).unwrap();
+        let bad = bad_antennas_overall(&[occ, clean], 0.3).unwrap();
+        assert!(bad.is_empty());
+        assert!(bad_antennas_overall(&[], 0.3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bad_timesteps_overall() {
+        // The second timestep is totally flagged in one band, and half-flagged
+        // in another.
+        let band = |counts: Vec<u32>| {
+            let mut occ = channel_occupancy(&[0.1; 8]);
+            occ.flag_fraction_per_timestep = counts.iter().map(|&c| c as f64 / 10.0).collect();
+            occ.flag_counts_per_timestep = counts;
+            occ.total_samples_per_timestep = 10;
+            occ
+        };
+        let bands = vec![band(vec![0, 10, 1]), band(vec![0, 5, 1]), band(vec![0; 3])];
+        assert_eq!(bad_timesteps_overall(&bands, 0.4).unwrap(), vec![1]);
+        assert!(bad_timesteps_overall(&bands, 0.5).unwrap().is_empty());
+        assert_eq!(
+            bad_timesteps_overall(&bands[..1], 0.05).unwrap(),
+            vec![1, 2]
+        );
+        assert!(bad_timesteps_overall(&[], 0.5).unwrap().is_empty());
+
+        let mismatched = vec![band(vec![0; 3]), band(vec![0; 2])];
+        let err = bad_timesteps_overall(&mismatched, 0.5)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("has 2 timesteps"), "{}", err);
+    }
+
+    #[test]
+    fn test_flag_timesteps() {
+        let header = MwafHeader {
+            version: "1.0".to_string(),
+            gps_time: 1065880128,
+            num_channels: 4,
+            num_antennas: 3,
+            num_scans: 3,
+            num_pols: 1,
+            gpubox_number: 1,
+            cotter_version: None,
+            cotter_version_date: None,
+        };
+        let mut flags = ndarray::Array3::from_elem(header.flags_shape(), false);
+        flags[[0, 1, 2]] = true;
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("1065880128_01.mwaf");
+        write_mwaf(&original, &header, flags.view()).unwrap();
+        let file = dir.path().join("RTS_1065880128_01.mwaf");
+        let occ = Occupancy::new(&original).unwrap();
+        occ.reflag_channels(&file, &[3]).unwrap();
+
+        flag_timesteps(&file, &[2]).unwrap();
+        let mwaf = MwafFile::read(&file).unwrap();
+        flags.index_axis_mut(Axis(0), 2).fill(true);
+        assert_eq!(mwaf.flags, flags);
+        // The REFLG keys are kept.
+        assert!(mwaf
+            .other_table_cards
+            .iter()
+            .any(|c| c.starts_with("REFLG_00")));
+
+        assert!(flag_timesteps(&file, &[3]).is_err());
+    }
+}
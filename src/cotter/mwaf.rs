@@ -0,0 +1,675 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to read and write cotter mwaf files.
+ *
+ * An mwaf file holds the flags of one coarse channel (gpubox file) of an
+ * observation. Its primary HDU has only a header; the flags are in a binary
+ * table with a row per baseline per scan (timestep), ordered by scan, then
+ * baseline. Baselines include auto-correlations, and are ordered (0, 0),
+ * (0, 1), ..., (0, N-1), (1, 1), etc. Each row has a bit per fine channel,
+ * packed into bytes with the first channel in the most significant bit.
+ */
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+
+use fitsio::{
+    errors::check_status as fits_check_status, hdu::FitsHdu, headers::ReadsKey, FileOpenMode,
+    FitsFile,
+};
+use ndarray::{Array3, ArrayView3};
+
+use super::error::MwafError;
+
+/// The keywords of the primary HDU that are read into an `MwafHeader`, or are
+/// written by cfitsio.
+const PRIMARY_KEYWORDS: [&str; 13] = [
+    "SIMPLE", "BITPIX", "NAXIS", "EXTEND", "VERSION", "GPSTIME", "NCHANS", "NANTENNA", "NSCANS",
+    "NPOLS", "GPUBOXNO", "COTVER", "COTVDATE",
+];
+
+/// The keywords of the flags table that describe its layout.
+const TABLE_KEYWORDS: [&str; 12] = [
+    "XTENSION", "BITPIX", "NAXIS", "NAXIS1", "NAXIS2", "PCOUNT", "GCOUNT", "TFIELDS", "TTYPE1",
+    "TFORM1", "TUNIT1", "EXTNAME",
+];
+
+/// The header of an mwaf file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MwafHeader {
+    /// The version of the mwaf format (VERSION).
+    pub version: String,
+
+    /// The observation ID (GPSTIME).
+    pub gps_time: u64,
+
+    /// The number of fine channels (NCHANS).
+    pub num_channels: usize,
+
+    /// The number of antennas (tiles) (NANTENNA).
+    pub num_antennas: usize,
+
+    /// The number of scans (timesteps) (NSCANS).
+    pub num_scans: usize,
+
+    /// The number of polarisations that the flags apply to (NPOLS).
+    pub num_pols: usize,
+
+    /// The number of the gpubox file that the flags are for (GPUBOXNO).
+    pub gpubox_number: usize,
+
+    /// The version of cotter that wrote the file (COTVER), if available.
+    pub cotter_version: Option<String>,
+
+    /// The date of the cotter version (COTVDATE), if available.
+    pub cotter_version_date: Option<String>,
+}
+
+impl MwafHeader {
+    /// The number of baselines per scan, including auto-correlations.
+    pub fn num_baselines(&self) -> usize {
+        self.num_antennas * (self.num_antennas + 1) / 2
+    }
+
+    /// The number of bytes needed for the flags of a single baseline and scan.
+    pub fn row_width(&self) -> usize {
+        (self.num_channels + 7) / 8
+    }
+
+    /// The shape that flags must have to go with this header; (scans,
+    /// baselines, channels).
+    pub fn flags_shape(&self) -> (usize, usize, usize) {
+        (self.num_scans, self.num_baselines(), self.num_channels)
+    }
+}
+
+/// An mwaf file, read into memory.
+#[derive(Clone, Debug)]
+pub struct MwafFile {
+    /// The file that was read.
+    pub path: PathBuf,
+
+    pub header: MwafHeader,
+
+    /// The flags, indexed by scan (timestep), baseline and fine channel. `true`
+    /// means flagged.
+    pub flags: Array3<bool>,
+
+    /// The header cards of the primary HDU that aren't part of `header` (e.g.
+    /// comments and keys added by other programs). They're written back as
+    /// they are.
+    pub other_primary_cards: Vec<String>,
+
+    /// The header cards of the flags table that don't describe its layout, e.g.
+    /// the REFLG_xx keys that tell the RTS which channels to flag. They're
+    /// written back as they are.
+    pub other_table_cards: Vec<String>,
+}
+
+impl MwafFile {
+    /// Read an mwaf file.
+    pub fn read<T: AsRef<Path>>(mwaf_file: &T) -> Result<Self, MwafError> {
+        let path = mwaf_file.as_ref();
+        let mut fptr = open_disk_file(path, false)?;
+        let hdu = fptr.primary_hdu()?;
+        let header = MwafHeader {
+            version: read_key(&mut fptr, &hdu, path, "VERSION")?,
+            gps_time: read_key::<i64>(&mut fptr, &hdu, path, "GPSTIME")? as u64,
+            num_channels: read_key::<i64>(&mut fptr, &hdu, path, "NCHANS")? as usize,
+            num_antennas: read_key::<i64>(&mut fptr, &hdu, path, "NANTENNA")? as usize,
+            num_scans: read_key::<i64>(&mut fptr, &hdu, path, "NSCANS")? as usize,
+            num_pols: read_key::<i64>(&mut fptr, &hdu, path, "NPOLS")? as usize,
+            gpubox_number: read_key::<i64>(&mut fptr, &hdu, path, "GPUBOXNO")? as usize,
+            cotter_version: hdu.read_key(&mut fptr, "COTVER").ok(),
+            cotter_version_date: hdu.read_key(&mut fptr, "COTVDATE").ok(),
+        };
+        let other_primary_cards = read_other_cards(&mut fptr, &PRIMARY_KEYWORDS)?;
+
+        let hdu = fptr.hdu(1)?;
+        let width = read_key::<i64>(&mut fptr, &hdu, path, "NAXIS1")? as usize;
+        let rows = read_key::<i64>(&mut fptr, &hdu, path, "NAXIS2")? as usize;
+        if width != header.row_width() {
+            return Err(MwafError::WrongRowWidth {
+                file: path.to_path_buf(),
+                width,
+                num_channels: header.num_channels,
+                expected: header.row_width(),
+            });
+        }
+        if rows != header.num_scans * header.num_baselines() {
+            return Err(MwafError::WrongNumberOfRows {
+                file: path.to_path_buf(),
+                rows,
+                num_scans: header.num_scans,
+                num_baselines: header.num_baselines(),
+                expected: header.num_scans * header.num_baselines(),
+            });
+        }
+
+        // Get the packed flags out of the binary table. I think reading bit
+        // columns is currently bugged in the rust-fitsio crate, so call cfitsio
+        // directly.
+        let mut packed: Vec<u8> = vec![0; rows * width];
+        let mut anynul = 0;
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffgcvb(
+                fptr.as_raw(),       /* I - FITS file pointer                       */
+                1,                   /* I - number of column to read (1 = 1st col)  */
+                1,                   /* I - first row to read (1 = 1st row)         */
+                1,                   /* I - first vector element to read (1 = 1st)  */
+                packed.len() as i64, /* I - number of values to read                */
+                0,                   /* I - value for null pixels                   */
+                packed.as_mut_ptr(), /* O - array of values that are read           */
+                &mut anynul,         /* O - set to 1 if any values are null; else 0 */
+                &mut status,         /* IO - error status                           */
+            );
+        }
+        fits_check_status(status)?;
+        let other_table_cards = read_other_cards(&mut fptr, &TABLE_KEYWORDS)?;
+
+        let flags = unpack_flags(
+            &packed,
+            header.num_scans,
+            header.num_baselines(),
+            header.num_channels,
+        );
+        Ok(Self {
+            path: path.to_path_buf(),
+            header,
+            flags,
+            other_primary_cards,
+            other_table_cards,
+        })
+    }
+
+    /// Write this mwaf file's header, other header cards and flags to a new
+    /// file. `path` doesn't have to be the file that was read, so flags can be
+    /// read, edited and written elsewhere.
+    pub fn write<T: AsRef<Path>>(&self, path: &T) -> Result<(), MwafError> {
+        write_mwaf_with_cards(
+            path.as_ref(),
+            &self.header,
+            self.flags.view(),
+            &self.other_primary_cards,
+            &self.other_table_cards,
+        )
+    }
+}
+
+/// Write flags to a new mwaf file, with the same layout that cotter uses. The
+/// flags are indexed by scan (timestep), baseline and fine channel, and their
+/// shape must match the header (see `MwafHeader::flags_shape`). Any existing
+/// file at `path` is overwritten.
+pub fn write_mwaf<T: AsRef<Path>>(
+    path: &T,
+    header: &MwafHeader,
+    flags: ArrayView3<bool>,
+) -> Result<(), MwafError> {
+    write_mwaf_with_cards(path.as_ref(), header, flags, &[], &[])
+}
+
+/// Write an mwaf file, adding header cards to the primary HDU and the flags
+/// table.
+fn write_mwaf_with_cards(
+    path: &Path,
+    header: &MwafHeader,
+    flags: ArrayView3<bool>,
+    primary_cards: &[String],
+    table_cards: &[String],
+) -> Result<(), MwafError> {
+    if flags.dim() != header.flags_shape() {
+        return Err(MwafError::WrongFlagsShape {
+            got: flags.dim(),
+            expected: header.flags_shape(),
+        });
+    }
+
+    // Delete any file that already exists.
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+
+    // Create a new fits file with an empty primary HDU.
+    let mut status = 0;
+    let mut fptr = open_disk_file(path, true)?;
+    unsafe {
+        fitsio_sys::ffphpr(
+            fptr.as_raw(),        /* I - FITS file pointer                        */
+            1,                    /* I - does file conform to FITS standard? 1/0  */
+            8,                    /* I - number of bits per data value pixel      */
+            0,                    /* I - number of axes in the data array         */
+            std::ptr::null_mut(), /* I - length of each data axis                 */
+            0,                    /* I - number of group parameters (usually 0)   */
+            1,                    /* I - number of random groups (usually 1 or 0) */
+            1,                    /* I - may FITS file have extensions?           */
+            &mut status,          /* IO - error status                            */
+        );
+    }
+    fits_check_status(status)?;
+
+    // Write the header keys in the same order as cotter.
+    let hdu = fptr.primary_hdu()?;
+    hdu.write_key(&mut fptr, "VERSION", header.version.as_str())?;
+    hdu.write_key(&mut fptr, "GPSTIME", header.gps_time as i64)?;
+    hdu.write_key(&mut fptr, "NCHANS", header.num_channels as i64)?;
+    hdu.write_key(&mut fptr, "NANTENNA", header.num_antennas as i64)?;
+    hdu.write_key(&mut fptr, "NSCANS", header.num_scans as i64)?;
+    hdu.write_key(&mut fptr, "NPOLS", header.num_pols as i64)?;
+    hdu.write_key(&mut fptr, "GPUBOXNO", header.gpubox_number as i64)?;
+    if let Some(v) = &header.cotter_version {
+        hdu.write_key(&mut fptr, "COTVER", v.as_str())?;
+    }
+    if let Some(d) = &header.cotter_version_date {
+        hdu.write_key(&mut fptr, "COTVDATE", d.as_str())?;
+    }
+    write_cards(&mut fptr, primary_cards)?;
+
+    // The flags go in a binary table with a single bit column. BINARY_TBL is
+    // 2.
+    let num_rows = header.num_scans * header.num_baselines();
+    let mut c_col_names = [CString::new("FLAGS")?.into_raw()];
+    let mut c_col_formats = [CString::new(format!("{}X", header.num_channels))?.into_raw()];
+    let mut c_col_units = [CString::new("")?.into_raw()];
+    unsafe {
+        fitsio_sys::ffcrtb(
+            fptr.as_raw(),              /* I - FITS file pointer                        */
+            2,                          /* I - type of table to create                  */
+            num_rows as i64,            /* I - number of rows in the table              */
+            1,                          /* I - number of columns in the table           */
+            c_col_names.as_mut_ptr(),   /* I - name of each column                      */
+            c_col_formats.as_mut_ptr(), /* I - value of TFORMn keyword for each column  */
+            c_col_units.as_mut_ptr(),   /* I - value of TUNITn keyword for each column  */
+            std::ptr::null(),           /* I - value of EXTNAME keyword, if any         */
+            &mut status,                /* IO - error status                            */
+        );
+        // Give the strings back to Rust so they're freed.
+        for p in c_col_names
+            .iter()
+            .chain(c_col_formats.iter())
+            .chain(c_col_units.iter())
+        {
+            drop(CString::from_raw(*p));
+        }
+    }
+    fits_check_status(status)?;
+
+    // As with reading, write the packed bytes with cfitsio directly.
+    let mut packed = pack_flags(flags);
+    unsafe {
+        fitsio_sys::ffpclb(
+            fptr.as_raw(),       /* I - FITS file pointer                       */
+            1,                   /* I - number of column to write (1 = 1st col) */
+            1,                   /* I - first row to write (1 = 1st row)        */
+            1,                   /* I - first vector element to write (1 = 1st) */
+            packed.len() as i64, /* I - number of values to write               */
+            packed.as_mut_ptr(), /* I - array of values to write                */
+            &mut status,         /* IO - error status                           */
+        );
+    }
+    fits_check_status(status)?;
+    write_cards(&mut fptr, table_cards)?;
+
+    Ok(())
+}
+
+/// Open (or create) a FITS file with cfitsio's "disk file" routines, which use
+/// the path as it is. The other routines treat the path as an extended file
+/// name, where e.g. a leading "!" or a "[" mean something else. The file is
+/// closed when the returned `FitsFile` is dropped.
+fn open_disk_file(path: &Path, create: bool) -> Result<FitsFile, MwafError> {
+    let c_filename = CString::new(path.to_string_lossy().as_bytes())?;
+    let mut fptr = std::ptr::null_mut();
+    let mut status = 0;
+    unsafe {
+        if create {
+            fitsio_sys::ffdkinit(
+                &mut fptr as *mut *mut _, /* O - FITS file pointer                   */
+                c_filename.as_ptr(),      /* I - name of file to create              */
+                &mut status,              /* IO - error status                       */
+            );
+        } else {
+            fitsio_sys::ffdkopn(
+                &mut fptr as *mut *mut _, /* O - FITS file pointer                   */
+                c_filename.as_ptr(),      /* I - name of file to open                */
+                0,                        /* I - 0 = open readonly; 1 = read/write   */
+                &mut status,              /* IO - error status                       */
+            );
+        }
+    }
+    fits_check_status(status)?;
+    let mode = if create {
+        FileOpenMode::READWRITE
+    } else {
+        FileOpenMode::READONLY
+    };
+    Ok(unsafe { FitsFile::from_raw(fptr, mode)? })
+}
+
+/// Read the header cards of the current HDU whose keywords aren't in
+/// `keywords`.
+fn read_other_cards(fptr: &mut FitsFile, keywords: &[&str]) -> Result<Vec<String>, MwafError> {
+    let mut num_cards = 0;
+    let mut num_more = 0;
+    let mut status = 0;
+    unsafe {
+        fitsio_sys::ffghsp(
+            fptr.as_raw(),  /* I - FITS file pointer                     */
+            &mut num_cards, /* O - number of existing keywords in header */
+            &mut num_more,  /* O - how many more keywords will fit       */
+            &mut status,    /* IO - error status                         */
+        );
+    }
+    fits_check_status(status)?;
+
+    let mut cards = vec![];
+    // A card is 80 characters, plus a null terminator.
+    let mut card: [c_char; 81] = [0; 81];
+    for i in 1..=num_cards {
+        unsafe {
+            fitsio_sys::ffgrec(
+                fptr.as_raw(),     /* I - FITS file pointer             */
+                i,                 /* I - number of the keyword to read */
+                card.as_mut_ptr(), /* O - the card                      */
+                &mut status,       /* IO - error status                 */
+            );
+        }
+        fits_check_status(status)?;
+        let card = unsafe { CStr::from_ptr(card.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+        let keyword = card.get(..8).unwrap_or(&card).trim_end();
+        if !keywords.contains(&keyword) {
+            cards.push(card);
+        }
+    }
+    Ok(cards)
+}
+
+/// Append header cards to the current HDU. Cards that are already in the
+/// header (e.g. the comments that cfitsio writes) are skipped.
+fn write_cards(fptr: &mut FitsFile, cards: &[String]) -> Result<(), MwafError> {
+    let existing = read_other_cards(fptr, &[])?;
+    let mut status = 0;
+    for card in cards.iter().filter(|c| !existing.contains(c)) {
+        let c_card = CString::new(card.as_str())?;
+        unsafe {
+            fitsio_sys::ffprec(
+                fptr.as_raw(),   /* I - FITS file pointer        */
+                c_card.as_ptr(), /* I - the card to append       */
+                &mut status,     /* IO - error status            */
+            );
+        }
+        fits_check_status(status)?;
+    }
+    Ok(())
+}
+
+/// Read a required header key, saying which key it was if that fails.
+fn read_key<T: ReadsKey>(
+    fptr: &mut FitsFile,
+    hdu: &FitsHdu,
+    file: &Path,
+    key: &'static str,
+) -> Result<T, MwafError> {
+    hdu.read_key(fptr, key).map_err(|e| MwafError::Key {
+        file: file.to_path_buf(),
+        key,
+        err: e.to_string(),
+    })
+}
+
+/// Unpack the bits of mwaf flags into an array indexed by scan, baseline and
+/// channel. Each row of `packed` is the flags of a single baseline and scan,
+/// with the first channel in the most significant bit of the first byte.
+pub fn unpack_flags(
+    packed: &[u8],
+    num_scans: usize,
+    num_baselines: usize,
+    num_channels: usize,
+) -> Array3<bool> {
+    let width = (num_channels + 7) / 8;
+    Array3::from_shape_fn((num_scans, num_baselines, num_channels), |(s, b, c)| {
+        packed[(s * num_baselines + b) * width + c / 8] & (0x80 >> (c % 8)) != 0
+    })
+}
+
+/// Pack flags indexed by scan, baseline and channel into the bits of mwaf
+/// rows; the inverse of `unpack_flags`. Unused bits at the end of each row are
+/// zero.
+pub fn pack_flags(flags: ArrayView3<bool>) -> Vec<u8> {
+    let (num_scans, num_baselines, num_channels) = flags.dim();
+    let width = (num_channels + 7) / 8;
+    let mut packed = vec![0; num_scans * num_baselines * width];
+    for ((s, b, c), &f) in flags.indexed_iter() {
+        if f {
+            packed[(s * num_baselines + b) * width + c / 8] |= 0x80 >> (c % 8);
+        }
+    }
+    packed
+}
+
+/// Get the antennas of a baseline index, in the order used by mwaf files.
+/// `None` is returned if the baseline is out of range for the number of
+/// antennas.
+pub fn baseline_to_antennas(baseline: usize, num_antennas: usize) -> Option<(usize, usize)> {
+    let mut first_baseline = 0;
+    for ant1 in 0..num_antennas {
+        let num_baselines = num_antennas - ant1;
+        if baseline < first_baseline + num_baselines {
+            return Some((ant1, ant1 + baseline - first_baseline));
+        }
+        first_baseline += num_baselines;
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Unzip the mwaf file of the first coarse channel of 1065880128.
+    pub(crate) fn mwaf_1065880128() -> tempfile::NamedTempFile {
+        let mut mwaf = tempfile::NamedTempFile::new().unwrap();
+        let mut z =
+            zip::ZipArchive::new(std::fs::File::open("tests/1065880128_01.mwaf.zip").unwrap())
+                .unwrap();
+        let mut z_mwaf = z.by_index(0).unwrap();
+        std::io::copy(&mut z_mwaf, &mut mwaf).unwrap();
+        mwaf
+    }
+
+    #[test]
+    fn test_read_1065880128() {
+        let mwaf = MwafFile::read(&mwaf_1065880128()).unwrap();
+        assert_eq!(
+            mwaf.header,
+            MwafHeader {
+                version: "1.0".to_string(),
+                gps_time: 1065880128,
+                num_channels: 32,
+                num_antennas: 128,
+                num_scans: 224,
+                num_pols: 1,
+                gpubox_number: 1,
+                cotter_version: Some("2.4".to_string()),
+                cotter_version_date: Some("2014-08-07".to_string()),
+            }
+        );
+        assert_eq!(mwaf.flags.dim(), (224, 8256, 32));
+
+        // Only the first auto-correlation of the first scan isn't flagged in
+        // the first channel.
+        assert!(!mwaf.flags[[0, 0, 0]]);
+        assert!(mwaf.flags[[0, 1, 0]]);
+        assert!(mwaf.flags[[223, 8255, 0]]);
+        let num_flagged = |c| {
+            mwaf.flags
+                .iter()
+                .skip(c)
+                .step_by(32)
+                .filter(|&&f| f)
+                .count()
+        };
+        assert_eq!(num_flagged(0), 1849343);
+        assert_eq!(num_flagged(2), 155462);
+        assert_eq!(num_flagged(16), 1849343);
+    }
+
+    #[test]
+    fn test_unpack_flags() {
+        // 2 scans of 3 baselines with 10 channels (2 bytes per row).
+        let packed = [
+            0b1000_0000,
+            0b0000_0000, // scan 0, baseline 0: channel 0
+            0b0000_0001,
+            0b0000_0000, // scan 0, baseline 1: channel 7
+            0b0000_0000,
+            0b1100_0000, // scan 0, baseline 2: channels 8 and 9
+            0b0000_0000,
+            0b0010_0000, // scan 1, baseline 0: nothing (padding)
+            0b1111_1111,
+            0b1100_0000, // scan 1, baseline 1: everything
+            0b0001_0000,
+            0b0000_0000, // scan 1, baseline 2: channel 3
+        ];
+        let flags = unpack_flags(&packed, 2, 3, 10);
+        assert_eq!(flags.dim(), (2, 3, 10));
+        let flagged: Vec<(usize, usize, usize)> = flags
+            .indexed_iter()
+            .filter(|(_, &f)| f)
+            .map(|(i, _)| i)
+            .collect();
+        let mut expected = vec![(0, 0, 0), (0, 1, 7), (0, 2, 8), (0, 2, 9), (1, 2, 3)];
+        expected.extend((0..10).map(|c| (1, 1, c)));
+        expected.sort_unstable();
+        assert_eq!(flagged, expected);
+    }
+
+    #[test]
+    fn test_pack_flags() {
+        let packed = vec![0b1000_0000, 0b0100_0000, 0b0000_0001, 0b1100_0000];
+        let flags = unpack_flags(&packed, 1, 2, 10);
+        assert_eq!(pack_flags(flags.view()), packed);
+
+        // Bits past the last channel aren't kept.
+        let flags = unpack_flags(&[0b1111_1111, 0b1111_1111], 1, 1, 12);
+        assert_eq!(pack_flags(flags.view()), vec![0b1111_1111, 0b1111_0000]);
+    }
+
+    #[test]
+    fn test_write_mwaf() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // A brand new file.
+        let header = MwafHeader {
+            version: "2.0".to_string(),
+            gps_time: 1090008640,
+            num_channels: 10,
+            num_antennas: 3,
+            num_scans: 2,
+            num_pols: 1,
+            gpubox_number: 12,
+            cotter_version: None,
+            cotter_version_date: None,
+        };
+        let mut flags = Array3::from_elem(header.flags_shape(), false);
+        flags[[0, 0, 0]] = true;
+        flags[[1, 5, 9]] = true;
+        flags[[1, 3, 4]] = true;
+        let new_file = dir.path().join("1090008640_12.mwaf");
+        write_mwaf(&new_file, &header, flags.view()).unwrap();
+        let mwaf = MwafFile::read(&new_file).unwrap();
+        assert_eq!(mwaf.header, header);
+        assert_eq!(mwaf.flags, flags);
+
+        // Edit an existing file, and write it elsewhere.
+        let mut mwaf = MwafFile::read(&mwaf_1065880128()).unwrap();
+        mwaf.flags[[0, 0, 0]] = true;
+        mwaf.flags[[100, 200, 3]] = false;
+        let edited_file = dir.path().join("1065880128_01.mwaf");
+        mwaf.write(&edited_file).unwrap();
+        let edited = MwafFile::read(&edited_file).unwrap();
+        assert_eq!(edited.header, mwaf.header);
+        assert_eq!(edited.flags, mwaf.flags);
+        assert_eq!(edited.other_primary_cards, mwaf.other_primary_cards);
+        assert_eq!(edited.other_table_cards, mwaf.other_table_cards);
+
+        // cfitsio's extended file name syntax doesn't apply.
+        let odd_file = dir.path().join("!1065880128[01].mwaf");
+        mwaf.write(&odd_file).unwrap();
+        assert!(odd_file.exists());
+        assert_eq!(MwafFile::read(&odd_file).unwrap().flags, mwaf.flags);
+
+        // The flags must match the header.
+        let flags = Array3::from_elem((2, 6, 11), false);
+        match write_mwaf(&new_file, &header, flags.view()) {
+            Err(MwafError::WrongFlagsShape { got, expected }) => {
+                assert_eq!(got, (2, 6, 11));
+                assert_eq!(expected, (2, 6, 10));
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_other_cards() {
+        // Add REFLG keys (as reflag-mwaf-files does) and a comment, then check
+        // that they survive being read and written.
+        let dir = tempfile::tempdir().unwrap();
+        let reflagged = dir.path().join("1065880128_01.mwaf");
+        std::fs::copy(mwaf_1065880128().path(), &reflagged).unwrap();
+        {
+            let mut fptr = FitsFile::edit(&reflagged).unwrap();
+            let hdu = fptr.hdu(1).unwrap();
+            hdu.write_key(&mut fptr, "REFLG_00", 0_u32).unwrap();
+            hdu.write_key(&mut fptr, "REFLG_01", 31_u32).unwrap();
+            let hdu = fptr.primary_hdu().unwrap();
+            hdu.write_key(&mut fptr, "MYKEY", "kept").unwrap();
+        }
+        let mwaf = MwafFile::read(&reflagged).unwrap();
+        let keywords = |cards: &[String]| -> Vec<String> {
+            cards
+                .iter()
+                .map(|c| c[..8].trim_end().to_string())
+                .collect()
+        };
+        assert!(keywords(&mwaf.other_table_cards).contains(&"REFLG_00".to_string()));
+        assert!(keywords(&mwaf.other_table_cards).contains(&"REFLG_01".to_string()));
+        assert!(keywords(&mwaf.other_primary_cards).contains(&"MYKEY".to_string()));
+        // Keys that are read into the header aren't repeated.
+        assert!(!keywords(&mwaf.other_primary_cards).contains(&"GPSTIME".to_string()));
+        assert!(!keywords(&mwaf.other_table_cards).contains(&"NAXIS2".to_string()));
+
+        let written = dir.path().join("written.mwaf");
+        mwaf.write(&written).unwrap();
+        let mut fptr = FitsFile::open(&written).unwrap();
+        let hdu = fptr.hdu(1).unwrap();
+        assert_eq!(hdu.read_key::<i64>(&mut fptr, "REFLG_00").unwrap(), 0);
+        assert_eq!(hdu.read_key::<i64>(&mut fptr, "REFLG_01").unwrap(), 31);
+        let hdu = fptr.primary_hdu().unwrap();
+        assert_eq!(hdu.read_key::<String>(&mut fptr, "MYKEY").unwrap(), "kept");
+        let round_trip = MwafFile::read(&written).unwrap();
+        assert_eq!(round_trip.header, mwaf.header);
+        assert_eq!(round_trip.flags, mwaf.flags);
+        assert_eq!(round_trip.other_primary_cards, mwaf.other_primary_cards);
+        assert_eq!(round_trip.other_table_cards, mwaf.other_table_cards);
+    }
+
+    #[test]
+    fn test_baseline_to_antennas() {
+        assert_eq!(baseline_to_antennas(0, 128), Some((0, 0)));
+        assert_eq!(baseline_to_antennas(1, 128), Some((0, 1)));
+        assert_eq!(baseline_to_antennas(127, 128), Some((0, 127)));
+        assert_eq!(baseline_to_antennas(128, 128), Some((1, 1)));
+        assert_eq!(baseline_to_antennas(8255, 128), Some((127, 127)));
+        assert_eq!(baseline_to_antennas(2, 2), Some((1, 1)));
+        assert_eq!(baseline_to_antennas(8256, 128), None);
+        assert_eq!(baseline_to_antennas(3, 2), None);
+        assert_eq!(baseline_to_antennas(0, 0), None);
+    }
+}
@@ -2,11 +2,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+pub mod beam;
 pub mod coords;
 pub mod cotter;
 pub mod fits;
 pub mod ms;
 pub mod rts;
+pub mod srclist;
 pub mod time;
 
 /// The speed of light [m/s]
@@ -0,0 +1,136 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to compare RTS .in files.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::error::RtsParseError;
+use super::parse_key_values;
+
+/// An RTS key that has a different value in two .in files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtsKeyDiff {
+    pub key: String,
+    /// The value in the first .in file, if the key is present.
+    pub first: Option<String>,
+    /// The value in the second .in file, if the key is present.
+    pub second: Option<String>,
+}
+
+/// Report the RTS keys that differ between the contents of two .in files,
+/// sorted by key. Every key is compared, whether or not mongoose knows about
+/// it. The ordering of keys, comments (including the mongoose header) and the
+/// formatting of numbers (e.g. "2.0" vs. "2") don't matter.
+pub fn diff_in_files(first: &str, second: &str) -> Result<Vec<RtsKeyDiff>, RtsParseError> {
+    let to_map = |s: &str| -> Result<BTreeMap<String, String>, RtsParseError> {
+        Ok(parse_key_values(s)?
+            .into_iter()
+            .map(|kv| (kv.key, kv.value))
+            .collect())
+    };
+    let first = to_map(first)?;
+    let second = to_map(second)?;
+    let keys: BTreeSet<&String> = first.keys().chain(second.keys()).collect();
+    Ok(keys
+        .into_iter()
+        .filter_map(|key| {
+            let f = first.get(key);
+            let s = second.get(key);
+            let same = match (f, s) {
+                (Some(f), Some(s)) => values_match(f, s),
+                (None, None) => true,
+                _ => false,
+            };
+            if same {
+                None
+            } else {
+                Some(RtsKeyDiff {
+                    key: key.clone(),
+                    first: f.cloned(),
+                    second: s.cloned(),
+                })
+            }
+        })
+        .collect())
+}
+
+/// Are two values the same? Numbers are compared by value.
+fn values_match(first: &str, second: &str) -> bool {
+    if first == second {
+        return true;
+    }
+    match (first.parse::<f64>(), second.parse::<f64>()) {
+        (Ok(f), Ok(s)) => f == s,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let text = r#"// RTS in file to patch obsid 1065880128
+// Generated by mongoose v0.2.3
+// at 2020-12-01 01:02:03 UTC
+FscrunchChan=2
+SubBandIDs=1,2,3
+BaseFilename=/data/*_gpubox
+ReadMetafitsFile=0
+ImportCotterFlags=1
+ImportCotterBasename=/data/RTS_1065880128
+CorrDumpTime=2.0
+CorrDumpsPerCadence=32
+NumberOfIntegrationBins=6
+NumberOfIterations=1
+ObservationFrequencyBase=138.875
+NumberOfChannels=32
+ChannelBandwidth=0.04
+ObservationImageCentreRA=0
+ObservationImageCentreDec=-27
+SourceCatalogueFile=srclist.txt
+NumberOfCalibrators=1
+"#;
+
+        // Shuffle the keys, change the header and re-format a value; none of
+        // this should count as a difference.
+        let mut lines: Vec<&str> = text.lines().skip(3).collect();
+        lines.reverse();
+        let text2 = format!(
+            "// RTS in file to patch obsid 1065880128\n// at 2021-01-19 00:00:00 UTC\n{}\n",
+            lines
+                .join("\n")
+                .replace("CorrDumpTime=2.0", "CorrDumpTime=2")
+        );
+        assert!(diff_in_files(text, &text2).unwrap().is_empty());
+
+        // Keys that mongoose doesn't know about, or that it doesn't represent
+        // in `RtsParams`, are compared too.
+        let text2 = format!(
+            "{}NumberOfIonoCalibrators=1000\nDoCalibration=0\nSomeNewKey=1\n",
+            text.replace("CorrDumpTime=2.0", "CorrDumpTime=0.5")
+        );
+        let diffs = diff_in_files(text, &text2).unwrap();
+        let keys: Vec<&str> = diffs.iter().map(|d| d.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "CorrDumpTime",
+                "DoCalibration",
+                "NumberOfIonoCalibrators",
+                "SomeNewKey"
+            ]
+        );
+        assert_eq!(diffs[0].first.as_deref(), Some("2.0"));
+        assert_eq!(diffs[0].second.as_deref(), Some("0.5"));
+        assert_eq!(diffs[2].first, None);
+        assert_eq!(diffs[2].second.as_deref(), Some("1000"));
+
+        assert!(diff_in_files(text, "[package]\n").is_err());
+    }
+}
@@ -0,0 +1,102 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Error handling for RTS .in files.
+ */
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RtsParseError {
+    /// A non-comment line didn't contain an "=".
+    #[error("Line {line}: Expected a key=value pair, but got '{text}'")]
+    MissingEquals { line: usize, text: String },
+
+    /// The same key was specified more than once.
+    #[error("Line {line}: RTS key '{key}' was already specified on line {first_line}")]
+    DuplicateKey {
+        line: usize,
+        key: String,
+        first_line: usize,
+    },
+
+    /// The value of a key couldn't be parsed as the expected type.
+    #[error("Line {line}: Could not parse '{value}' as {expected} for RTS key '{key}'")]
+    BadValue {
+        line: usize,
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+
+    /// A key required to fill `RtsParams` wasn't present.
+    #[error("Required RTS key '{0}' is missing")]
+    MissingKey(&'static str),
+
+    /// The obsid couldn't be found in ImportCotterBasename, the header comment
+    /// or MetafitsFilename.
+    #[error("Could not determine the obsid; ImportCotterBasename is not set, there is no mongoose header comment and MetafitsFilename doesn't start with an obsid")]
+    NoObsid,
+
+    /// A line of a flagged tiles file isn't a tile index.
+    #[error("Line {line}: Expected a tile index, but got '{text}'")]
+    BadFlaggedTile { line: usize, text: String },
+}
+
+#[derive(Error, Debug)]
+pub enum RtsTimingError {
+    /// The integration time or cadence was zero.
+    #[error("Cannot derive RTS timing from an integration time of {integration_time_ms}ms and a cadence of {cadence_ms}ms")]
+    Zero {
+        integration_time_ms: u64,
+        cadence_ms: u64,
+    },
+
+    /// The cadence can't be made of whole correlator dumps.
+    #[error("The cadence ({cadence_ms}ms) is not a multiple of the integration time ({integration_time_ms}ms)")]
+    CadenceNotMultiple {
+        integration_time_ms: u64,
+        cadence_ms: u64,
+    },
+}
+
+/// A problem with a file that the RTS will open.
+#[derive(Error, Debug, PartialEq)]
+pub enum RtsFileProblem {
+    #[error("The base directory {0} can't be read")]
+    UnreadableBaseDir(PathBuf),
+
+    #[error("The base filename '{0}' is not a valid pattern")]
+    BadBaseFilename(String),
+
+    /// No visibility files match the base filename. If the visibilities are in
+    /// a file per subband, the subband is included.
+    #[error("No visibility files match {pattern}")]
+    MissingVisibilities {
+        subband: Option<u8>,
+        pattern: PathBuf,
+    },
+
+    #[error("{file} is not from obsid {obsid}")]
+    WrongObsid { file: PathBuf, obsid: u32 },
+
+    #[error("The cotter flags file {0} does not exist")]
+    MissingMwaf(PathBuf),
+
+    #[error("The metafits file {0} does not exist")]
+    MissingMetafits(PathBuf),
+
+    #[error("The source list {0} does not exist")]
+    MissingSrclist(PathBuf),
+
+    #[error("The FEE beam file {0} does not exist")]
+    MissingFeeBeam(PathBuf),
+
+    /// The FEE beam file exists, but isn't an MWA FEE beam file.
+    #[error("{0}")]
+    BadFeeBeam(String),
+}
@@ -0,0 +1,75 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to handle the RTS's list of tiles to flag.
+ *
+ * The RTS reads this list from a file named flagged_tiles.txt in the directory
+ * it runs in. Each line is the index of a tile to flag, i.e. its "Antenna"
+ * number in the metafits file.
+ */
+
+use std::io::Write;
+
+use super::error::RtsParseError;
+
+/// The name of the file that the RTS reads tile flags from.
+pub const FLAGGED_TILES_FILE_NAME: &str = "flagged_tiles.txt";
+
+/// Parse the contents of a flagged tiles file. Blank lines are ignored.
+pub fn parse_flagged_tiles(s: &str) -> Result<Vec<u32>, RtsParseError> {
+    let mut tiles = vec![];
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(t) => tiles.push(t),
+            Err(_) => {
+                return Err(RtsParseError::BadFlaggedTile {
+                    line: i + 1,
+                    text: line.to_string(),
+                })
+            }
+        }
+    }
+    Ok(tiles)
+}
+
+/// Write tiles to flag in the format of a flagged tiles file. The tiles are
+/// sorted and written once each.
+pub fn write_flagged_tiles<W: Write>(writer: &mut W, tiles: &[u32]) -> Result<(), std::io::Error> {
+    let mut tiles = tiles.to_vec();
+    tiles.sort_unstable();
+    tiles.dedup();
+    for t in tiles {
+        writeln!(writer, "{}", t)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flagged_tiles() {
+        let mut written = vec![];
+        write_flagged_tiles(&mut written, &[72, 3, 127, 3]).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(written, "3\n72\n127\n");
+        assert_eq!(parse_flagged_tiles(&written).unwrap(), vec![3, 72, 127]);
+        assert_eq!(parse_flagged_tiles("\n 5 \n\n").unwrap(), vec![5]);
+        assert!(parse_flagged_tiles("").unwrap().is_empty());
+
+        match parse_flagged_tiles("3\nTile011\n") {
+            Err(RtsParseError::BadFlaggedTile { line, text }) => {
+                assert_eq!(line, 2);
+                assert_eq!(text, "Tile011");
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+}
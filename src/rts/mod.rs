@@ -2,12 +2,45 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod diff;
+pub mod error;
+mod flagged_tiles;
+mod parse;
+mod subbands;
+mod timing;
+mod validate;
+
+pub use diff::{diff_in_files, RtsKeyDiff};
+pub use flagged_tiles::{parse_flagged_tiles, write_flagged_tiles, FLAGGED_TILES_FILE_NAME};
+pub use parse::{parse_key_values, RtsKeyValue};
+pub use subbands::{subband_groups, CoarseChannel, SubbandGroup};
+pub use timing::{
+    RtsTiming, DEFAULT_PATCH_CADENCE, DEFAULT_PEEL_CADENCE, MAX_PATCH_INTEGRATION_BINS,
+    MAX_PEEL_INTEGRATION_BINS,
+};
+
 use std::path::PathBuf;
 
 use chrono::Utc;
 use itertools::Itertools;
 
-#[derive(Clone, Copy, Debug)]
+/// The default value of MaxFrequency [MHz].
+pub const DEFAULT_MAX_FREQUENCY: f64 = 200.0;
+/// The default value of ArrayNumberOfStations (the number of MWA Phase I
+/// tiles).
+pub const DEFAULT_ARRAY_NUM_STATIONS: u32 = 128;
+/// The default value of ArrayPositionLat (the MWA's latitude) [degrees].
+pub const DEFAULT_ARRAY_POSITION_LAT: f64 = -26.70331940;
+/// The default value of ArrayPositionLong (the MWA's longitude) [degrees].
+pub const DEFAULT_ARRAY_POSITION_LONG: f64 = 116.67081524;
+/// The default value of calBaselineMin [wavelengths].
+pub const DEFAULT_CAL_BASELINE_MIN: f64 = 20.0;
+/// The default value of calShortBaselineTaper [wavelengths].
+pub const DEFAULT_CAL_SHORT_BASELINE_TAPER: f64 = 40.0;
+/// The default value of ImageOversampling.
+pub const DEFAULT_IMAGE_OVERSAMPLING: f64 = 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RtsMode {
     Patch,
     Peel {
@@ -23,7 +56,7 @@ pub enum RtsMode {
     },
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RtsParams {
     /// The type of RTS processing we're doing.
     pub mode: RtsMode,
@@ -69,6 +102,13 @@ pub struct RtsParams {
     /// filename. Used when reading uvfits.
     pub add_node_number_to_filename: bool,
 
+    /// UsePacketInput: Read visibilities from packets sent over the network by
+    /// the correlator, rather than files.
+    pub use_packet_input: bool,
+
+    /// UseThreadedVI: Read visibilities with a separate thread.
+    pub use_threaded_vi: bool,
+
     /// The path to the FEE beam HDF5 file (hdf5Filename). If it's not
     /// specified, assume we're using the analytic beam.
     pub fee_beam_file: Option<PathBuf>,
@@ -95,6 +135,10 @@ pub struct RtsParams {
     /// Beam. Degrees. Overridden by dipole delays if available.
     pub obs_pointing_centre_dec: Option<f64>,
 
+    /// StartProcessingAt: The correlator dump (timestep) to start processing
+    /// from.
+    pub start_processing_at: u32,
+
     /// CorrDumpTime: Output time resolution, or the cadence at which data is
     /// dumped in seconds
     pub corr_dump_time: f64,
@@ -145,6 +189,41 @@ pub struct RtsParams {
     /// writeVisToUVFITS: Write the visibilities processed by the RTS to uvfits
     /// files. The names are always uvdump_??.uvfits
     pub write_vis_to_uvfits: bool,
+
+    // Magic.
+    /// MaxFrequency: Used to set size of uv cells for gridding, in MHz. Also
+    /// affects binning of baselines by setting maximum decorrelation.
+    pub max_frequency: f64,
+
+    /// ArrayNumberOfStations: The number of tiles in the array.
+    pub array_num_stations: u32,
+
+    /// ArrayPositionLat: The latitude of the array in degrees.
+    pub array_position_lat: f64,
+
+    /// ArrayPositionLong: The longitude of the array in degrees.
+    pub array_position_long: f64,
+
+    /// calBaselineMin: The minimum baseline length used in calibration, in
+    /// wavelengths.
+    pub cal_baseline_min: f64,
+
+    /// calShortBaselineTaper: The width of the taper applied to baselines
+    /// shorter than calBaselineMin, in wavelengths.
+    pub cal_short_baseline_taper: f64,
+
+    /// ImageOversampling: Sets oversampling of imaging pixel.
+    pub image_oversampling: f64,
+
+    /// StorePixelMatrices: Store pixel beam weights along with intensity.
+    /// Required if subsequently integrating images using integrate_image
+    /// utility. Images will be 4X greater data volume.
+    pub store_pixel_matrices: bool,
+
+    /// RTS keys that mongoose doesn't know about (e.g. from a hand-edited .in
+    /// file), with their values and the lines they were read from. These are
+    /// written after all of the other keys.
+    pub other_keys: Vec<RtsKeyValue>,
 }
 
 impl std::fmt::Display for RtsParams {
@@ -159,7 +238,7 @@ FscrunchChan={fscrunch}
 
 SubBandIDs={subband_ids}
 
-StartProcessingAt=0
+StartProcessingAt={start_processing_at}
 
 DoCalibration=1
 generateDIjones={generate_di_jones}
@@ -175,8 +254,8 @@ doRawDataCorrections={do_raw_data_corrections}
 ReadGpuboxDirect={read_gpubox_direct}
 ReadAllFromSingleFile={read_all_from_single_file}
 AddNodeNumberToFilename={add_node_number_to_filename}
-UsePacketInput=0
-UseThreadedVI=0
+UsePacketInput={use_packet_input}
+UseThreadedVI={use_threaded_vi}
 
 {beam}
 
@@ -205,27 +284,27 @@ NumberOfCalibrators={num_primary_cals}
 // MaxFrequency [MHz, float]: Used to set size of uv cells for gridding. Also
 // affects binning of baselines by setting maximum decorrelation. Default is 300
 // MHz.
-MaxFrequency=200
+MaxFrequency={max_frequency}
 
 // array_file.txt doesn't exist, but currently, the RTS will not run without it!
 ArrayFile=array_file.txt
-ArrayNumberOfStations=128
+ArrayNumberOfStations={array_num_stations}
 
 // Heaven help you if you're not using the MWA.
-ArrayPositionLat=-26.70331940
-ArrayPositionLong=116.67081524
+ArrayPositionLat={array_position_lat}
+ArrayPositionLong={array_position_long}
 
-calBaselineMin=20.0
-calShortBaselineTaper=40.0
+calBaselineMin={cal_baseline_min}
+calShortBaselineTaper={cal_short_baseline_taper}
 
 // ImageOversampling [float]: Sets oversampling of imaging pixel. Default value
 // is 3.
-ImageOversampling=3
+ImageOversampling={image_oversampling}
 
 // Store pixel beam weights along with intensity. Required if subsequently
 // integrating images using integrate_image utility. Images will be 4X greater
 // data volume.
-StorePixelMatrices=0
+StorePixelMatrices={store_pixel_matrices}
 "#,
             mode = match &self.mode {
                 RtsMode::Patch => "patch",
@@ -235,6 +314,7 @@ StorePixelMatrices=0
             version = env!("CARGO_PKG_VERSION"),
             time = Utc::now().format("%Y-%m-%d %H:%M:%S"),
             fscrunch = self.f_scrunch,
+            start_processing_at = self.start_processing_at,
             subband_ids = self.subband_ids.iter().map(|x| format!("{}", x)).join(","),
             generate_di_jones = match &self.mode {
                 RtsMode::Patch => 1,
@@ -274,6 +354,8 @@ StorePixelMatrices=0
             } else {
                 0
             },
+            use_packet_input = if self.use_packet_input { 1 } else { 0 },
+            use_threaded_vi = if self.use_threaded_vi { 1 } else { 0 },
             beam = if let Some(f) = &self.fee_beam_file {
                 format!(
                     "// FEE beam\n\
@@ -326,7 +408,23 @@ StorePixelMatrices=0
                 "writeVisToUVFITS={}",
                 if self.write_vis_to_uvfits { 1 } else { 0 }
             ),
-        )
+            max_frequency = self.max_frequency,
+            array_num_stations = self.array_num_stations,
+            array_position_lat = self.array_position_lat,
+            array_position_long = self.array_position_long,
+            cal_baseline_min = self.cal_baseline_min,
+            cal_short_baseline_taper = self.cal_short_baseline_taper,
+            image_oversampling = self.image_oversampling,
+            store_pixel_matrices = if self.store_pixel_matrices { 1 } else { 0 },
+        )?;
+
+        if !self.other_keys.is_empty() {
+            writeln!(f, "\n// Keys not known to mongoose.")?;
+        }
+        for kv in &self.other_keys {
+            writeln!(f, "{}={}", kv.key, kv.value)?;
+        }
+        Ok(())
     }
 }
 
@@ -350,12 +448,15 @@ mod tests {
             read_gpubox_direct: true,
             read_all_from_single_file: true,
             add_node_number_to_filename: false,
+            use_packet_input: false,
+            use_threaded_vi: false,
             fee_beam_file: None,
             obsid,
             obs_image_centre_ra: 0.0,
             obs_image_centre_dec: -27.0,
             obs_pointing_centre_ha: None,
             obs_pointing_centre_dec: None,
+            start_processing_at: 0,
             corr_dump_time: 2.0,
             corr_dumps_per_cadence: 32,
             num_integration_bins: 7,
@@ -368,6 +469,15 @@ mod tests {
             num_primary_cals: 1,
             disable_srclist_vetos: false,
             write_vis_to_uvfits: false,
+            max_frequency: DEFAULT_MAX_FREQUENCY,
+            array_num_stations: DEFAULT_ARRAY_NUM_STATIONS,
+            array_position_lat: DEFAULT_ARRAY_POSITION_LAT,
+            array_position_long: DEFAULT_ARRAY_POSITION_LONG,
+            cal_baseline_min: DEFAULT_CAL_BASELINE_MIN,
+            cal_short_baseline_taper: DEFAULT_CAL_SHORT_BASELINE_TAPER,
+            image_oversampling: DEFAULT_IMAGE_OVERSAMPLING,
+            store_pixel_matrices: false,
+            other_keys: vec![],
         };
         let output = format!("{}", params);
 
@@ -415,12 +525,15 @@ mod tests {
             read_gpubox_direct: true,
             read_all_from_single_file: true,
             add_node_number_to_filename: false,
+            use_packet_input: false,
+            use_threaded_vi: false,
             fee_beam_file: Some(PathBuf::from("/random/spot/beam_file.hdf5")),
             obsid,
             obs_image_centre_ra: 0.0,
             obs_image_centre_dec: -27.0,
             obs_pointing_centre_ha: None,
             obs_pointing_centre_dec: None,
+            start_processing_at: 4,
             corr_dump_time: 2.0,
             corr_dumps_per_cadence: 32,
             num_integration_bins: 7,
@@ -433,6 +546,15 @@ mod tests {
             num_primary_cals: 5,
             disable_srclist_vetos: true,
             write_vis_to_uvfits: true,
+            max_frequency: DEFAULT_MAX_FREQUENCY,
+            array_num_stations: 256,
+            array_position_lat: DEFAULT_ARRAY_POSITION_LAT,
+            array_position_long: DEFAULT_ARRAY_POSITION_LONG,
+            cal_baseline_min: DEFAULT_CAL_BASELINE_MIN,
+            cal_short_baseline_taper: DEFAULT_CAL_SHORT_BASELINE_TAPER,
+            image_oversampling: DEFAULT_IMAGE_OVERSAMPLING,
+            store_pixel_matrices: true,
+            other_keys: vec![],
         };
         let output = format!("{}", params);
 
@@ -458,5 +580,9 @@ mod tests {
         assert!(output.contains("DisableSourcelistVetos=1\n"));
         assert!(output.contains("doRFIflagging=1\n"));
         assert!(output.contains("SubBandIDs=1,2,3\n"));
+        assert!(output.contains("StartProcessingAt=4\n"));
+        assert!(output.contains("ArrayNumberOfStations=256\n"));
+        assert!(output.contains("StorePixelMatrices=1\n"));
+        assert!(output.contains("MaxFrequency=200\n"));
     }
 }
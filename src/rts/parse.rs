@@ -0,0 +1,576 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to read RTS .in files back into `RtsParams`.
+ */
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::error::RtsParseError;
+use super::*;
+
+/// A single key=value pair from an RTS .in file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtsKeyValue {
+    /// The line number that this pair was found on (starting from 1).
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+/// Split the contents of an RTS .in file into its key=value pairs. A "//" at
+/// the start of a line or after whitespace starts a comment (so paths like
+/// "/data//RTS_1065880128" are left alone), and blank lines are ignored. No key
+/// may be specified more than once.
+pub fn parse_key_values(s: &str) -> Result<Vec<RtsKeyValue>, RtsParseError> {
+    let mut pairs: Vec<RtsKeyValue> = vec![];
+    for (i, line) in s.lines().enumerate() {
+        let line_num = i + 1;
+        let text = match comment_start(line) {
+            Some(j) => &line[..j],
+            None => line,
+        }
+        .trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let (key, value) = match text.find('=') {
+            Some(j) => (text[..j].trim(), text[j + 1..].trim()),
+            None => {
+                return Err(RtsParseError::MissingEquals {
+                    line: line_num,
+                    text: text.to_string(),
+                })
+            }
+        };
+        if let Some(first) = pairs.iter().find(|p| p.key == key) {
+            return Err(RtsParseError::DuplicateKey {
+                line: line_num,
+                key: key.to_string(),
+                first_line: first.line,
+            });
+        }
+        pairs.push(RtsKeyValue {
+            line: line_num,
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    Ok(pairs)
+}
+
+/// Find the "//" that starts a comment in a line, if any.
+fn comment_start(line: &str) -> Option<usize> {
+    line.match_indices("//")
+        .map(|(j, _)| j)
+        .find(|&j| j == 0 || line[..j].ends_with(char::is_whitespace))
+}
+
+fn parse_value<T: FromStr>(kv: &RtsKeyValue, expected: &'static str) -> Result<T, RtsParseError> {
+    kv.value.parse().map_err(|_| RtsParseError::BadValue {
+        line: kv.line,
+        key: kv.key.clone(),
+        value: kv.value.clone(),
+        expected,
+    })
+}
+
+/// The RTS uses 0 and 1 for booleans.
+fn parse_bool(kv: &RtsKeyValue) -> Result<bool, RtsParseError> {
+    match kv.value.as_str() {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(RtsParseError::BadValue {
+            line: kv.line,
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+            expected: "a boolean (0 or 1)",
+        }),
+    }
+}
+
+/// Get the obsid out of a path like "/some/dir/RTS_1065880128".
+fn obsid_from_cotter_basename(kv: &RtsKeyValue) -> Result<u32, RtsParseError> {
+    Path::new(&kv.value)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .and_then(|f| f.strip_prefix("RTS_"))
+        .and_then(|o| o.parse().ok())
+        .ok_or_else(|| RtsParseError::BadValue {
+            line: kv.line,
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+            expected: "a path ending in RTS_<obsid>",
+        })
+}
+
+/// Get the obsid out of a metafits path like "/some/dir/1065880128.metafits"
+/// or "1065880128_metafits_ppds.fits".
+fn obsid_from_metafits(metafits: &Path) -> Option<u32> {
+    let file_name = metafits.file_name()?.to_str()?;
+    let digits: String = file_name
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Get the obsid out of the header comment that mongoose writes, e.g.
+/// "// RTS in file to patch obsid 1065880128".
+fn obsid_from_header(s: &str) -> Option<u32> {
+    s.lines()
+        .filter_map(|l| l.trim().strip_prefix("//"))
+        .filter(|l| l.trim().starts_with("RTS in file to"))
+        .find_map(|l| {
+            let mut words = l.split_whitespace().skip_while(|&w| w != "obsid");
+            words.nth(1).and_then(|o| o.parse().ok())
+        })
+}
+
+impl FromStr for RtsParams {
+    type Err = RtsParseError;
+
+    /// Read the contents of an RTS .in file. Values that can't be parsed are
+    /// reported with their line number. Keys that mongoose always writes but
+    /// that aren't represented in `RtsParams` are checked, but otherwise
+    /// ignored. Keys that mongoose doesn't know about are kept in `other_keys`
+    /// with their line numbers.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = parse_key_values(s)?;
+        let mut bools: HashMap<&str, bool> = HashMap::new();
+        let mut f_scrunch = None;
+        let mut subband_ids = None;
+        let mut base_filename = None;
+        let mut metafits = None;
+        let mut obsid = None;
+        let mut fee_beam_file = None;
+        let mut corr_dump_time = None;
+        let mut corr_dumps_per_cadence = None;
+        let mut num_integration_bins = None;
+        let mut num_iterations = None;
+        let mut base_freq = None;
+        let mut num_fine_channels = None;
+        let mut fine_channel_width_mhz = None;
+        let mut obs_image_centre_ra = None;
+        let mut obs_image_centre_dec = None;
+        let mut obs_pointing_centre_ha = None;
+        let mut obs_pointing_centre_dec = None;
+        let mut source_catalogue_file = None;
+        let mut num_primary_cals = None;
+        let mut num_cals = None;
+        let mut num_peel = None;
+        let mut start_processing_at = None;
+        let mut max_frequency = None;
+        let mut array_num_stations = None;
+        let mut array_position_lat = None;
+        let mut array_position_long = None;
+        let mut cal_baseline_min = None;
+        let mut cal_short_baseline_taper = None;
+        let mut image_oversampling = None;
+        let mut other_keys = vec![];
+
+        for kv in &pairs {
+            match kv.key.as_str() {
+                "FscrunchChan" => f_scrunch = Some(parse_value(kv, "an integer")?),
+                "SubBandIDs" => {
+                    subband_ids = Some(
+                        kv.value
+                            .split(',')
+                            .map(|s| s.trim().parse())
+                            .collect::<Result<Vec<u8>, _>>()
+                            .map_err(|_| RtsParseError::BadValue {
+                                line: kv.line,
+                                key: kv.key.clone(),
+                                value: kv.value.clone(),
+                                expected: "a comma-separated list of integers",
+                            })?,
+                    )
+                }
+                "BaseFilename" => base_filename = Some(kv.value.clone()),
+                "MetafitsFilename" => metafits = Some(PathBuf::from(&kv.value)),
+                "ImportCotterBasename" => obsid = Some(obsid_from_cotter_basename(kv)?),
+                "hdf5Filename" => fee_beam_file = Some(PathBuf::from(&kv.value)),
+                "CorrDumpTime" => corr_dump_time = Some(parse_value(kv, "a float")?),
+                "CorrDumpsPerCadence" => {
+                    corr_dumps_per_cadence = Some(parse_value(kv, "an integer")?)
+                }
+                "NumberOfIntegrationBins" => {
+                    num_integration_bins = Some(parse_value(kv, "an integer")?)
+                }
+                "NumberOfIterations" => num_iterations = Some(parse_value(kv, "an integer")?),
+                "ObservationFrequencyBase" => base_freq = Some(parse_value(kv, "a float")?),
+                "NumberOfChannels" => num_fine_channels = Some(parse_value(kv, "an integer")?),
+                "ChannelBandwidth" => fine_channel_width_mhz = Some(parse_value(kv, "a float")?),
+                "ObservationImageCentreRA" => {
+                    obs_image_centre_ra = Some(parse_value(kv, "a float")?)
+                }
+                "ObservationImageCentreDec" => {
+                    obs_image_centre_dec = Some(parse_value(kv, "a float")?)
+                }
+                "ObservationPointCentreHA" => {
+                    obs_pointing_centre_ha = Some(parse_value(kv, "a float")?)
+                }
+                "ObservationPointCentreDec" => {
+                    obs_pointing_centre_dec = Some(parse_value(kv, "a float")?)
+                }
+                "SourceCatalogueFile" => source_catalogue_file = Some(PathBuf::from(&kv.value)),
+                "NumberOfCalibrators" => num_primary_cals = Some(parse_value(kv, "an integer")?),
+                "NumberOfIonoCalibrators" => num_cals = Some(parse_value(kv, "an integer")?),
+                "NumberOfSourcesToPeel" => num_peel = Some(parse_value(kv, "an integer")?),
+                "StartProcessingAt" => start_processing_at = Some(parse_value(kv, "an integer")?),
+                "MaxFrequency" => max_frequency = Some(parse_value(kv, "a float")?),
+                "ArrayNumberOfStations" => array_num_stations = Some(parse_value(kv, "an integer")?),
+                "ArrayPositionLat" => array_position_lat = Some(parse_value(kv, "a float")?),
+                "ArrayPositionLong" => array_position_long = Some(parse_value(kv, "a float")?),
+                "calBaselineMin" => cal_baseline_min = Some(parse_value(kv, "a float")?),
+                "calShortBaselineTaper" => {
+                    cal_short_baseline_taper = Some(parse_value(kv, "a float")?)
+                }
+                "ImageOversampling" => image_oversampling = Some(parse_value(kv, "a float")?),
+
+                "ReadMetafitsFile"
+                | "ImportCotterFlags"
+                | "doRFIflagging"
+                | "doMWArxCorrections"
+                | "doRawDataCorrections"
+                | "ReadGpuboxDirect"
+                | "ReadAllFromSingleFile"
+                | "AddNodeNumberToFilename"
+                | "DisableSourcelistVetos"
+                | "writeVisToUVFITS"
+                | "UsePacketInput"
+                | "UseThreadedVI"
+                | "StorePixelMatrices"
+                // Not represented in `RtsParams`.
+                | "DoCalibration"
+                | "generateDIjones"
+                | "useStoredCalibrationFiles"
+                | "applyDIcalibration"
+                | "useFastPrimaryBeamModels"
+                | "UpdateCalibratorAmplitudes" => {
+                    bools.insert(kv.key.as_str(), parse_bool(kv)?);
+                }
+
+                // Not represented in `RtsParams`.
+                "TileBeamType" => {
+                    parse_value::<u32>(kv, "an integer")?;
+                }
+                "ArrayFile" => (),
+
+                _ => other_keys.push(kv.clone()),
+            }
+        }
+
+        let get_bool = |key: &str| bools.get(key).copied().unwrap_or(false);
+
+        // BaseFilename is written as <base_dir>/<base_filename>.
+        let base_filename = base_filename.ok_or(RtsParseError::MissingKey("BaseFilename"))?;
+        let (base_dir, base_filename) = match base_filename.rfind('/') {
+            Some(i) => (
+                PathBuf::from(&base_filename[..i.max(1)]),
+                base_filename[i + 1..].to_string(),
+            ),
+            None => (PathBuf::from("."), base_filename),
+        };
+
+        let mode = match (num_cals, num_peel) {
+            (None, None) => RtsMode::Patch,
+            (Some(num_cals), Some(num_peel)) => RtsMode::Peel { num_cals, num_peel },
+            (Some(_), None) => return Err(RtsParseError::MissingKey("NumberOfSourcesToPeel")),
+            (None, Some(_)) => return Err(RtsParseError::MissingKey("NumberOfIonoCalibrators")),
+        };
+
+        let use_cotter_flags = get_bool("ImportCotterFlags");
+        let obsid = match obsid {
+            Some(o) if use_cotter_flags => o,
+            _ => match obsid_from_header(s)
+                .or_else(|| metafits.as_deref().and_then(obsid_from_metafits))
+            {
+                Some(o) => o,
+                None if use_cotter_flags => {
+                    return Err(RtsParseError::MissingKey("ImportCotterBasename"))
+                }
+                None => return Err(RtsParseError::NoObsid),
+            },
+        };
+
+        let metafits = if get_bool("ReadMetafitsFile") {
+            Some(metafits.ok_or(RtsParseError::MissingKey("MetafitsFilename"))?)
+        } else {
+            None
+        };
+
+        Ok(RtsParams {
+            mode,
+            base_dir,
+            base_filename,
+            metafits,
+            use_cotter_flags,
+            source_catalogue_file: source_catalogue_file
+                .ok_or(RtsParseError::MissingKey("SourceCatalogueFile"))?,
+            do_rfi_flagging: get_bool("doRFIflagging"),
+            do_rx_corrections: get_bool("doMWArxCorrections"),
+            do_raw_data_corrections: get_bool("doRawDataCorrections"),
+            read_gpubox_direct: get_bool("ReadGpuboxDirect"),
+            read_all_from_single_file: get_bool("ReadAllFromSingleFile"),
+            add_node_number_to_filename: get_bool("AddNodeNumberToFilename"),
+            use_packet_input: get_bool("UsePacketInput"),
+            use_threaded_vi: get_bool("UseThreadedVI"),
+            fee_beam_file,
+            obsid,
+            obs_image_centre_ra: obs_image_centre_ra
+                .ok_or(RtsParseError::MissingKey("ObservationImageCentreRA"))?,
+            obs_image_centre_dec: obs_image_centre_dec
+                .ok_or(RtsParseError::MissingKey("ObservationImageCentreDec"))?,
+            obs_pointing_centre_ha,
+            obs_pointing_centre_dec,
+            start_processing_at: start_processing_at.unwrap_or(0),
+            corr_dump_time: corr_dump_time.ok_or(RtsParseError::MissingKey("CorrDumpTime"))?,
+            corr_dumps_per_cadence: corr_dumps_per_cadence
+                .ok_or(RtsParseError::MissingKey("CorrDumpsPerCadence"))?,
+            num_integration_bins: num_integration_bins
+                .ok_or(RtsParseError::MissingKey("NumberOfIntegrationBins"))?,
+            num_iterations: num_iterations
+                .ok_or(RtsParseError::MissingKey("NumberOfIterations"))?,
+            fine_channel_width_mhz: fine_channel_width_mhz
+                .ok_or(RtsParseError::MissingKey("ChannelBandwidth"))?,
+            num_fine_channels: num_fine_channels
+                .ok_or(RtsParseError::MissingKey("NumberOfChannels"))?,
+            f_scrunch: f_scrunch.ok_or(RtsParseError::MissingKey("FscrunchChan"))?,
+            base_freq: base_freq.ok_or(RtsParseError::MissingKey("ObservationFrequencyBase"))?,
+            subband_ids: subband_ids.ok_or(RtsParseError::MissingKey("SubBandIDs"))?,
+            num_primary_cals: num_primary_cals
+                .ok_or(RtsParseError::MissingKey("NumberOfCalibrators"))?,
+            disable_srclist_vetos: get_bool("DisableSourcelistVetos"),
+            write_vis_to_uvfits: get_bool("writeVisToUVFITS"),
+            max_frequency: max_frequency.unwrap_or(DEFAULT_MAX_FREQUENCY),
+            array_num_stations: array_num_stations.unwrap_or(DEFAULT_ARRAY_NUM_STATIONS),
+            array_position_lat: array_position_lat.unwrap_or(DEFAULT_ARRAY_POSITION_LAT),
+            array_position_long: array_position_long.unwrap_or(DEFAULT_ARRAY_POSITION_LONG),
+            cal_baseline_min: cal_baseline_min.unwrap_or(DEFAULT_CAL_BASELINE_MIN),
+            cal_short_baseline_taper: cal_short_baseline_taper
+                .unwrap_or(DEFAULT_CAL_SHORT_BASELINE_TAPER),
+            image_oversampling: image_oversampling.unwrap_or(DEFAULT_IMAGE_OVERSAMPLING),
+            store_pixel_matrices: get_bool("StorePixelMatrices"),
+            other_keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_params(mode: RtsMode) -> RtsParams {
+        RtsParams {
+            mode,
+            base_dir: PathBuf::from("/data/1065880128"),
+            base_filename: "*_gpubox".to_string(),
+            metafits: Some(PathBuf::from("/data/1065880128/1065880128.metafits")),
+            use_cotter_flags: true,
+            source_catalogue_file: PathBuf::from("cool_srclist.txt"),
+            do_rfi_flagging: false,
+            do_rx_corrections: true,
+            do_raw_data_corrections: true,
+            read_gpubox_direct: true,
+            read_all_from_single_file: true,
+            add_node_number_to_filename: false,
+            use_packet_input: false,
+            use_threaded_vi: false,
+            fee_beam_file: None,
+            obsid: 1065880128,
+            obs_image_centre_ra: 0.0,
+            obs_image_centre_dec: -27.0,
+            obs_pointing_centre_ha: None,
+            obs_pointing_centre_dec: None,
+            start_processing_at: 1,
+            corr_dump_time: 0.5,
+            corr_dumps_per_cadence: 128,
+            num_integration_bins: 7,
+            num_iterations: 1,
+            fine_channel_width_mhz: 0.04,
+            num_fine_channels: 32,
+            f_scrunch: 2,
+            base_freq: 167.035,
+            subband_ids: (1..=24).collect(),
+            num_primary_cals: 1,
+            disable_srclist_vetos: false,
+            write_vis_to_uvfits: false,
+            max_frequency: DEFAULT_MAX_FREQUENCY,
+            array_num_stations: DEFAULT_ARRAY_NUM_STATIONS,
+            array_position_lat: DEFAULT_ARRAY_POSITION_LAT,
+            array_position_long: DEFAULT_ARRAY_POSITION_LONG,
+            cal_baseline_min: DEFAULT_CAL_BASELINE_MIN,
+            cal_short_baseline_taper: DEFAULT_CAL_SHORT_BASELINE_TAPER,
+            image_oversampling: DEFAULT_IMAGE_OVERSAMPLING,
+            store_pixel_matrices: false,
+            other_keys: vec![],
+        }
+    }
+
+    #[test]
+    fn test_patch_round_trip() {
+        let params = get_params(RtsMode::Patch);
+        let parsed: RtsParams = params.to_string().parse().unwrap();
+        assert_eq!(parsed, params);
+    }
+
+    #[test]
+    fn test_peel_round_trip() {
+        let mut params = get_params(RtsMode::Peel {
+            num_cals: 1000,
+            num_peel: 500,
+        });
+        // Without cotter flags, the obsid must come from the header.
+        params.use_cotter_flags = false;
+        params.metafits = None;
+        params.fee_beam_file = Some(PathBuf::from("/random/spot/beam_file.hdf5"));
+        params.obs_pointing_centre_ha = Some(-1.5);
+        params.obs_pointing_centre_dec = Some(-26.7);
+        params.write_vis_to_uvfits = true;
+        params.use_threaded_vi = true;
+        params.max_frequency = 300.0;
+        params.array_num_stations = 256;
+        params.array_position_lat = -30.0;
+        params.array_position_long = 120.5;
+        params.image_oversampling = 2.5;
+        let parsed: RtsParams = params.to_string().parse().unwrap();
+        assert_eq!(parsed, params);
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let params = get_params(RtsMode::Patch);
+        let text = format!("{}\nNotARealKey=1\n", params);
+        let line = text.lines().count();
+        let parsed: RtsParams = text.parse().unwrap();
+        assert_eq!(
+            parsed.other_keys,
+            vec![RtsKeyValue {
+                line,
+                key: "NotARealKey".to_string(),
+                value: "1".to_string(),
+            }]
+        );
+        // Unknown keys survive being written out again, although they're now
+        // on a different line.
+        let mut reparsed: RtsParams = parsed.to_string().parse().unwrap();
+        assert_ne!(reparsed.other_keys[0].line, line);
+        reparsed.other_keys[0].line = line;
+        assert_eq!(reparsed, parsed);
+    }
+
+    #[test]
+    fn test_obsid_from_metafits() {
+        let mut params = get_params(RtsMode::Patch);
+        params.use_cotter_flags = false;
+        // Remove the mongoose header, so that the obsid can only come from
+        // MetafitsFilename.
+        let text: String = params
+            .to_string()
+            .lines()
+            .skip(3)
+            .map(|l| format!("{}\n", l))
+            .collect();
+        let parsed: RtsParams = text.parse().unwrap();
+        assert_eq!(parsed.obsid, 1065880128);
+
+        let text = text.replace("MetafitsFilename=", "// MetafitsFilename=");
+        assert!(matches!(
+            text.parse::<RtsParams>(),
+            Err(RtsParseError::NoObsid)
+        ));
+    }
+
+    #[test]
+    fn test_bad_value() {
+        let text = get_params(RtsMode::Patch)
+            .to_string()
+            .replace("CorrDumpTime=0.5", "CorrDumpTime=half");
+        let line = text
+            .lines()
+            .position(|l| l.starts_with("CorrDumpTime"))
+            .unwrap()
+            + 1;
+        match text.parse::<RtsParams>() {
+            Err(RtsParseError::BadValue {
+                line: l,
+                key,
+                value,
+                ..
+            }) => {
+                assert_eq!(l, line);
+                assert_eq!(key, "CorrDumpTime");
+                assert_eq!(value, "half");
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        let text = get_params(RtsMode::Patch)
+            .to_string()
+            .replace("doRFIflagging=0", "doRFIflagging=yes");
+        assert!(matches!(
+            text.parse::<RtsParams>(),
+            Err(RtsParseError::BadValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_key_values() {
+        let text = "// A comment\n\nFscrunchChan=2 // trailing comment\n  CorrDumpTime = 2.0\n";
+        let pairs = parse_key_values(text).unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                RtsKeyValue {
+                    line: 3,
+                    key: "FscrunchChan".to_string(),
+                    value: "2".to_string(),
+                },
+                RtsKeyValue {
+                    line: 4,
+                    key: "CorrDumpTime".to_string(),
+                    value: "2.0".to_string(),
+                },
+            ]
+        );
+
+        // A "//" that isn't at the start of a line or after whitespace isn't a
+        // comment.
+        let pairs =
+            parse_key_values("BaseFilename=/data//*_gpubox\nhdf5Filename=a.h5 // comment\n")
+                .unwrap();
+        assert_eq!(pairs[0].value, "/data//*_gpubox");
+        assert_eq!(pairs[1].value, "a.h5");
+
+        assert!(matches!(
+            parse_key_values("FscrunchChan=2\nFscrunchChan=4\n"),
+            Err(RtsParseError::DuplicateKey {
+                line: 2,
+                first_line: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_key_values("FscrunchChan 2\n"),
+            Err(RtsParseError::MissingEquals { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let text = get_params(RtsMode::Patch)
+            .to_string()
+            .replace("SourceCatalogueFile=", "// SourceCatalogueFile=");
+        assert!(matches!(
+            text.parse::<RtsParams>(),
+            Err(RtsParseError::MissingKey("SourceCatalogueFile"))
+        ));
+    }
+}
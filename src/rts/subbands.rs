@@ -0,0 +1,205 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to handle non-contiguous ("picket fence") coarse channel selections.
+ *
+ * The RTS assumes that subband N has a frequency of ObservationFrequencyBase +
+ * (N - 1) * coarse channel width. This isn't true when the coarse channels
+ * aren't contiguous, so each contiguous group of coarse channels needs its own
+ * .in file with its own base frequency.
+ */
+
+/// The parts of a coarse channel that the RTS cares about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoarseChannel {
+    /// The receiver channel number (e.g. 131).
+    pub receiver_channel_number: u32,
+
+    /// The gpubox number of the channel (e.g. 1), i.e. its RTS subband ID.
+    pub gpubox_number: u8,
+
+    /// The frequency of the lower edge of the channel [Hz].
+    pub start_hz: u32,
+}
+
+/// A group of coarse channels with consecutive receiver channel numbers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubbandGroup {
+    /// SubBandIDs: The gpubox numbers of the channels in this group, sorted.
+    pub subband_ids: Vec<u8>,
+
+    /// ObservationFrequencyBase: The base frequency that places this group's
+    /// subbands at their true frequencies [MHz].
+    pub base_freq: f64,
+}
+
+/// The difference between ObservationFrequencyBase and the frequency of the
+/// lower edge of subband 1 [Hz]. The RTS's frequencies are the centres of the
+/// legacy correlator's native 10 kHz fine channels, so this is half of one of
+/// those channels. For contiguous observations, this gives the same base
+/// frequency as the metafits centre frequency - bandwidth / 2 - fine channel
+/// width / 2.
+pub const BASE_FREQ_OFFSET_HZ: u32 = 5_000;
+
+/// Split the coarse channels of an observation into groups with consecutive
+/// receiver channel numbers. If `subband_ids` is given, only the channels with
+/// those gpubox numbers are used.
+///
+/// Each group's base frequency is derived from the start frequency of its
+/// lowest channel, such that the group's subbands have their true frequencies.
+/// If `base_freq` is given, it is used as the base frequency of the
+/// observation's lowest channel (whether or not it's selected) [MHz], and each
+/// group's base frequency is offset from it.
+pub fn subband_groups(
+    channels: &[CoarseChannel],
+    subband_ids: Option<&[u8]>,
+    coarse_channel_width_hz: u32,
+    base_freq: Option<f64>,
+) -> Vec<SubbandGroup> {
+    let mut channels = channels.to_vec();
+    channels.sort_unstable_by_key(|c| c.receiver_channel_number);
+    // The base frequency of each group is relative to the observation's lowest
+    // channel, so find it before any channels are left out.
+    let lowest_start_hz = match channels.first() {
+        Some(c) => c.start_hz,
+        None => return vec![],
+    };
+    let base_freq =
+        base_freq.unwrap_or_else(|| (lowest_start_hz - BASE_FREQ_OFFSET_HZ) as f64 / 1e6);
+    if let Some(ids) = subband_ids {
+        channels.retain(|c| ids.contains(&c.gpubox_number));
+    }
+
+    let mut groups: Vec<Vec<CoarseChannel>> = vec![];
+    for c in channels {
+        match groups.last_mut() {
+            Some(g)
+                if g.last().unwrap().receiver_channel_number + 1 == c.receiver_channel_number =>
+            {
+                g.push(c)
+            }
+            _ => groups.push(vec![c]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|g| {
+            // Use the lowest-frequency channel of the group as a reference.
+            let reference = g[0];
+            let mut subband_ids: Vec<u8> = g.iter().map(|c| c.gpubox_number).collect();
+            subband_ids.sort_unstable();
+            // Work in Hz, so that groups with the same base frequency get
+            // exactly the same value.
+            let offset_hz = reference.start_hz as i64
+                - lowest_start_hz as i64
+                - (reference.gpubox_number as i64 - 1) * coarse_channel_width_hz as i64;
+            SubbandGroup {
+                subband_ids,
+                base_freq: base_freq + offset_hz as f64 / 1e6,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    fn channels(receiver_channel_numbers: &[u32]) -> Vec<CoarseChannel> {
+        receiver_channel_numbers
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| CoarseChannel {
+                receiver_channel_number: r,
+                gpubox_number: i as u8 + 1,
+                start_hz: r * 1_280_000 - 640_000,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_contiguous() {
+        let c = channels(&(131..=154).collect::<Vec<_>>());
+        let groups = subband_groups(&c, None, 1_280_000, None);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].subband_ids, (1..=24).collect::<Vec<_>>());
+        assert_abs_diff_eq!(groups[0].base_freq, 167.035, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_picket_fence() {
+        // Channels 62-63, 69 and 120-121.
+        let c = channels(&[62, 63, 69, 120, 121]);
+        let groups = subband_groups(&c, None, 1_280_000, Some(78.715));
+        assert_eq!(groups.len(), 3);
+
+        assert_eq!(groups[0].subband_ids, vec![1, 2]);
+        assert_abs_diff_eq!(groups[0].base_freq, 78.715, epsilon = 1e-9);
+
+        // Channel 69 is subband 3, so the base frequency must be two coarse
+        // channels below channel 69.
+        assert_eq!(groups[1].subband_ids, vec![3]);
+        assert_abs_diff_eq!(
+            groups[1].base_freq,
+            78.715 + 7.0 * 1.28 - 2.0 * 1.28,
+            epsilon = 1e-9
+        );
+
+        assert_eq!(groups[2].subband_ids, vec![4, 5]);
+        assert_abs_diff_eq!(
+            groups[2].base_freq,
+            78.715 + 58.0 * 1.28 - 3.0 * 1.28,
+            epsilon = 1e-9
+        );
+    }
+
+    #[test]
+    fn test_selection() {
+        // Leaving out subband 1 of a contiguous observation doesn't change the
+        // base frequency; subband 13 is still 12 coarse channels above it.
+        let c = channels(&(131..=154).collect::<Vec<_>>());
+        let ids: Vec<u8> = (13..=24).collect();
+        let groups = subband_groups(&c, Some(&ids), 1_280_000, None);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].subband_ids, ids);
+        assert_abs_diff_eq!(groups[0].base_freq, 167.035, epsilon = 1e-9);
+
+        // The same goes for a given base frequency.
+        let groups = subband_groups(&c, Some(&[2, 3, 7]), 1_280_000, Some(167.035));
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].subband_ids, vec![2, 3]);
+        assert_abs_diff_eq!(groups[0].base_freq, 167.035, epsilon = 1e-9);
+        assert_eq!(groups[1].subband_ids, vec![7]);
+        assert_abs_diff_eq!(groups[1].base_freq, 167.035, epsilon = 1e-9);
+
+        // Channel 69 of a picket fence is subband 3, so without channels 62 and
+        // 63, its base frequency is still two coarse channels below it.
+        let c = channels(&[62, 63, 69, 120, 121]);
+        let groups = subband_groups(&c, Some(&[3]), 1_280_000, None);
+        assert_eq!(groups.len(), 1);
+        assert_abs_diff_eq!(
+            groups[0].base_freq,
+            (69.0 - 2.0) * 1.28 - 0.64 - 0.005,
+            epsilon = 1e-9
+        );
+    }
+
+    #[test]
+    fn test_unsorted() {
+        let mut c = channels(&[62, 63, 69]);
+        c.reverse();
+        let groups = subband_groups(&c, None, 1_280_000, Some(78.715));
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].subband_ids, vec![1, 2]);
+        assert_eq!(groups[1].subband_ids, vec![3]);
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(subband_groups(&[], None, 1_280_000, None).is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to derive the RTS's timing parameters from an integration time.
+ */
+
+use super::error::RtsTimingError;
+use super::RtsMode;
+
+/// The default length of a patch calibration interval [seconds].
+pub const DEFAULT_PATCH_CADENCE: f64 = 64.0;
+/// The default length of a peel calibration interval [seconds].
+pub const DEFAULT_PEEL_CADENCE: f64 = 8.0;
+/// The most integration bins to use when patching.
+pub const MAX_PATCH_INTEGRATION_BINS: u32 = 7;
+/// The most integration bins to use when peeling.
+pub const MAX_PEEL_INTEGRATION_BINS: u32 = 5;
+
+/// NumberOfIntegrationBins for the legacy correlator's integration times,
+/// which have been tuned by hand for the default cadences. The columns are the
+/// integration time [milliseconds], the number of patch bins and the number of
+/// peel bins.
+const TUNED_NUM_INTEGRATION_BINS: &[(u64, u32, u32)] = &[(500, 7, 5), (1000, 7, 3), (2000, 6, 3)];
+
+/// The timing-related parameters of an RTS .in file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RtsTiming {
+    /// CorrDumpTime: The time resolution of the input data [seconds].
+    pub corr_dump_time: f64,
+
+    /// CorrDumpsPerCadence: The number of correlator dumps in each calibration
+    /// interval.
+    pub corr_dumps_per_cadence: u32,
+
+    /// NumberOfIntegrationBins: The number of baseline-dependent averaging
+    /// bins.
+    pub num_integration_bins: u32,
+}
+
+impl RtsTiming {
+    /// Derive the timing parameters for data with an integration time of
+    /// `integration_time_ms` calibrated every `cadence_ms`. The cadence must be
+    /// a multiple of the integration time.
+    pub fn new(
+        mode: RtsMode,
+        integration_time_ms: u64,
+        cadence_ms: u64,
+    ) -> Result<RtsTiming, RtsTimingError> {
+        if integration_time_ms == 0 || cadence_ms == 0 {
+            return Err(RtsTimingError::Zero {
+                integration_time_ms,
+                cadence_ms,
+            });
+        }
+        if cadence_ms % integration_time_ms != 0 {
+            return Err(RtsTimingError::CadenceNotMultiple {
+                integration_time_ms,
+                cadence_ms,
+            });
+        }
+
+        Ok(Self::with_corr_dumps_per_cadence(
+            mode,
+            integration_time_ms,
+            (cadence_ms / integration_time_ms) as u32,
+        ))
+    }
+
+    /// Derive the timing parameters for data with an integration time of
+    /// `integration_time_ms` and a known number of correlator dumps per
+    /// cadence.
+    ///
+    /// Each integration bin averages half as many correlator dumps as the bin
+    /// before it, starting from the whole cadence, so as many bins as evenly
+    /// halve `corr_dumps_per_cadence` are used (up to a mode-dependent
+    /// maximum). For the legacy correlator's integration times at the default
+    /// cadences, hand-tuned values are used instead.
+    pub fn with_corr_dumps_per_cadence(
+        mode: RtsMode,
+        integration_time_ms: u64,
+        corr_dumps_per_cadence: u32,
+    ) -> RtsTiming {
+        let (default_cadence, max_bins) = match mode {
+            RtsMode::Patch => (DEFAULT_PATCH_CADENCE, MAX_PATCH_INTEGRATION_BINS),
+            RtsMode::Peel { .. } => (DEFAULT_PEEL_CADENCE, MAX_PEEL_INTEGRATION_BINS),
+        };
+
+        let tuned = if corr_dumps_per_cadence as u64 * integration_time_ms
+            == (default_cadence * 1e3) as u64
+        {
+            TUNED_NUM_INTEGRATION_BINS
+                .iter()
+                .find(|(int_time, _, _)| *int_time == integration_time_ms)
+                .map(|(_, patch, peel)| match mode {
+                    RtsMode::Patch => *patch,
+                    RtsMode::Peel { .. } => *peel,
+                })
+        } else {
+            None
+        };
+
+        let num_integration_bins = tuned.unwrap_or_else(|| {
+            // The number of times the cadence can be halved, plus one.
+            let halvings = if corr_dumps_per_cadence == 0 {
+                0
+            } else {
+                corr_dumps_per_cadence.trailing_zeros()
+            };
+            (halvings + 1).min(max_bins)
+        });
+
+        RtsTiming {
+            corr_dump_time: integration_time_ms as f64 / 1e3,
+            corr_dumps_per_cadence,
+            num_integration_bins,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEEL: RtsMode = RtsMode::Peel {
+        num_cals: 1000,
+        num_peel: 1000,
+    };
+
+    fn timing(mode: RtsMode, integration_time_ms: u64) -> (f64, u32, u32) {
+        let cadence = match mode {
+            RtsMode::Patch => DEFAULT_PATCH_CADENCE,
+            RtsMode::Peel { .. } => DEFAULT_PEEL_CADENCE,
+        };
+        let t = RtsTiming::new(mode, integration_time_ms, (cadence * 1e3) as u64).unwrap();
+        (
+            t.corr_dump_time,
+            t.corr_dumps_per_cadence,
+            t.num_integration_bins,
+        )
+    }
+
+    #[test]
+    fn test_legacy_integration_times() {
+        assert_eq!(timing(RtsMode::Patch, 500), (0.5, 128, 7));
+        assert_eq!(timing(PEEL, 500), (0.5, 16, 5));
+        assert_eq!(timing(RtsMode::Patch, 1000), (1.0, 64, 7));
+        assert_eq!(timing(PEEL, 1000), (1.0, 8, 3));
+        assert_eq!(timing(RtsMode::Patch, 2000), (2.0, 32, 6));
+        assert_eq!(timing(PEEL, 2000), (2.0, 4, 3));
+    }
+
+    #[test]
+    fn test_mwax_integration_times() {
+        // 320 = 5 * 2^6
+        assert_eq!(timing(RtsMode::Patch, 200), (0.2, 320, 7));
+        // 40 = 5 * 2^3
+        assert_eq!(timing(PEEL, 200), (0.2, 40, 4));
+        assert_eq!(timing(RtsMode::Patch, 250), (0.25, 256, 7));
+        assert_eq!(timing(PEEL, 250), (0.25, 32, 5));
+        assert_eq!(timing(RtsMode::Patch, 4000), (4.0, 16, 5));
+        assert_eq!(timing(PEEL, 4000), (4.0, 2, 2));
+        assert_eq!(timing(RtsMode::Patch, 8000), (8.0, 8, 4));
+        assert_eq!(timing(PEEL, 8000), (8.0, 1, 1));
+    }
+
+    #[test]
+    fn test_other_cadences() {
+        // The tuned values don't apply to other cadences.
+        let t = RtsTiming::new(PEEL, 1000, 16000).unwrap();
+        assert_eq!(t.corr_dumps_per_cadence, 16);
+        assert_eq!(t.num_integration_bins, 5);
+
+        let t = RtsTiming::new(RtsMode::Patch, 500, 32000).unwrap();
+        assert_eq!(t.corr_dumps_per_cadence, 64);
+        assert_eq!(t.num_integration_bins, 7);
+    }
+
+    #[test]
+    fn test_bad_cadences() {
+        assert!(matches!(
+            RtsTiming::new(PEEL, 3000, 8000),
+            Err(RtsTimingError::CadenceNotMultiple { .. })
+        ));
+        assert!(matches!(
+            RtsTiming::new(PEEL, 0, 8000),
+            Err(RtsTimingError::Zero { .. })
+        ));
+        assert!(matches!(
+            RtsTiming::new(PEEL, 500, 0),
+            Err(RtsTimingError::Zero { .. })
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to check that the files an RTS .in file refers to exist.
+ */
+
+use std::path::{Path, PathBuf};
+
+use super::error::RtsFileProblem;
+use super::RtsParams;
+use crate::beam::fee::check_fee_beam_file;
+
+impl RtsParams {
+    /// Check that every file the RTS will open exists and belongs to this
+    /// observation. All of the problems found are returned; if there are none,
+    /// the returned vector is empty.
+    pub fn validate(&self) -> Vec<RtsFileProblem> {
+        let mut problems = vec![];
+
+        if let Some(m) = &self.metafits {
+            if !m.exists() {
+                problems.push(RtsFileProblem::MissingMetafits(m.clone()));
+            }
+        }
+        if !self.source_catalogue_file.exists() {
+            problems.push(RtsFileProblem::MissingSrclist(
+                self.source_catalogue_file.clone(),
+            ));
+        }
+        if let Some(f) = &self.fee_beam_file {
+            if !f.exists() {
+                problems.push(RtsFileProblem::MissingFeeBeam(f.clone()));
+            } else if let Err(e) = check_fee_beam_file(f) {
+                problems.push(RtsFileProblem::BadFeeBeam(e.to_string()));
+            }
+        }
+
+        match file_names(&self.base_dir) {
+            Ok(file_names) => self.validate_data(&file_names, &mut problems),
+            Err(_) => problems.push(RtsFileProblem::UnreadableBaseDir(self.base_dir.clone())),
+        }
+
+        problems
+    }
+
+    /// Check the visibility and flag files in the base directory, whose file
+    /// names are `file_names`.
+    fn validate_data(&self, file_names: &[String], problems: &mut Vec<RtsFileProblem>) {
+        // Each subband needs its own visibilities, unless they're all in one
+        // uvfits file.
+        let patterns: Vec<(Option<u8>, String)> =
+            if self.read_gpubox_direct || self.add_node_number_to_filename {
+                self.subband_ids
+                    .iter()
+                    .map(|&id| {
+                        let pattern = if self.read_gpubox_direct {
+                            format!("{}{:02}_*.fits", self.base_filename, id)
+                        } else {
+                            format!("{}{:02}.uvfits", self.base_filename, id)
+                        };
+                        (Some(id), pattern)
+                    })
+                    .collect()
+            } else {
+                vec![(None, format!("{}*.uvfits", self.base_filename))]
+            };
+        for (subband, pattern) in patterns {
+            let glob = match globset::Glob::new(&pattern) {
+                Ok(g) => g.compile_matcher(),
+                Err(_) => {
+                    problems.push(RtsFileProblem::BadBaseFilename(self.base_filename.clone()));
+                    return;
+                }
+            };
+            let matches: Vec<&String> = file_names.iter().filter(|f| glob.is_match(f)).collect();
+            if matches.is_empty() {
+                problems.push(RtsFileProblem::MissingVisibilities {
+                    subband,
+                    pattern: self.base_dir.join(pattern),
+                });
+            }
+            // gpubox files are named after their obsid.
+            if self.read_gpubox_direct {
+                let obsid_prefix = format!("{}_", self.obsid);
+                for f in matches {
+                    if !f.starts_with(&obsid_prefix) {
+                        problems.push(RtsFileProblem::WrongObsid {
+                            file: self.base_dir.join(f),
+                            obsid: self.obsid,
+                        });
+                    }
+                }
+            }
+        }
+
+        if self.use_cotter_flags {
+            for id in &self.subband_ids {
+                let mwaf = format!("RTS_{}_{:02}.mwaf", self.obsid, id);
+                if !file_names.contains(&mwaf) {
+                    problems.push(RtsFileProblem::MissingMwaf(self.base_dir.join(mwaf)));
+                }
+            }
+        }
+    }
+}
+
+/// Get the names of the files in a directory.
+fn file_names(dir: &Path) -> Result<Vec<String>, std::io::Error> {
+    let mut file_names = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path: PathBuf = entry?.path();
+        if let Some(f) = path.file_name() {
+            file_names.push(f.to_string_lossy().to_string());
+        }
+    }
+    Ok(file_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beam::fee::tests::write_fake_fee_beam_file;
+    use std::fs::File;
+
+    fn get_params() -> RtsParams {
+        r#"// RTS in file to patch obsid 1065880128
+FscrunchChan=2
+SubBandIDs=1,2
+BaseFilename=/data/*_gpubox
+ReadMetafitsFile=0
+ImportCotterFlags=1
+ImportCotterBasename=/data/RTS_1065880128
+CorrDumpTime=2.0
+CorrDumpsPerCadence=32
+NumberOfIntegrationBins=6
+NumberOfIterations=1
+ObservationFrequencyBase=138.875
+NumberOfChannels=32
+ChannelBandwidth=0.04
+ObservationImageCentreRA=0
+ObservationImageCentreDec=-27
+SourceCatalogueFile=srclist.txt
+NumberOfCalibrators=1
+"#
+        .parse()
+        .unwrap()
+    }
+
+    fn touch(dir: &Path, file_name: &str) {
+        File::create(dir.join(file_name)).unwrap();
+    }
+
+    #[test]
+    fn test_validate_gpubox() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut params = get_params();
+        params.base_dir = dir.path().to_path_buf();
+        params.base_filename = "*_gpubox".to_string();
+        params.read_gpubox_direct = true;
+        params.use_cotter_flags = true;
+        params.subband_ids = vec![1, 2];
+        params.metafits = None;
+        params.fee_beam_file = Some(dir.path().join("mwa_full_embedded_element_pattern.h5"));
+        params.source_catalogue_file = dir.path().join("srclist.txt");
+
+        // Everything is missing.
+        let problems = params.validate();
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+        assert!(problems.contains(&RtsFileProblem::MissingMwaf(
+            dir.path().join("RTS_1065880128_02.mwaf")
+        )));
+        assert!(problems.contains(&RtsFileProblem::MissingVisibilities {
+            subband: Some(2),
+            pattern: dir.path().join("*_gpubox02_*.fits"),
+        }));
+
+        touch(dir.path(), "srclist.txt");
+        touch(dir.path(), "mwa_full_embedded_element_pattern.h5");
+        touch(dir.path(), "1065880128_20131015134830_gpubox01_00.fits");
+        touch(dir.path(), "1065880128_20131015134830_gpubox02_00.fits");
+        touch(dir.path(), "RTS_1065880128_01.mwaf");
+        touch(dir.path(), "RTS_1065880128_02.mwaf");
+        // The FEE beam file is there, but empty.
+        match params.validate().as_slice() {
+            [RtsFileProblem::BadFeeBeam(e)] => assert!(e.contains("not an HDF5 file"), "{}", e),
+            p => panic!("Unexpected problems {:?}", p),
+        }
+        write_fake_fee_beam_file(&dir.path().join("mwa_full_embedded_element_pattern.h5"));
+        assert_eq!(params.validate(), vec![]);
+
+        // A gpubox file from another observation.
+        touch(dir.path(), "1065880248_20131015135030_gpubox02_00.fits");
+        assert_eq!(
+            params.validate(),
+            vec![RtsFileProblem::WrongObsid {
+                file: dir
+                    .path()
+                    .join("1065880248_20131015135030_gpubox02_00.fits"),
+                obsid: 1065880128,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_uvfits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut params = get_params();
+        params.base_dir = dir.path().to_path_buf();
+        params.base_filename = "1098108248_band".to_string();
+        params.read_gpubox_direct = false;
+        params.add_node_number_to_filename = true;
+        params.use_cotter_flags = false;
+        params.subband_ids = vec![1, 2];
+        params.metafits = None;
+        params.fee_beam_file = None;
+        params.source_catalogue_file = dir.path().join("srclist.txt");
+        touch(dir.path(), "srclist.txt");
+        touch(dir.path(), "1098108248_band01.uvfits");
+
+        assert_eq!(
+            params.validate(),
+            vec![RtsFileProblem::MissingVisibilities {
+                subband: Some(2),
+                pattern: dir.path().join("1098108248_band02.uvfits"),
+            }]
+        );
+        touch(dir.path(), "1098108248_band02.uvfits");
+        assert_eq!(params.validate(), vec![]);
+
+        params.base_dir = dir.path().join("nowhere");
+        assert_eq!(
+            params.validate(),
+            vec![RtsFileProblem::UnreadableBaseDir(
+                dir.path().join("nowhere")
+            )]
+        );
+    }
+}
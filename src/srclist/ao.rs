@@ -0,0 +1,691 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to handle "skymodel" source lists, as used by André Offringa's tools
+ * (e.g. calibrate), e.g.
+ *
+ * ```text
+ * skymodel fileformat 1.1
+ * source {
+ *   name "J000042-342358"
+ *   component {
+ *     type gaussian
+ *     position 0h00m42.000000s -34d23m58.00000s
+ *     shape 20 10 75
+ *     sed {
+ *       frequency 180 MHz
+ *       fluxdensity Jy 1 0 0 0
+ *       spectral-index { -0.8 0.1 }
+ *     }
+ *   }
+ * }
+ * ```
+ *
+ * Gaussian shapes are the major and minor axes [arcseconds] and the position
+ * angle [degrees]. A component's flux densities are either a number of
+ * "measurement" blocks (each with a frequency and flux density, read as a list)
+ * or a single "sed" block. The terms of a spectral index are the power law's
+ * spectral index and, optionally, its curvature (see `FluxDensityType`).
+ * Shapelets aren't supported. Everything after a "#" is a comment.
+ */
+
+use std::io::Write;
+use std::str::FromStr;
+
+use super::error::{ReadSourceListError, WriteSourceListError};
+use super::*;
+
+/// The tokens of an AO source list, and where we're up to.
+struct Tokens {
+    /// Each token and the line it's on.
+    tokens: Vec<(usize, String)>,
+    next: usize,
+}
+
+impl Tokens {
+    fn new(s: &str) -> Self {
+        let mut tokens = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let text = match line.find('#') {
+                Some(j) => &line[..j],
+                None => line,
+            };
+            let mut chars = text.char_indices().peekable();
+            while let Some((j, c)) = chars.next() {
+                if c.is_whitespace() || c == ',' {
+                    continue;
+                }
+                let token = if c == '"' {
+                    // Quoted strings can contain spaces; the quotes aren't
+                    // kept.
+                    let mut end = text.len();
+                    for (k, c) in &mut chars {
+                        if c == '"' {
+                            end = k;
+                            break;
+                        }
+                    }
+                    &text[j + 1..end]
+                } else if c == '{' || c == '}' {
+                    &text[j..j + 1]
+                } else {
+                    let mut end = text.len();
+                    while let Some(&(k, c)) = chars.peek() {
+                        if c.is_whitespace() || c == ',' || c == '{' || c == '}' {
+                            end = k;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    &text[j..end]
+                };
+                tokens.push((i + 1, token.to_string()));
+            }
+        }
+        Self { tokens, next: 0 }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(|(_, t)| t.as_str())
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<(usize, String), ReadSourceListError> {
+        match self.tokens.get(self.next) {
+            Some(t) => {
+                self.next += 1;
+                Ok(t.clone())
+            }
+            None => Err(ReadSourceListError::UnexpectedEnd { expected }),
+        }
+    }
+
+    /// Get the next token, which must be `token`.
+    fn expect(&mut self, token: &'static str) -> Result<usize, ReadSourceListError> {
+        let (line, t) = self.next(token)?;
+        if t == token {
+            Ok(line)
+        } else {
+            Err(ReadSourceListError::UnexpectedToken {
+                line,
+                expected: token,
+                got: t,
+            })
+        }
+    }
+
+    fn parse<T: FromStr>(
+        &mut self,
+        keyword: &str,
+        expected: &'static str,
+    ) -> Result<T, ReadSourceListError> {
+        let (line, t) = self.next(expected)?;
+        t.parse().map_err(|_| ReadSourceListError::BadValue {
+            line,
+            keyword: keyword.to_string(),
+            value: t,
+            expected,
+        })
+    }
+}
+
+/// Parse the contents of an AO-format source list.
+pub fn parse_source_list(s: &str) -> Result<Vec<Source>, ReadSourceListError> {
+    let mut tokens = Tokens::new(s);
+    if tokens.peek().is_none() {
+        return Ok(vec![]);
+    }
+    tokens.expect("skymodel")?;
+    tokens.expect("fileformat")?;
+    let (line, version) = tokens.next("a file format version")?;
+    if version != "1.0" && version != "1.1" {
+        return Err(ReadSourceListError::BadValue {
+            line,
+            keyword: "fileformat".to_string(),
+            value: version,
+            expected: "1.0 or 1.1",
+        });
+    }
+
+    let mut sources = vec![];
+    while tokens.peek().is_some() {
+        let start = tokens.expect("source")?;
+        tokens.expect("{")?;
+        let mut name = None;
+        let mut components = vec![];
+        loop {
+            let (line, t) = tokens.next("name, component or }")?;
+            match t.as_str() {
+                "name" => name = Some(tokens.next("a source name")?.1),
+                "component" => components.push(parse_component(&mut tokens, line)?),
+                "}" => break,
+                _ => {
+                    return Err(ReadSourceListError::UnexpectedToken {
+                        line,
+                        expected: "name, component or }",
+                        got: t,
+                    })
+                }
+            }
+        }
+        let name = match name {
+            Some(n) => n,
+            None => {
+                return Err(ReadSourceListError::MissingField {
+                    line: start,
+                    block: "source",
+                    field: "name",
+                })
+            }
+        };
+        if components.is_empty() {
+            return Err(ReadSourceListError::NoComponents { name });
+        }
+        sources.push(Source { name, components });
+    }
+    Ok(sources)
+}
+
+fn parse_component(
+    tokens: &mut Tokens,
+    start: usize,
+) -> Result<SourceComponent, ReadSourceListError> {
+    tokens.expect("{")?;
+    let mut comp_type = None;
+    let mut position = None;
+    let mut shape = None;
+    let mut measurements = vec![];
+    let mut sed = None;
+    loop {
+        let (line, t) = tokens.next("a component field or }")?;
+        match t.as_str() {
+            "type" => {
+                let (line, t) = tokens.next("a component type")?;
+                if t != "point" && t != "gaussian" {
+                    return Err(ReadSourceListError::UnexpectedToken {
+                        line,
+                        expected: "point or gaussian",
+                        got: t,
+                    });
+                }
+                comp_type = Some(t);
+            }
+            "position" => {
+                let (line, ra) = tokens.next("an RA")?;
+                let ra = parse_hms(&ra).ok_or_else(|| ReadSourceListError::BadValue {
+                    line,
+                    keyword: "position".to_string(),
+                    value: ra,
+                    expected: "an RA like 22h14m25.752s",
+                })?;
+                let (line, dec) = tokens.next("a Dec")?;
+                let dec = parse_dms(&dec).ok_or_else(|| ReadSourceListError::BadValue {
+                    line,
+                    keyword: "position".to_string(),
+                    value: dec,
+                    expected: "a Dec like -17d01m36.29s",
+                })?;
+                position = Some((ra, dec));
+            }
+            "shape" => {
+                let maj = tokens.parse("shape", "a major axis")?;
+                let min = tokens.parse("shape", "a minor axis")?;
+                let pa = tokens.parse("shape", "a position angle")?;
+                shape = Some((line, ComponentType::Gaussian { maj, min, pa }));
+            }
+            "measurement" => {
+                let (fd, _) = parse_flux_density_block(tokens, line, "measurement", false)?;
+                measurements.push(fd);
+            }
+            "sed" => {
+                if sed.is_some() {
+                    return Err(ReadSourceListError::UnexpectedToken {
+                        line,
+                        expected: "only one sed per component",
+                        got: t,
+                    });
+                }
+                sed = Some(parse_flux_density_block(tokens, line, "sed", true)?);
+            }
+            "}" => break,
+            _ => {
+                return Err(ReadSourceListError::UnexpectedToken {
+                    line,
+                    expected: "a component field or }",
+                    got: t,
+                })
+            }
+        }
+    }
+
+    let missing = |field| ReadSourceListError::MissingField {
+        line: start,
+        block: "component",
+        field,
+    };
+    let (ra, dec) = position.ok_or_else(|| missing("position"))?;
+    let comp_type = match (comp_type.as_deref(), shape) {
+        (None, _) => return Err(missing("type")),
+        (Some("point"), None) => ComponentType::Point,
+        (Some("point"), Some((line, _))) => {
+            return Err(ReadSourceListError::UnexpectedToken {
+                line,
+                expected: "no shape for a point component",
+                got: "shape".to_string(),
+            })
+        }
+        (_, None) => return Err(missing("shape")),
+        (_, Some((_, shape))) => shape,
+    };
+    let flux_type = match (sed, measurements.is_empty()) {
+        (None, true) => return Err(missing("measurement or sed")),
+        (None, false) => FluxDensityType::List(measurements),
+        (Some((fd, spec_index)), true) => match spec_index.as_slice() {
+            [si] => FluxDensityType::PowerLaw { si: *si, fd },
+            [si, q] => FluxDensityType::CurvedPowerLaw { si: *si, fd, q: *q },
+            _ => {
+                return Err(ReadSourceListError::UnexpectedToken {
+                    line: start,
+                    expected: "one or two spectral index terms",
+                    got: format!("{:?}", spec_index),
+                })
+            }
+        },
+        (Some(_), false) => {
+            return Err(ReadSourceListError::UnexpectedToken {
+                line: start,
+                expected: "either measurements or a sed",
+                got: "both".to_string(),
+            })
+        }
+    };
+    Ok(SourceComponent {
+        ra,
+        dec,
+        comp_type,
+        flux_type,
+    })
+}
+
+/// Parse a "measurement" or "sed" block. Only seds have spectral index terms.
+fn parse_flux_density_block(
+    tokens: &mut Tokens,
+    start: usize,
+    block: &'static str,
+    is_sed: bool,
+) -> Result<(FluxDensity, Vec<f64>), ReadSourceListError> {
+    tokens.expect("{")?;
+    let mut freq = None;
+    let mut stokes = None;
+    let mut spec_index = vec![];
+    loop {
+        let (line, t) = tokens.next("a frequency, flux density or }")?;
+        match t.as_str() {
+            "frequency" => {
+                let f: f64 = tokens.parse("frequency", "a frequency")?;
+                let (line, unit) = tokens.next("a frequency unit")?;
+                let scale = match unit.as_str() {
+                    "Hz" => 1.0,
+                    "kHz" => 1e3,
+                    "MHz" => 1e6,
+                    "GHz" => 1e9,
+                    _ => {
+                        return Err(ReadSourceListError::UnexpectedToken {
+                            line,
+                            expected: "Hz, kHz, MHz or GHz",
+                            got: unit,
+                        })
+                    }
+                };
+                freq = Some(f * scale);
+            }
+            "fluxdensity" => {
+                tokens.expect("Jy")?;
+                let i = tokens.parse("fluxdensity", "a flux density")?;
+                let q = tokens.parse("fluxdensity", "a flux density")?;
+                let u = tokens.parse("fluxdensity", "a flux density")?;
+                let v = tokens.parse("fluxdensity", "a flux density")?;
+                stokes = Some((i, q, u, v));
+            }
+            "spectral-index" if is_sed => {
+                tokens.expect("{")?;
+                while tokens.peek() != Some("}") {
+                    spec_index.push(tokens.parse("spectral-index", "a spectral index term")?);
+                }
+                tokens.expect("}")?;
+            }
+            "}" => break,
+            _ => {
+                return Err(ReadSourceListError::UnexpectedToken {
+                    line,
+                    expected: "a frequency, flux density or }",
+                    got: t,
+                })
+            }
+        }
+    }
+
+    let missing = |field| ReadSourceListError::MissingField {
+        line: start,
+        block,
+        field,
+    };
+    let freq = freq.ok_or_else(|| missing("frequency"))?;
+    let (i, q, u, v) = stokes.ok_or_else(|| missing("fluxdensity"))?;
+    if is_sed && spec_index.is_empty() {
+        return Err(missing("spectral-index"));
+    }
+    Ok((FluxDensity { freq, i, q, u, v }, spec_index))
+}
+
+/// Parse an RA like "22h14m25.752s" into degrees.
+fn parse_hms(s: &str) -> Option<f64> {
+    if !s.ends_with('s') {
+        return None;
+    }
+    let (h, rest) = s.split_at(s.find('h')?);
+    let (m, rest) = rest[1..].split_at(rest.find('m')? - 1);
+    let sec = &rest[1..rest.len() - 1];
+    let h: u32 = h.parse().ok()?;
+    let m: u32 = m.parse().ok()?;
+    let sec: f64 = sec.parse().ok()?;
+    if h >= 24 || m >= 60 || !(0.0..60.0).contains(&sec) {
+        return None;
+    }
+    Some((h as f64 + m as f64 / 60.0 + sec / 3600.0) * 15.0)
+}
+
+/// Parse a Dec like "-17d01m36.29s" into degrees.
+fn parse_dms(s: &str) -> Option<f64> {
+    if !s.ends_with('s') {
+        return None;
+    }
+    let (sign, s) = match s.chars().next() {
+        Some('-') => (-1.0, s.split_at(1).1),
+        Some('+') => (1.0, s.split_at(1).1),
+        _ => (1.0, s),
+    };
+    let (d, rest) = s.split_at(s.find('d')?);
+    let (m, rest) = rest[1..].split_at(rest.find('m')? - 1);
+    let sec = &rest[1..rest.len() - 1];
+    let d: u32 = d.parse().ok()?;
+    let m: u32 = m.parse().ok()?;
+    let sec: f64 = sec.parse().ok()?;
+    let dec = d as f64 + m as f64 / 60.0 + sec / 3600.0;
+    if m >= 60 || !(0.0..60.0).contains(&sec) || dec > 90.0 {
+        return None;
+    }
+    Some(sign * dec)
+}
+
+/// Format an RA [degrees] like "22h14m25.752000s".
+fn format_hms(ra: f64) -> String {
+    // Work in microseconds of time, so that rounding can't give 60 seconds.
+    let t = (ra.rem_euclid(360.0) / 15.0 * 3600.0 * 1e6).round() as u64 % (24 * 3600 * 1_000_000);
+    format!(
+        "{}h{:02}m{:09.6}s",
+        t / 3_600_000_000,
+        t / 60_000_000 % 60,
+        (t % 60_000_000) as f64 / 1e6
+    )
+}
+
+/// Format a Dec [degrees] like "-17d01m36.29000s".
+fn format_dms(dec: f64) -> String {
+    // Work in units of 10 microarcseconds.
+    let t = (dec.abs() * 3600.0 * 1e5).round() as u64;
+    format!(
+        "{}{}d{:02}m{:08.5}s",
+        if dec < 0.0 && t > 0 { "-" } else { "" },
+        t / 360_000_000,
+        t / 6_000_000 % 60,
+        (t % 6_000_000) as f64 / 1e5
+    )
+}
+
+/// Write sources in the AO source list format.
+pub fn write_source_list<W: Write>(
+    writer: &mut W,
+    sources: &[Source],
+) -> Result<(), WriteSourceListError> {
+    writeln!(writer, "skymodel fileformat 1.1")?;
+    for src in sources {
+        if src.name.is_empty() || src.name.contains(&['"', '\n'][..]) {
+            return Err(WriteSourceListError::BadName(src.name.clone()));
+        }
+        if src.components.is_empty() {
+            return Err(WriteSourceListError::NoComponents(src.name.clone()));
+        }
+        writeln!(writer, "source {{")?;
+        writeln!(writer, "  name \"{}\"", src.name)?;
+        for comp in &src.components {
+            writeln!(writer, "  component {{")?;
+            match &comp.comp_type {
+                ComponentType::Point => writeln!(writer, "    type point")?,
+                ComponentType::Gaussian { .. } => writeln!(writer, "    type gaussian")?,
+                ComponentType::Shapelet { .. } => {
+                    return Err(WriteSourceListError::UnsupportedShapelet(src.name.clone()))
+                }
+            }
+            writeln!(
+                writer,
+                "    position {} {}",
+                format_hms(comp.ra),
+                format_dms(comp.dec)
+            )?;
+            if let ComponentType::Gaussian { maj, min, pa } = comp.comp_type {
+                writeln!(writer, "    shape {} {} {}", maj, min, pa)?;
+            }
+            match &comp.flux_type {
+                FluxDensityType::List(fds) => {
+                    if fds.is_empty() {
+                        return Err(WriteSourceListError::NoFluxDensities(src.name.clone()));
+                    }
+                    for fd in fds {
+                        writeln!(writer, "    measurement {{")?;
+                        write_flux_density(writer, fd)?;
+                        writeln!(writer, "    }}")?;
+                    }
+                }
+                FluxDensityType::PowerLaw { si, fd } => {
+                    writeln!(writer, "    sed {{")?;
+                    write_flux_density(writer, fd)?;
+                    writeln!(writer, "      spectral-index {{ {} }}", si)?;
+                    writeln!(writer, "    }}")?;
+                }
+                FluxDensityType::CurvedPowerLaw { si, fd, q } => {
+                    writeln!(writer, "    sed {{")?;
+                    write_flux_density(writer, fd)?;
+                    writeln!(writer, "      spectral-index {{ {} {} }}", si, q)?;
+                    writeln!(writer, "    }}")?;
+                }
+            }
+            writeln!(writer, "  }}")?;
+        }
+        writeln!(writer, "}}")?;
+    }
+    Ok(())
+}
+
+fn write_flux_density<W: Write>(writer: &mut W, fd: &FluxDensity) -> Result<(), std::io::Error> {
+    writeln!(writer, "      frequency {} MHz", fd.freq / 1e6)?;
+    writeln!(
+        writer,
+        "      fluxdensity Jy {} {} {} {}",
+        fd.i, fd.q, fd.u, fd.v
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srclist::tests::assert_sources_eq;
+    use approx::*;
+
+    const SRCLIST: &str = r#"skymodel fileformat 1.1
+# A point source and a Gaussian source.
+source {
+  name "3C444 A"
+  component {
+    type point
+    position 22h14m25.752s -17d01m36.29s
+    measurement {
+      frequency 150 MHz
+      fluxdensity Jy 60.0 0 0 0
+    }
+    measurement {
+      frequency 200 MHz
+      fluxdensity Jy 50.0 0 0 0
+    }
+  }
+}
+source {
+  name "gaussian"
+  component {
+    type gaussian
+    position 0h30m00s -0d30m00s
+    shape 140.76 105.12 146.7
+    sed {
+      frequency 200 MHz
+      fluxdensity Jy 10.0 0 0 0
+      spectral-index { -0.79, 0.1 }
+    }
+  }
+  component {
+    type point
+    position 0h31m00s -0d31m00s
+    sed {
+      frequency 0.2 GHz
+      fluxdensity Jy 1.0 0 0 0
+      spectral-index { -0.79 }
+    }
+  }
+}
+"#;
+
+    #[test]
+    fn test_parse_source_list() {
+        let sources = parse_source_list(SRCLIST).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].name, "3C444 A");
+        let comp = &sources[0].components[0];
+        assert_abs_diff_eq!(comp.ra, (22.0 + 14.0 / 60.0 + 25.752 / 3600.0) * 15.0);
+        assert_abs_diff_eq!(comp.dec, -(17.0 + 1.0 / 60.0 + 36.29 / 3600.0));
+        assert!(matches!(&comp.flux_type, FluxDensityType::List(fds) if fds.len() == 2));
+
+        let comps = &sources[1].components;
+        assert_abs_diff_eq!(comps[0].dec, -0.5);
+        assert_eq!(
+            comps[0].comp_type,
+            ComponentType::Gaussian {
+                maj: 140.76,
+                min: 105.12,
+                pa: 146.7
+            }
+        );
+        assert!(matches!(
+            comps[0].flux_type,
+            FluxDensityType::CurvedPowerLaw { si, q, .. } if si == -0.79 && q == 0.1
+        ));
+        match &comps[1].flux_type {
+            FluxDensityType::PowerLaw { si, fd } => {
+                assert_abs_diff_eq!(*si, -0.79);
+                assert_abs_diff_eq!(fd.freq, 200e6);
+            }
+            _ => panic!("Expected a power law"),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = parse_source_list(SRCLIST).unwrap();
+        let mut written = vec![];
+        write_source_list(&mut written, &sources).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("    position 0h30m00.000000s -0d30m00.00000s\n"));
+        assert_sources_eq(&parse_source_list(&written).unwrap(), &sources);
+
+        // Converting from a hyperdrive source list and back.
+        let hyp = crate::srclist::hyperdrive::parse_source_list(
+            "a:\n  - ra: 359.9999999999\n    dec: 89.5\n    comp_type: point\n    flux_type:\n      curved_power_law:\n        si: -0.5\n        q: 0.2\n        fd: {freq: 182415000.0, i: 1.5, q: 0.1, u: 0.2, v: 0.3}\n",
+        )
+        .unwrap();
+        let mut written = vec![];
+        write_source_list(&mut written, &hyp).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("position 0h00m00.000000s 89d30m00.00000s\n"));
+        let read = parse_source_list(&written).unwrap();
+        assert_abs_diff_eq!(read[0].components[0].ra, 0.0);
+        assert_eq!(
+            read[0].components[0].flux_type,
+            hyp[0].components[0].flux_type
+        );
+    }
+
+    #[test]
+    fn test_shapelets_unsupported() {
+        let rts = crate::srclist::rts::parse_source_list(
+            "SOURCE a 0.5 -27.0\nSHAPELET2 0 1 1\nCOEFF 0 0 1.0\nFREQ 180e6 1 0 0 0\nENDSOURCE\n",
+        )
+        .unwrap();
+        let mut written = vec![];
+        assert!(matches!(
+            write_source_list(&mut written, &rts),
+            Err(WriteSourceListError::UnsupportedShapelet(_))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_source_lists() {
+        assert_eq!(parse_source_list("# Nothing here\n"), Ok(vec![]));
+        assert_eq!(
+            parse_source_list("[package]\nname = \"mongoose\"\n"),
+            Err(ReadSourceListError::UnexpectedToken {
+                line: 1,
+                expected: "skymodel",
+                got: "[package]".to_string()
+            })
+        );
+        assert_eq!(
+            parse_source_list(&SRCLIST[..SRCLIST.len() - 3]),
+            Err(ReadSourceListError::UnexpectedEnd {
+                expected: "name, component or }"
+            })
+        );
+        assert!(matches!(
+            parse_source_list(&SRCLIST.replace("22h14m25.752s", "22:14:25.752")),
+            Err(ReadSourceListError::BadValue { line: 7, .. })
+        ));
+        assert_eq!(
+            parse_source_list(&SRCLIST.replace("    shape 140.76 105.12 146.7\n", "")),
+            Err(ReadSourceListError::MissingField {
+                line: 20,
+                block: "component",
+                field: "shape"
+            })
+        );
+        assert_eq!(
+            parse_source_list(&SRCLIST.replace("      spectral-index { -0.79 }\n", "")),
+            Err(ReadSourceListError::MissingField {
+                line: 33,
+                block: "sed",
+                field: "spectral-index"
+            })
+        );
+    }
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(format_hms(0.0), "0h00m00.000000s");
+        assert_eq!(format_hms(359.99999999999), "0h00m00.000000s");
+        assert_eq!(format_hms(-15.0), "23h00m00.000000s");
+        assert_eq!(format_dms(-0.5), "-0d30m00.00000s");
+        assert_eq!(format_dms(-1e-12), "0d00m00.00000s");
+        assert_abs_diff_eq!(parse_dms("-0d30m00s").unwrap(), -0.5);
+        assert_abs_diff_eq!(parse_dms("+45d00m00s").unwrap(), 45.0);
+        assert!(parse_hms("24h00m00s").is_none());
+        assert!(parse_dms("91d00m00s").is_none());
+        assert!(parse_dms("-17d01m").is_none());
+    }
+}
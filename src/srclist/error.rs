@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Error handling for source lists.
+ */
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ReadSourceListError {
+    /// The first word of a line isn't an RTS source list keyword.
+    #[error("Line {line}: Unknown source list keyword '{keyword}'")]
+    UnknownKeyword { line: usize, keyword: String },
+
+    /// A keyword was followed by the wrong number of values.
+    #[error("Line {line}: {keyword} expects {expected} values, but got {got}")]
+    WrongNumberOfValues {
+        line: usize,
+        keyword: String,
+        expected: usize,
+        got: usize,
+    },
+
+    /// A value couldn't be parsed as the expected type.
+    #[error("Line {line}: Could not parse '{value}' as {expected} for {keyword}")]
+    BadValue {
+        line: usize,
+        keyword: String,
+        value: String,
+        expected: &'static str,
+    },
+
+    /// An RA wasn't between 0 and 24 hours.
+    #[error("Line {line}: RA {ra} is not between 0 and 24 hours")]
+    BadRa { line: usize, ra: f64 },
+
+    /// A Dec wasn't between -90 and 90 degrees.
+    #[error("Line {line}: Dec {dec} is not between -90 and 90 degrees")]
+    BadDec { line: usize, dec: f64 },
+
+    /// A keyword that describes a source appeared outside of a SOURCE block.
+    #[error("Line {line}: {keyword} found outside of a SOURCE block")]
+    OutsideSource { line: usize, keyword: String },
+
+    /// A SOURCE block started before the previous one ended.
+    #[error(
+        "Line {line}: SOURCE found before the SOURCE on line {start} was ended with ENDSOURCE"
+    )]
+    UnterminatedSource { line: usize, start: usize },
+
+    /// The source list ended inside a SOURCE block.
+    #[error("The SOURCE on line {start} was not ended with ENDSOURCE")]
+    MissingEndSource { start: usize },
+
+    /// A COMPONENT or ENDSOURCE appeared before a COMPONENT block ended.
+    #[error("Line {line}: {keyword} found before the COMPONENT on line {start} was ended with ENDCOMPONENT")]
+    UnterminatedComponent {
+        line: usize,
+        keyword: String,
+        start: usize,
+    },
+
+    /// An ENDCOMPONENT appeared without a COMPONENT.
+    #[error("Line {line}: ENDCOMPONENT found without a COMPONENT")]
+    UnmatchedEndComponent { line: usize },
+
+    /// A component has no flux densities.
+    #[error("The component on line {start} has no FREQ lines")]
+    NoFluxDensities { start: usize },
+
+    /// A component was given more than one GAUSSIAN or SHAPELET shape.
+    #[error("Line {line}: The component on line {start} already has a GAUSSIAN or SHAPELET shape")]
+    MultipleShapes { line: usize, start: usize },
+
+    /// A COEFF appeared in a component that isn't a shapelet.
+    #[error("Line {line}: COEFF found in the component on line {start}, which is not a SHAPELET")]
+    CoeffWithoutShapelet { line: usize, start: usize },
+
+    /// A source has no components.
+    #[error("Source '{name}' has no components")]
+    NoComponents { name: String },
+
+    /// A component of a source has an empty list of flux densities.
+    #[error("A component of source '{name}' has no flux densities")]
+    EmptyFluxDensityList { name: String },
+
+    /// A YAML source list couldn't be deserialised.
+    #[error("{0}")]
+    Yaml(String),
+
+    /// Something other than what was expected was found in an AO source list.
+    #[error("Line {line}: Expected {expected}, but got '{got}'")]
+    UnexpectedToken {
+        line: usize,
+        expected: &'static str,
+        got: String,
+    },
+
+    /// An AO source list ended before a block did.
+    #[error("Expected {expected}, but the source list ended")]
+    UnexpectedEnd { expected: &'static str },
+
+    /// A block in an AO source list is missing something it needs.
+    #[error("The {block} on line {line} has no {field}")]
+    MissingField {
+        line: usize,
+        block: &'static str,
+        field: &'static str,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum WriteSourceListError {
+    /// A source name would not be read back correctly.
+    #[error("Source name '{0}' is empty or has characters that can't be written in this format")]
+    BadName(String),
+
+    /// More than one source has the same name, which the format can't
+    /// represent.
+    #[error("More than one source is named '{0}'")]
+    DuplicateName(String),
+
+    /// A shapelet component can't be written in the AO format.
+    #[error("Source '{0}' has a shapelet component, which can't be written in the AO format")]
+    UnsupportedShapelet(String),
+
+    /// A source has no components.
+    #[error("Source '{0}' has no components")]
+    NoComponents(String),
+
+    /// A component has no flux densities.
+    #[error("A component of source '{0}' has no flux densities")]
+    NoFluxDensities(String),
+
+    /// An error associated with serde_yaml.
+    #[error("{0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    /// An IO error.
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+}
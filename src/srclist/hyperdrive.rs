@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to handle hyperdrive-format YAML source lists (also used by WODEN).
+ *
+ * Each source name is a key for a list of components, e.g.
+ *
+ * ```yaml
+ * J000042-342358:
+ *   - ra: 0.175
+ *     dec: -34.39944
+ *     comp_type:
+ *       gaussian:
+ *         maj: 20.0
+ *         min: 10.0
+ *         pa: 75.0
+ *     flux_type:
+ *       power_law:
+ *         si: -0.8
+ *         fd:
+ *           freq: 180000000.0
+ *           i: 1.0
+ *           q: 0.0
+ *           u: 0.0
+ *           v: 0.0
+ * ```
+ *
+ * The units are the same as those of `SourceComponent`. A component's
+ * `comp_type` is `point`, `gaussian` or `shapelet` (which also has `coeffs`),
+ * and its `flux_type` is a `list` of flux densities, a `power_law` or a
+ * `curved_power_law` (which also has `q`).
+ */
+
+use std::io::Write;
+
+use serde_yaml::{Mapping, Value};
+
+use super::error::{ReadSourceListError, WriteSourceListError};
+use super::*;
+
+/// Parse the contents of a hyperdrive-format YAML source list.
+pub fn parse_source_list(s: &str) -> Result<Vec<Source>, ReadSourceListError> {
+    // serde_yaml doesn't accept a document without any content.
+    if s.lines()
+        .map(|l| l.trim())
+        .all(|l| l.is_empty() || l.starts_with('#') || l == "---")
+    {
+        return Ok(vec![]);
+    }
+    let map: Mapping =
+        serde_yaml::from_str(s).map_err(|e| ReadSourceListError::Yaml(e.to_string()))?;
+
+    let mut sources = Vec::with_capacity(map.len());
+    for (name, components) in map {
+        let name = match name {
+            Value::String(n) => n,
+            n => {
+                return Err(ReadSourceListError::Yaml(format!(
+                    "Source names must be strings, but got {:?}",
+                    n
+                )))
+            }
+        };
+        let components: Vec<SourceComponent> = serde_yaml::from_value(components)
+            .map_err(|e| ReadSourceListError::Yaml(format!("Source '{}': {}", name, e)))?;
+        if components.is_empty() {
+            return Err(ReadSourceListError::NoComponents { name });
+        }
+        if components
+            .iter()
+            .any(|c| matches!(&c.flux_type, FluxDensityType::List(fds) if fds.is_empty()))
+        {
+            return Err(ReadSourceListError::EmptyFluxDensityList { name });
+        }
+        sources.push(Source { name, components });
+    }
+    Ok(sources)
+}
+
+/// Write sources in the hyperdrive YAML source list format.
+pub fn write_source_list<W: Write>(
+    writer: &mut W,
+    sources: &[Source],
+) -> Result<(), WriteSourceListError> {
+    let mut map = Mapping::new();
+    for src in sources {
+        let name = Value::String(src.name.clone());
+        if map.contains_key(&name) {
+            return Err(WriteSourceListError::DuplicateName(src.name.clone()));
+        }
+        if src.components.is_empty() {
+            return Err(WriteSourceListError::NoComponents(src.name.clone()));
+        }
+        map.insert(name, serde_yaml::to_value(&src.components)?);
+    }
+    serde_yaml::to_writer(&mut *writer, &map)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srclist::tests::assert_sources_eq;
+
+    const SRCLIST: &str = r#"# A hyperdrive source list.
+point:
+  - ra: 7.5
+    dec: -27.0
+    comp_type: point
+    flux_type:
+      list:
+        - freq: 180000000.0
+          i: 10.0
+          q: 0.0
+          u: 0.0
+          v: 0.0
+multi:
+  - ra: 15.0
+    dec: -26.5
+    comp_type:
+      gaussian:
+        maj: 120.0
+        min: 60.0
+        pa: 45.0
+    flux_type:
+      power_law:
+        si: -0.7
+        fd:
+          freq: 150000000.0
+          i: 3.0
+          q: 0.0
+          u: 0.0
+          v: 0.0
+  - ra: 15.3
+    dec: -26.7
+    comp_type:
+      shapelet:
+        maj: 180.0
+        min: 120.0
+        pa: 10.0
+        coeffs:
+          - n1: 0
+            n2: 0
+            value: 0.5
+    flux_type:
+      curved_power_law:
+        si: -0.7
+        fd:
+          freq: 150000000.0
+          i: 1.0
+          q: 0.0
+          u: 0.0
+          v: 0.0
+        q: -0.2
+"#;
+
+    #[test]
+    fn test_parse_source_list() {
+        let sources = parse_source_list(SRCLIST).unwrap();
+        assert_eq!(sources.len(), 2);
+        // The order of the sources is kept.
+        assert_eq!(sources[0].name, "point");
+        assert_eq!(sources[1].name, "multi");
+        assert_eq!(sources[0].components[0].comp_type, ComponentType::Point);
+
+        let multi = &sources[1].components;
+        assert_eq!(
+            multi[0].comp_type,
+            ComponentType::Gaussian {
+                maj: 120.0,
+                min: 60.0,
+                pa: 45.0
+            }
+        );
+        assert!(matches!(
+            multi[0].flux_type,
+            FluxDensityType::PowerLaw { si, .. } if si == -0.7
+        ));
+        assert!(matches!(
+            multi[1].comp_type,
+            ComponentType::Shapelet { ref coeffs, .. } if coeffs.len() == 1
+        ));
+        assert!(matches!(
+            multi[1].flux_type,
+            FluxDensityType::CurvedPowerLaw { q, .. } if q == -0.2
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = parse_source_list(SRCLIST).unwrap();
+        let mut written = vec![];
+        write_source_list(&mut written, &sources).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_sources_eq(&parse_source_list(&written).unwrap(), &sources);
+
+        // Converting from an RTS source list and back loses nothing.
+        let rts = crate::srclist::rts::parse_source_list(
+            "SOURCE a 0.5 -27.0\nFREQ 180e6 10 0 0 0\nCOMPONENT 0.6 -27.5\nGAUSSIAN 1 2 3\nFREQ 180e6 1 0 0 0\nENDCOMPONENT\nENDSOURCE\n",
+        )
+        .unwrap();
+        let mut written = vec![];
+        write_source_list(&mut written, &rts).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_sources_eq(&parse_source_list(&written).unwrap(), &rts);
+    }
+
+    #[test]
+    fn test_empty_source_list() {
+        assert_eq!(parse_source_list("# Nothing here\n"), Ok(vec![]));
+        let mut written = vec![];
+        write_source_list(&mut written, &[]).unwrap();
+        assert_eq!(
+            parse_source_list(&String::from_utf8(written).unwrap()),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn test_malformed_source_lists() {
+        assert!(matches!(
+            parse_source_list("[package]\nname = \"mongoose\"\n"),
+            Err(ReadSourceListError::Yaml(_))
+        ));
+        // Misspelled component type.
+        let s = SRCLIST.replace("comp_type: point", "comp_type: pont");
+        match parse_source_list(&s) {
+            Err(ReadSourceListError::Yaml(e)) => assert!(e.contains("point"), "{}", e),
+            r => panic!("Unexpected result {:?}", r),
+        }
+        assert_eq!(
+            parse_source_list("a: []\n"),
+            Err(ReadSourceListError::NoComponents {
+                name: "a".to_string()
+            })
+        );
+        assert_eq!(
+            parse_source_list(
+                "a:\n  - ra: 0.0\n    dec: 0.0\n    comp_type: point\n    flux_type:\n      list: []\n"
+            ),
+            Err(ReadSourceListError::EmptyFluxDensityList {
+                name: "a".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_duplicate_names() {
+        let mut sources = parse_source_list(SRCLIST).unwrap();
+        sources[1].name = "point".to_string();
+        let mut written = vec![];
+        assert!(matches!(
+            write_source_list(&mut written, &sources),
+            Err(WriteSourceListError::DuplicateName(_))
+        ));
+    }
+}
@@ -0,0 +1,445 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Sky-model source lists.
+ *
+ * Three formats are supported: the RTS format, the hyperdrive/WODEN YAML
+ * format and the "skymodel" format of André Offringa's tools (AO). Sources are
+ * read and written with `parse_source_list` and `write_source_list`, or with the
+ * functions in the format-specific modules.
+ */
+
+pub mod ao;
+pub mod error;
+pub mod hyperdrive;
+pub mod rts;
+
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use error::{ReadSourceListError, WriteSourceListError};
+
+/// The spectral index assumed for components with a single flux density.
+pub const DEFAULT_SPEC_INDEX: f64 = -0.8;
+
+/// The supported source list formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SourceListType {
+    Rts,
+    Hyperdrive,
+    Ao,
+}
+
+impl SourceListType {
+    /// Guess the format of a source list from its file extension; ".txt" is
+    /// RTS, ".yaml" or ".yml" is hyperdrive and ".skymodel" is AO.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "txt" => Some(Self::Rts),
+            "yaml" | "yml" => Some(Self::Hyperdrive),
+            "skymodel" => Some(Self::Ao),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceListType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Rts => "RTS",
+            Self::Hyperdrive => "hyperdrive",
+            Self::Ao => "AO",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for SourceListType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rts" => Ok(Self::Rts),
+            "hyperdrive" => Ok(Self::Hyperdrive),
+            "ao" => Ok(Self::Ao),
+            _ => Err(format!(
+                "Unknown source list type '{}'; expected one of rts, hyperdrive or ao",
+                s
+            )),
+        }
+    }
+}
+
+/// Parse the contents of a source list of the specified format.
+pub fn parse_source_list(
+    s: &str,
+    sl_type: SourceListType,
+) -> Result<Vec<Source>, ReadSourceListError> {
+    match sl_type {
+        SourceListType::Rts => rts::parse_source_list(s),
+        SourceListType::Hyperdrive => hyperdrive::parse_source_list(s),
+        SourceListType::Ao => ao::parse_source_list(s),
+    }
+}
+
+/// Write sources in the specified format.
+pub fn write_source_list<W: Write>(
+    writer: &mut W,
+    sources: &[Source],
+    sl_type: SourceListType,
+) -> Result<(), WriteSourceListError> {
+    match sl_type {
+        SourceListType::Rts => rts::write_source_list(writer, sources),
+        SourceListType::Hyperdrive => hyperdrive::write_source_list(writer, sources),
+        SourceListType::Ao => ao::write_source_list(writer, sources),
+    }
+}
+
+/// A sky-model source. The RTS treats all of the components of a source as a
+/// single calibrator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    pub name: String,
+    pub components: Vec<SourceComponent>,
+}
+
+/// A single component of a sky-model source.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SourceComponent {
+    /// Right ascension [degrees]
+    pub ra: f64,
+    /// Declination [degrees]
+    pub dec: f64,
+    pub comp_type: ComponentType,
+    pub flux_type: FluxDensityType,
+}
+
+/// The shape of a source component.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentType {
+    Point,
+
+    Gaussian {
+        /// Major axis [arcseconds]
+        maj: f64,
+        /// Minor axis [arcseconds]
+        min: f64,
+        /// Position angle [degrees]
+        pa: f64,
+    },
+
+    Shapelet {
+        /// Major axis [arcseconds]
+        maj: f64,
+        /// Minor axis [arcseconds]
+        min: f64,
+        /// Position angle [degrees]
+        pa: f64,
+        coeffs: Vec<ShapeletCoeff>,
+    },
+}
+
+/// A single shapelet coefficient.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShapeletCoeff {
+    pub n1: u32,
+    pub n2: u32,
+    pub value: f64,
+}
+
+/// The Stokes flux densities of a component at a single frequency.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FluxDensity {
+    /// [Hz]
+    pub freq: f64,
+    /// Stokes I [Jy]
+    pub i: f64,
+    /// Stokes Q [Jy]
+    pub q: f64,
+    /// Stokes U [Jy]
+    pub u: f64,
+    /// Stokes V [Jy]
+    pub v: f64,
+}
+
+impl FluxDensity {
+    fn scaled(&self, freq: f64, factor: f64) -> Self {
+        Self {
+            freq,
+            i: self.i * factor,
+            q: self.q * factor,
+            u: self.u * factor,
+            v: self.v * factor,
+        }
+    }
+}
+
+/// How the flux density of a component varies with frequency.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FluxDensityType {
+    /// Flux densities at specific frequencies, in the order they were
+    /// specified.
+    List(Vec<FluxDensity>),
+
+    /// S(f) = S(f0) * (f/f0)^si, where `fd` is S(f0).
+    PowerLaw { si: f64, fd: FluxDensity },
+
+    /// S(f) = S(f0) * (f/f0)^si * exp(q * ln(f/f0)^2), where `fd` is S(f0).
+    CurvedPowerLaw { si: f64, fd: FluxDensity, q: f64 },
+}
+
+impl FluxDensityType {
+    /// Estimate the flux densities at a frequency [Hz].
+    ///
+    /// For a list, a power law is fitted through the two flux densities that
+    /// bracket the frequency (or the two closest to it, if it's outside of the
+    /// listed frequencies). If there is only one flux density, the
+    /// `DEFAULT_SPEC_INDEX` is used. Power laws can't be fitted to
+    /// non-positive flux densities, so they are interpolated linearly. An
+    /// empty list has no flux density.
+    pub fn estimate_at(&self, freq_hz: f64) -> FluxDensity {
+        match self {
+            Self::List(fds) => {
+                let mut fds: Vec<&FluxDensity> = fds.iter().collect();
+                fds.sort_by(|a, b| a.freq.total_cmp(&b.freq));
+                let (fd1, fd2) = match fds.as_slice() {
+                    [] => {
+                        return FluxDensity {
+                            freq: freq_hz,
+                            i: 0.0,
+                            q: 0.0,
+                            u: 0.0,
+                            v: 0.0,
+                        }
+                    }
+                    [fd] => {
+                        return fd.scaled(freq_hz, (freq_hz / fd.freq).powf(DEFAULT_SPEC_INDEX))
+                    }
+                    _ => {
+                        let i = fds
+                            .iter()
+                            .skip(1)
+                            .position(|fd| fd.freq >= freq_hz)
+                            .unwrap_or(fds.len() - 2);
+                        (fds[i], fds[i + 1])
+                    }
+                };
+                let interp = |s1: f64, s2: f64| {
+                    if s1 > 0.0 && s2 > 0.0 {
+                        let si = (s2 / s1).ln() / (fd2.freq / fd1.freq).ln();
+                        s1 * (freq_hz / fd1.freq).powf(si)
+                    } else {
+                        s1 + (s2 - s1) * (freq_hz - fd1.freq) / (fd2.freq - fd1.freq)
+                    }
+                };
+                FluxDensity {
+                    freq: freq_hz,
+                    i: interp(fd1.i, fd2.i),
+                    q: interp(fd1.q, fd2.q),
+                    u: interp(fd1.u, fd2.u),
+                    v: interp(fd1.v, fd2.v),
+                }
+            }
+
+            Self::PowerLaw { si, fd } => fd.scaled(freq_hz, (freq_hz / fd.freq).powf(*si)),
+
+            Self::CurvedPowerLaw { si, fd, q } => {
+                let ratio = freq_hz / fd.freq;
+                fd.scaled(freq_hz, ratio.powf(*si) * (q * ratio.ln().powi(2)).exp())
+            }
+        }
+    }
+
+    /// Estimate the Stokes I flux density [Jy] at a frequency [Hz]. See
+    /// `estimate_at`.
+    pub fn estimate_stokes_i(&self, freq_hz: f64) -> f64 {
+        self.estimate_at(freq_hz).i
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use approx::*;
+
+    fn assert_fds_eq(a: &FluxDensity, b: &FluxDensity) {
+        assert_relative_eq!(a.freq, b.freq, max_relative = 1e-10);
+        assert_abs_diff_eq!(a.i, b.i, epsilon = 1e-10);
+        assert_abs_diff_eq!(a.q, b.q, epsilon = 1e-10);
+        assert_abs_diff_eq!(a.u, b.u, epsilon = 1e-10);
+        assert_abs_diff_eq!(a.v, b.v, epsilon = 1e-10);
+    }
+
+    /// Check that two sets of sources are the same, up to floating-point
+    /// precision. Positions need only match to a milliarcsecond.
+    pub(crate) fn assert_sources_eq(a: &[Source], b: &[Source]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.components.len(), b.components.len());
+            for (a, b) in a.components.iter().zip(b.components.iter()) {
+                assert_abs_diff_eq!(a.ra, b.ra, epsilon = 1e-6 / 3.6);
+                assert_abs_diff_eq!(a.dec, b.dec, epsilon = 1e-6 / 3.6);
+                match (&a.flux_type, &b.flux_type) {
+                    (FluxDensityType::List(a), FluxDensityType::List(b)) => {
+                        assert_eq!(a.len(), b.len());
+                        for (a, b) in a.iter().zip(b.iter()) {
+                            assert_fds_eq(a, b);
+                        }
+                    }
+                    (
+                        FluxDensityType::PowerLaw { si, fd },
+                        FluxDensityType::PowerLaw { si: si2, fd: fd2 },
+                    ) => {
+                        assert_abs_diff_eq!(si, si2, epsilon = 1e-10);
+                        assert_fds_eq(fd, fd2);
+                    }
+                    (
+                        FluxDensityType::CurvedPowerLaw { si, fd, q },
+                        FluxDensityType::CurvedPowerLaw {
+                            si: si2,
+                            fd: fd2,
+                            q: q2,
+                        },
+                    ) => {
+                        assert_abs_diff_eq!(si, si2, epsilon = 1e-10);
+                        assert_abs_diff_eq!(q, q2, epsilon = 1e-10);
+                        assert_fds_eq(fd, fd2);
+                    }
+                    (a, b) => panic!("Flux density types differ: {:?} {:?}", a, b),
+                }
+                match (&a.comp_type, &b.comp_type) {
+                    (ComponentType::Point, ComponentType::Point) => (),
+                    (
+                        ComponentType::Gaussian { maj, min, pa },
+                        ComponentType::Gaussian {
+                            maj: maj2,
+                            min: min2,
+                            pa: pa2,
+                        },
+                    ) => {
+                        assert_abs_diff_eq!(maj, maj2, epsilon = 1e-10);
+                        assert_abs_diff_eq!(min, min2, epsilon = 1e-10);
+                        assert_abs_diff_eq!(pa, pa2, epsilon = 1e-10);
+                    }
+                    (
+                        ComponentType::Shapelet {
+                            maj,
+                            min,
+                            pa,
+                            coeffs,
+                        },
+                        ComponentType::Shapelet {
+                            maj: maj2,
+                            min: min2,
+                            pa: pa2,
+                            coeffs: coeffs2,
+                        },
+                    ) => {
+                        assert_abs_diff_eq!(maj, maj2, epsilon = 1e-10);
+                        assert_abs_diff_eq!(min, min2, epsilon = 1e-10);
+                        assert_abs_diff_eq!(pa, pa2, epsilon = 1e-10);
+                        assert_eq!(coeffs, coeffs2);
+                    }
+                    (a, b) => panic!("Component types differ: {:?} {:?}", a, b),
+                }
+            }
+        }
+    }
+
+    fn list(fds: &[(f64, f64)]) -> FluxDensityType {
+        FluxDensityType::List(fds.iter().map(|&(freq, i)| fd(freq, i)).collect())
+    }
+
+    fn fd(freq: f64, i: f64) -> FluxDensity {
+        FluxDensity {
+            freq,
+            i,
+            q: 0.0,
+            u: 0.0,
+            v: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_estimate_list() {
+        let l = list(&[(150e6, 2.0)]);
+        assert_abs_diff_eq!(l.estimate_stokes_i(150e6), 2.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(
+            l.estimate_stokes_i(300e6),
+            2.0 * 2_f64.powf(DEFAULT_SPEC_INDEX),
+            epsilon = 1e-10
+        );
+
+        // A spectral index of -1 between 100 and 200 MHz, and -2 between 200
+        // and 400 MHz.
+        let l = list(&[(400e6, 1.0), (100e6, 8.0), (200e6, 4.0)]);
+        assert_abs_diff_eq!(l.estimate_stokes_i(200e6), 4.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(l.estimate_stokes_i(160e6), 5.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(l.estimate_stokes_i(50e6), 16.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(l.estimate_stokes_i(800e6), 0.25, epsilon = 1e-10);
+
+        let l = list(&[(100e6, 0.0), (200e6, 2.0)]);
+        assert_abs_diff_eq!(l.estimate_stokes_i(150e6), 1.0, epsilon = 1e-10);
+
+        assert_abs_diff_eq!(list(&[]).estimate_stokes_i(150e6), 0.0);
+
+        // A NaN frequency doesn't stop the other flux densities being used.
+        let l = list(&[(f64::NAN, 1.0), (100e6, 8.0), (200e6, 4.0)]);
+        assert_abs_diff_eq!(l.estimate_stokes_i(160e6), 5.0, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_estimate_power_laws() {
+        let pl = FluxDensityType::PowerLaw {
+            si: -1.0,
+            fd: FluxDensity {
+                freq: 100e6,
+                i: 8.0,
+                q: 4.0,
+                u: 0.0,
+                v: 0.0,
+            },
+        };
+        let est = pl.estimate_at(200e6);
+        assert_abs_diff_eq!(est.freq, 200e6);
+        assert_abs_diff_eq!(est.i, 4.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(est.q, 2.0, epsilon = 1e-10);
+
+        // With no curvature, a curved power law is a power law.
+        let cpl = FluxDensityType::CurvedPowerLaw {
+            si: -1.0,
+            fd: fd(100e6, 8.0),
+            q: 0.0,
+        };
+        assert_abs_diff_eq!(cpl.estimate_stokes_i(200e6), 4.0, epsilon = 1e-10);
+        // Negative curvature turns the spectrum down on both sides.
+        let cpl = FluxDensityType::CurvedPowerLaw {
+            si: 0.0,
+            fd: fd(100e6, 8.0),
+            q: -0.5,
+        };
+        let expected = 8.0 * (-0.5 * 2_f64.ln().powi(2)).exp();
+        assert_abs_diff_eq!(cpl.estimate_stokes_i(200e6), expected, epsilon = 1e-10);
+        assert_abs_diff_eq!(cpl.estimate_stokes_i(50e6), expected, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_source_list_type() {
+        let from_path = |p: &str| SourceListType::from_path(Path::new(p));
+        assert_eq!(from_path("srclist_pumav3.txt"), Some(SourceListType::Rts));
+        assert_eq!(from_path("srclist.yaml"), Some(SourceListType::Hyperdrive));
+        assert_eq!(from_path("srclist.yml"), Some(SourceListType::Hyperdrive));
+        assert_eq!(from_path("model.skymodel"), Some(SourceListType::Ao));
+        assert_eq!(from_path("srclist"), None);
+        assert_eq!("ao".parse(), Ok(SourceListType::Ao));
+        assert!("woden".parse::<SourceListType>().is_err());
+    }
+}
@@ -0,0 +1,643 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * Code to handle RTS-format source lists.
+ *
+ * A source starts with a SOURCE line (name, RA [hours], Dec [degrees]), which
+ * also starts its first component. Any further components are enclosed by
+ * COMPONENT (RA, Dec) and ENDCOMPONENT lines, and the source is ended by
+ * ENDSOURCE. Each component has one or more FREQ lines (frequency [Hz] and
+ * Stokes I, Q, U and V [Jy]), and optionally a GAUSSIAN or SHAPELET2 line
+ * (position angle [degrees], major and minor axes [arcminutes]). Shapelets
+ * have their coefficients on COEFF lines. Everything after a "#" is a comment.
+ *
+ * Source lists written by `write_source_list` are read back identically by
+ * `parse_source_list`, up to floating-point precision.
+ */
+
+use std::io::Write;
+use std::str::FromStr;
+
+use super::error::{ReadSourceListError, WriteSourceListError};
+use super::*;
+
+/// The RTS format can only list flux densities, so power laws are written as
+/// flux densities at these frequencies [Hz], which span the MWA's band. Listed
+/// flux densities are interpolated with power laws (see
+/// `FluxDensityType::estimate_at`), so only the first and last frequencies are
+/// needed for an exact power law. Curved power laws are approximated with all
+/// of them.
+pub const POWER_LAW_FREQS: [f64; 5] = [70e6, 110e6, 150e6, 200e6, 300e6];
+
+const KEYWORDS: &[&str] = &[
+    "SOURCE",
+    "COMPONENT",
+    "ENDCOMPONENT",
+    "ENDSOURCE",
+    "FREQ",
+    "GAUSSIAN",
+    "SHAPELET",
+    "SHAPELET2",
+    "COEFF",
+];
+
+/// Parse the contents of an RTS-format source list.
+pub fn parse_source_list(s: &str) -> Result<Vec<Source>, ReadSourceListError> {
+    let mut sources = vec![];
+    // The source currently being read, and the line it started on.
+    let mut source: Option<(usize, Source)> = None;
+    // The line that the current component started on, and whether it is
+    // enclosed by COMPONENT and ENDCOMPONENT.
+    let mut comp_start = 0;
+    let mut in_component = false;
+
+    for (i, line) in s.lines().enumerate() {
+        let line_num = i + 1;
+        let text = match line.find('#') {
+            Some(j) => &line[..j],
+            None => line,
+        };
+        let mut words = text.split_whitespace();
+        let keyword = match words.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let values: Vec<&str> = words.collect();
+
+        if !KEYWORDS.contains(&keyword) {
+            return Err(ReadSourceListError::UnknownKeyword {
+                line: line_num,
+                keyword: keyword.to_string(),
+            });
+        }
+
+        if keyword == "SOURCE" {
+            if let Some((start, _)) = &source {
+                return Err(ReadSourceListError::UnterminatedSource {
+                    line: line_num,
+                    start: *start,
+                });
+            }
+            check_num_values(line_num, keyword, &values, 3)?;
+            let (ra, dec) = parse_position(line_num, keyword, values[1], values[2])?;
+            source = Some((
+                line_num,
+                Source {
+                    name: values[0].to_string(),
+                    components: vec![new_component(ra, dec)],
+                },
+            ));
+            comp_start = line_num;
+            continue;
+        }
+
+        // Every other keyword must be inside a SOURCE block.
+        let src = match &mut source {
+            Some((_, src)) => src,
+            None => {
+                return Err(ReadSourceListError::OutsideSource {
+                    line: line_num,
+                    keyword: keyword.to_string(),
+                })
+            }
+        };
+
+        match keyword {
+            "COMPONENT" => {
+                if in_component {
+                    return Err(ReadSourceListError::UnterminatedComponent {
+                        line: line_num,
+                        keyword: keyword.to_string(),
+                        start: comp_start,
+                    });
+                }
+                check_flux_densities(src, comp_start)?;
+                check_num_values(line_num, keyword, &values, 2)?;
+                let (ra, dec) = parse_position(line_num, keyword, values[0], values[1])?;
+                src.components.push(new_component(ra, dec));
+                comp_start = line_num;
+                in_component = true;
+            }
+
+            "ENDCOMPONENT" => {
+                if !in_component {
+                    return Err(ReadSourceListError::UnmatchedEndComponent { line: line_num });
+                }
+                check_num_values(line_num, keyword, &values, 0)?;
+                check_flux_densities(src, comp_start)?;
+                in_component = false;
+            }
+
+            "ENDSOURCE" => {
+                if in_component {
+                    return Err(ReadSourceListError::UnterminatedComponent {
+                        line: line_num,
+                        keyword: keyword.to_string(),
+                        start: comp_start,
+                    });
+                }
+                check_num_values(line_num, keyword, &values, 0)?;
+                check_flux_densities(src, comp_start)?;
+                sources.push(source.take().unwrap().1);
+            }
+
+            "FREQ" => {
+                check_num_values(line_num, keyword, &values, 5)?;
+                let fd = FluxDensity {
+                    freq: parse_value(line_num, keyword, values[0], "a frequency")?,
+                    i: parse_value(line_num, keyword, values[1], "a flux density")?,
+                    q: parse_value(line_num, keyword, values[2], "a flux density")?,
+                    u: parse_value(line_num, keyword, values[3], "a flux density")?,
+                    v: parse_value(line_num, keyword, values[4], "a flux density")?,
+                };
+                if let FluxDensityType::List(fds) = &mut last_component(src).flux_type {
+                    fds.push(fd);
+                }
+            }
+
+            // SHAPELET is the older name for SHAPELET2; both are read the same
+            // way.
+            "GAUSSIAN" | "SHAPELET" | "SHAPELET2" => {
+                check_num_values(line_num, keyword, &values, 3)?;
+                let pa = parse_value(line_num, keyword, values[0], "a position angle")?;
+                // Convert arcminutes to arcseconds.
+                let maj = parse_value::<f64>(line_num, keyword, values[1], "a major axis")? * 60.0;
+                let min = parse_value::<f64>(line_num, keyword, values[2], "a minor axis")? * 60.0;
+                let comp = last_component(src);
+                if comp.comp_type != ComponentType::Point {
+                    return Err(ReadSourceListError::MultipleShapes {
+                        line: line_num,
+                        start: comp_start,
+                    });
+                }
+                comp.comp_type = if keyword == "GAUSSIAN" {
+                    ComponentType::Gaussian { maj, min, pa }
+                } else {
+                    ComponentType::Shapelet {
+                        maj,
+                        min,
+                        pa,
+                        coeffs: vec![],
+                    }
+                };
+            }
+
+            "COEFF" => {
+                check_num_values(line_num, keyword, &values, 3)?;
+                let coeff = ShapeletCoeff {
+                    n1: parse_coeff_index(line_num, keyword, values[0])?,
+                    n2: parse_coeff_index(line_num, keyword, values[1])?,
+                    value: parse_value(line_num, keyword, values[2], "a coefficient")?,
+                };
+                match &mut last_component(src).comp_type {
+                    ComponentType::Shapelet { coeffs, .. } => coeffs.push(coeff),
+                    _ => {
+                        return Err(ReadSourceListError::CoeffWithoutShapelet {
+                            line: line_num,
+                            start: comp_start,
+                        })
+                    }
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    if let Some((start, _)) = source {
+        return Err(ReadSourceListError::MissingEndSource { start });
+    }
+    Ok(sources)
+}
+
+/// Write sources in the RTS source list format.
+pub fn write_source_list<W: Write>(
+    writer: &mut W,
+    sources: &[Source],
+) -> Result<(), WriteSourceListError> {
+    for src in sources {
+        if src.name.is_empty() || src.name.contains(|c: char| c.is_whitespace() || c == '#') {
+            return Err(WriteSourceListError::BadName(src.name.clone()));
+        }
+        let (first, others) = match src.components.split_first() {
+            Some(c) => c,
+            None => return Err(WriteSourceListError::NoComponents(src.name.clone())),
+        };
+        if src
+            .components
+            .iter()
+            .any(|c| matches!(&c.flux_type, FluxDensityType::List(fds) if fds.is_empty()))
+        {
+            return Err(WriteSourceListError::NoFluxDensities(src.name.clone()));
+        }
+
+        let (ra, dec) = format_position(first);
+        writeln!(writer, "SOURCE {} {} {}", src.name, ra, dec)?;
+        write_component_body(writer, first)?;
+        for comp in others {
+            let (ra, dec) = format_position(comp);
+            writeln!(writer, "COMPONENT {} {}", ra, dec)?;
+            write_component_body(writer, comp)?;
+            writeln!(writer, "ENDCOMPONENT")?;
+        }
+        writeln!(writer, "ENDSOURCE")?;
+    }
+    Ok(())
+}
+
+/// Get the RA [hours] and Dec [degrees] of a component as they are written.
+fn format_position(comp: &SourceComponent) -> (f64, f64) {
+    (comp.ra.rem_euclid(360.0) / 15.0, comp.dec)
+}
+
+/// Write the flux densities and shape of a component.
+fn write_component_body<W: Write>(
+    writer: &mut W,
+    comp: &SourceComponent,
+) -> Result<(), std::io::Error> {
+    let fds = match &comp.flux_type {
+        FluxDensityType::List(fds) => fds.clone(),
+        FluxDensityType::PowerLaw { .. } => vec![
+            comp.flux_type.estimate_at(POWER_LAW_FREQS[0]),
+            comp.flux_type
+                .estimate_at(POWER_LAW_FREQS[POWER_LAW_FREQS.len() - 1]),
+        ],
+        FluxDensityType::CurvedPowerLaw { .. } => POWER_LAW_FREQS
+            .iter()
+            .map(|&f| comp.flux_type.estimate_at(f))
+            .collect(),
+    };
+    for fd in &fds {
+        writeln!(
+            writer,
+            "FREQ {:e} {} {} {} {}",
+            fd.freq, fd.i, fd.q, fd.u, fd.v
+        )?;
+    }
+    // Convert arcseconds back to arcminutes.
+    match &comp.comp_type {
+        ComponentType::Point => (),
+        ComponentType::Gaussian { maj, min, pa } => {
+            writeln!(writer, "GAUSSIAN {} {} {}", pa, maj / 60.0, min / 60.0)?
+        }
+        ComponentType::Shapelet {
+            maj,
+            min,
+            pa,
+            coeffs,
+        } => {
+            writeln!(writer, "SHAPELET2 {} {} {}", pa, maj / 60.0, min / 60.0)?;
+            for c in coeffs {
+                writeln!(writer, "COEFF {} {} {}", c.n1, c.n2, c.value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn new_component(ra: f64, dec: f64) -> SourceComponent {
+    SourceComponent {
+        ra,
+        dec,
+        comp_type: ComponentType::Point,
+        flux_type: FluxDensityType::List(vec![]),
+    }
+}
+
+/// Every source has at least one component.
+fn last_component(src: &mut Source) -> &mut SourceComponent {
+    src.components.last_mut().unwrap()
+}
+
+fn check_num_values(
+    line: usize,
+    keyword: &str,
+    values: &[&str],
+    expected: usize,
+) -> Result<(), ReadSourceListError> {
+    if values.len() == expected {
+        Ok(())
+    } else {
+        Err(ReadSourceListError::WrongNumberOfValues {
+            line,
+            keyword: keyword.to_string(),
+            expected,
+            got: values.len(),
+        })
+    }
+}
+
+/// Check that the last component of a source has at least one flux density.
+fn check_flux_densities(src: &Source, comp_start: usize) -> Result<(), ReadSourceListError> {
+    match src.components.last() {
+        Some(SourceComponent {
+            flux_type: FluxDensityType::List(fds),
+            ..
+        }) if fds.is_empty() => Err(ReadSourceListError::NoFluxDensities { start: comp_start }),
+        _ => Ok(()),
+    }
+}
+
+fn parse_value<T: FromStr>(
+    line: usize,
+    keyword: &str,
+    value: &str,
+    expected: &'static str,
+) -> Result<T, ReadSourceListError> {
+    value.parse().map_err(|_| ReadSourceListError::BadValue {
+        line,
+        keyword: keyword.to_string(),
+        value: value.to_string(),
+        expected,
+    })
+}
+
+/// Parse a shapelet coefficient index. These are usually written as floats
+/// (e.g. "1.0000000"), but must be whole numbers.
+fn parse_coeff_index(line: usize, keyword: &str, value: &str) -> Result<u32, ReadSourceListError> {
+    let index: f64 = parse_value(line, keyword, value, "a coefficient index")?;
+    if index.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&index) {
+        return Err(ReadSourceListError::BadValue {
+            line,
+            keyword: keyword.to_string(),
+            value: value.to_string(),
+            expected: "a whole-number coefficient index",
+        });
+    }
+    Ok(index as u32)
+}
+
+/// Parse an RA [hours] and Dec [degrees]. The returned RA is in degrees.
+fn parse_position(
+    line: usize,
+    keyword: &str,
+    ra: &str,
+    dec: &str,
+) -> Result<(f64, f64), ReadSourceListError> {
+    let ra: f64 = parse_value(line, keyword, ra, "an RA")?;
+    let dec: f64 = parse_value(line, keyword, dec, "a Dec")?;
+    if !(0.0..24.0).contains(&ra) {
+        return Err(ReadSourceListError::BadRa { line, ra });
+    }
+    if !(-90.0..=90.0).contains(&dec) {
+        return Err(ReadSourceListError::BadDec { line, dec });
+    }
+    Ok((ra * 15.0, dec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srclist::tests::assert_sources_eq;
+    use approx::*;
+
+    const SRCLIST: &str = r#"# A point source.
+SOURCE point 0.5 -27.0
+FREQ 180e6 10.0 0 0 0
+ENDSOURCE
+SOURCE multi 1.0 -26.5
+FREQ 150e6 3.0 0 0 0
+FREQ 200e6 2.0 0 0 0
+COMPONENT 1.01 -26.6
+GAUSSIAN 45.0 2.0 1.0
+FREQ 180e6 1.5 0 0 0
+ENDCOMPONENT
+COMPONENT 1.02 -26.7 # A shapelet.
+SHAPELET2 10.0 3.0 2.0
+COEFF 0.0000000 0.0000000 0.5
+COEFF 1.0000000 0 0.25
+FREQ 180e6 0.5 0 0 0
+ENDCOMPONENT
+ENDSOURCE
+"#;
+
+    #[test]
+    fn test_parse_source_list() {
+        let sources = parse_source_list(SRCLIST).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].name, "point");
+        assert_eq!(sources[0].components.len(), 1);
+        let comp = &sources[0].components[0];
+        assert_abs_diff_eq!(comp.ra, 7.5, epsilon = 1e-10);
+        assert_abs_diff_eq!(comp.dec, -27.0, epsilon = 1e-10);
+        assert_eq!(comp.comp_type, ComponentType::Point);
+        assert_eq!(
+            comp.flux_type,
+            FluxDensityType::List(vec![FluxDensity {
+                freq: 180e6,
+                i: 10.0,
+                q: 0.0,
+                u: 0.0,
+                v: 0.0
+            }])
+        );
+
+        let multi = &sources[1];
+        assert_eq!(multi.name, "multi");
+        assert_eq!(multi.components.len(), 3);
+        match &multi.components[0].flux_type {
+            FluxDensityType::List(fds) => assert_eq!(fds.len(), 2),
+            _ => panic!("Expected a list of flux densities"),
+        }
+        assert_abs_diff_eq!(multi.components[1].ra, 15.15, epsilon = 1e-10);
+        match multi.components[1].comp_type {
+            ComponentType::Gaussian { maj, min, pa } => {
+                assert_abs_diff_eq!(maj, 120.0, epsilon = 1e-10);
+                assert_abs_diff_eq!(min, 60.0, epsilon = 1e-10);
+                assert_abs_diff_eq!(pa, 45.0, epsilon = 1e-10);
+            }
+            _ => panic!("Expected a Gaussian"),
+        }
+        match &multi.components[2].comp_type {
+            ComponentType::Shapelet { maj, coeffs, .. } => {
+                assert_abs_diff_eq!(*maj, 180.0, epsilon = 1e-10);
+                assert_eq!(coeffs.len(), 2);
+                assert_eq!(coeffs[1].n1, 1);
+                assert_abs_diff_eq!(coeffs[1].value, 0.25, epsilon = 1e-10);
+            }
+            _ => panic!("Expected a shapelet"),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = parse_source_list(SRCLIST).unwrap();
+        let mut written = vec![];
+        write_source_list(&mut written, &sources).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with("SOURCE point 0.5 -27\nFREQ 1.8e8 10 0 0 0\nENDSOURCE\n"));
+        assert!(written.contains(
+            "COMPONENT 1.01 -26.6\nFREQ 1.8e8 1.5 0 0 0\nGAUSSIAN 45 2 1\nENDCOMPONENT\n"
+        ));
+        assert_sources_eq(&parse_source_list(&written).unwrap(), &sources);
+
+        // Writing what was read back gives the same text.
+        let mut rewritten = vec![];
+        write_source_list(&mut rewritten, &parse_source_list(&written).unwrap()).unwrap();
+        assert_eq!(String::from_utf8(rewritten).unwrap(), written);
+    }
+
+    #[test]
+    fn test_write_power_laws() {
+        let mut sources = parse_source_list(SRCLIST).unwrap();
+        let fd = FluxDensity {
+            freq: 150e6,
+            i: 2.0,
+            q: 0.0,
+            u: 0.0,
+            v: 0.0,
+        };
+        let pl = FluxDensityType::PowerLaw {
+            si: -0.7,
+            fd: fd.clone(),
+        };
+        let cpl = FluxDensityType::CurvedPowerLaw {
+            si: -0.7,
+            fd,
+            q: -0.2,
+        };
+        sources[0].components[0].flux_type = pl.clone();
+        sources[1].components[1].flux_type = cpl.clone();
+        let mut written = vec![];
+        write_source_list(&mut written, &sources).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let read = parse_source_list(&written).unwrap();
+
+        // Power laws are written exactly, and curved power laws are exact at
+        // the written frequencies.
+        let read_pl = &read[0].components[0].flux_type;
+        let read_cpl = &read[1].components[1].flux_type;
+        match (read_pl, read_cpl) {
+            (FluxDensityType::List(l1), FluxDensityType::List(l2)) => {
+                assert_eq!(l1.len(), 2);
+                assert_eq!(l2.len(), POWER_LAW_FREQS.len());
+            }
+            _ => panic!("Expected lists of flux densities"),
+        }
+        for &f in &[50e6, 150e6, 182.415e6, 400e6] {
+            assert_relative_eq!(
+                read_pl.estimate_stokes_i(f),
+                pl.estimate_stokes_i(f),
+                max_relative = 1e-10
+            );
+        }
+        for &f in &POWER_LAW_FREQS {
+            assert_relative_eq!(
+                read_cpl.estimate_stokes_i(f),
+                cpl.estimate_stokes_i(f),
+                max_relative = 1e-10
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_bad_sources() {
+        let mut sources = parse_source_list(SRCLIST).unwrap();
+        let mut written = vec![];
+
+        sources[0].name = "two words".to_string();
+        let result = write_source_list(&mut written, &sources);
+        assert!(matches!(result, Err(WriteSourceListError::BadName(_))));
+
+        sources[0].name = "point".to_string();
+        sources[1].components[2].flux_type = FluxDensityType::List(vec![]);
+        let result = write_source_list(&mut written, &sources);
+        assert!(matches!(
+            result,
+            Err(WriteSourceListError::NoFluxDensities(_))
+        ));
+
+        sources[1].components.clear();
+        let result = write_source_list(&mut written, &sources);
+        assert!(matches!(result, Err(WriteSourceListError::NoComponents(_))));
+    }
+
+    #[test]
+    fn test_empty_source_list() {
+        assert_eq!(parse_source_list("# Nothing here\n\n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_malformed_source_lists() {
+        let err = |s| parse_source_list(s).unwrap_err();
+        assert_eq!(
+            err("[package]\nname = \"mongoose\"\n"),
+            ReadSourceListError::UnknownKeyword {
+                line: 1,
+                keyword: "[package]".to_string()
+            }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nFREQ 180e6 1 0 0 0\n"),
+            ReadSourceListError::MissingEndSource { start: 1 }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nFREQ 180e6 1 0 0 0\nSOURCE b 0.5 -27.0\n"),
+            ReadSourceListError::UnterminatedSource { line: 3, start: 1 }
+        );
+        assert_eq!(
+            err("FREQ 180e6 1 0 0 0\n"),
+            ReadSourceListError::OutsideSource {
+                line: 1,
+                keyword: "FREQ".to_string()
+            }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nENDSOURCE\n"),
+            ReadSourceListError::NoFluxDensities { start: 1 }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nFREQ 180e6 1 0 0\nENDSOURCE\n"),
+            ReadSourceListError::WrongNumberOfValues {
+                line: 2,
+                keyword: "FREQ".to_string(),
+                expected: 5,
+                got: 4
+            }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nFREQ 180e6 bright 0 0 0\nENDSOURCE\n"),
+            ReadSourceListError::BadValue {
+                line: 2,
+                keyword: "FREQ".to_string(),
+                value: "bright".to_string(),
+                expected: "a flux density"
+            }
+        );
+        assert_eq!(
+            err("SOURCE a 25.0 -27.0\n"),
+            ReadSourceListError::BadRa { line: 1, ra: 25.0 }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nFREQ 180e6 1 0 0 0\nCOMPONENT 0.5 -27.0\nFREQ 180e6 1 0 0 0\nENDSOURCE\n"),
+            ReadSourceListError::UnterminatedComponent {
+                line: 5,
+                keyword: "ENDSOURCE".to_string(),
+                start: 3
+            }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nFREQ 180e6 1 0 0 0\nENDCOMPONENT\n"),
+            ReadSourceListError::UnmatchedEndComponent { line: 3 }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nGAUSSIAN 0 1 1\nSHAPELET2 0 1 1\n"),
+            ReadSourceListError::MultipleShapes { line: 3, start: 1 }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nGAUSSIAN 0 1 1\nCOEFF 0 0 1.0\n"),
+            ReadSourceListError::CoeffWithoutShapelet { line: 3, start: 1 }
+        );
+        assert_eq!(
+            err("SOURCE a 0.5 -27.0\nSHAPELET2 0 1 1\nCOEFF 0.5 0 1.0\n"),
+            ReadSourceListError::BadValue {
+                line: 3,
+                keyword: "COEFF".to_string(),
+                value: "0.5".to_string(),
+                expected: "a whole-number coefficient index"
+            }
+        );
+    }
+}
@@ -0,0 +1,360 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * This module tests the reflag-mwaf-files command-line interface.
+ */
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use assert_cmd::Command;
+    use fitsio::FitsFile;
+    use mongoose::cotter::{write_mwaf, MwafFile, MwafHeader};
+    use mongoose::rts::parse_flagged_tiles;
+    use mwalib::mwalibContext;
+    use ndarray::Array3;
+
+    fn cmd() -> Command {
+        Command::cargo_bin("reflag-mwaf-files").unwrap()
+    }
+
+    /// Unzip the mwaf file of the first coarse channel of 1065880128 into a
+    /// directory.
+    fn unzip_mwaf(dir: &Path) {
+        let mut z =
+            zip::ZipArchive::new(std::fs::File::open("tests/1065880128_01.mwaf.zip").unwrap())
+                .unwrap();
+        let mut z_mwaf = z.by_index(0).unwrap();
+        let mut mwaf = std::fs::File::create(dir.join("1065880128_01.mwaf")).unwrap();
+        std::io::copy(&mut z_mwaf, &mut mwaf).unwrap();
+    }
+
+    #[test]
+    fn reflag() {
+        let dir = tempfile::tempdir().unwrap();
+        unzip_mwaf(dir.path());
+        cmd()
+            .current_dir(&dir)
+            .args(&["--threshold", "0.8"])
+            .assert()
+            .success();
+        assert!(dir.path().join("RTS_1065880128_01.mwaf").exists());
+        // Tiles aren't flagged unless asked for.
+        assert!(!dir.path().join("flagged_tiles.txt").exists());
+    }
+
+    #[test]
+    fn flag_tiles() {
+        let dir = tempfile::tempdir().unwrap();
+        unzip_mwaf(dir.path());
+        let output = cmd()
+            .current_dir(&dir)
+            .args(&["--tile-threshold", "0.5"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Flagged 2 tiles"));
+        assert!(dir.path().join("RTS_1065880128_01.mwaf").exists());
+        let tiles = std::fs::read_to_string(dir.path().join("flagged_tiles.txt")).unwrap();
+        assert_eq!(parse_flagged_tiles(&tiles).unwrap(), vec![82, 123]);
+    }
+
+    #[test]
+    fn flag_metafits_tiles() {
+        let dir = tempfile::tempdir().unwrap();
+        unzip_mwaf(dir.path());
+        let metafits = dir.path().join("1065880128.metafits");
+        std::fs::copy("tests/1065880128.metafits", &metafits).unwrap();
+        cmd()
+            .current_dir(&dir)
+            .args(&["--tile-threshold", "0.21", "--metafits"])
+            .arg(&metafits)
+            .assert()
+            .success();
+        let tiles = std::fs::read_to_string(dir.path().join("flagged_tiles.txt")).unwrap();
+        assert_eq!(parse_flagged_tiles(&tiles).unwrap(), vec![3, 21, 82, 123]);
+
+        // 82 and 123 were already flagged in the metafits, which is left
+        // alone; the tiles are flagged in a copy.
+        let flagged = |metafits: &Path| {
+            let context = mwalibContext::new(&metafits, &[]).unwrap();
+            let mut flagged: Vec<u32> = context
+                .antennas
+                .iter()
+                .filter(|a| a.x_pol.flagged && a.y_pol.flagged)
+                .map(|a| a.antenna)
+                .collect();
+            flagged.sort_unstable();
+            flagged
+        };
+        assert_eq!(flagged(&metafits), vec![82, 123]);
+        assert_eq!(
+            flagged(&dir.path().join("RTS_1065880128.metafits")),
+            vec![3, 21, 82, 123]
+        );
+
+        // The metafits must be of the mwaf files' obsid (GPSTIME), whatever
+        // the files are called.
+        let dir = tempfile::tempdir().unwrap();
+        write_observation(dir.path(), 1065880248);
+        cmd()
+            .current_dir(&dir)
+            .args(&["--tile-threshold", "0.5", "--metafits"])
+            .arg(&metafits)
+            .assert()
+            .failure();
+        // Nothing is written.
+        assert!(!dir.path().join("flagged_tiles.txt").exists());
+        assert!(!dir.path().join("RTS_1065880128.metafits").exists());
+        for band in 1..=3 {
+            let rts_mwaf = format!("RTS_1065880128_{:02}.mwaf", band);
+            assert!(!dir.path().join(rts_mwaf).exists());
+        }
+    }
+
+    #[test]
+    fn flag_tiles_one_obsid() {
+        // The files are all named after one obsid, but one of them is from
+        // another.
+        let dir = tempfile::tempdir().unwrap();
+        write_observation(dir.path(), 1065880128);
+        cmd()
+            .current_dir(&dir)
+            .args(&["--tile-threshold", "0.5"])
+            .assert()
+            .success();
+        let other = tempfile::tempdir().unwrap();
+        write_observation(other.path(), 1065880248);
+        std::fs::copy(
+            other.path().join("1065880128_03.mwaf"),
+            dir.path().join("1065880128_03.mwaf"),
+        )
+        .unwrap();
+        let read_outputs = || {
+            let mut outputs = vec![std::fs::read(dir.path().join("flagged_tiles.txt")).unwrap()];
+            for band in 1..=3 {
+                let rts_mwaf = format!("RTS_1065880128_{:02}.mwaf", band);
+                outputs.push(std::fs::read(dir.path().join(rts_mwaf)).unwrap());
+            }
+            outputs
+        };
+        let before = read_outputs();
+        let output = cmd()
+            .current_dir(&dir)
+            .args(&["--tile-threshold", "0.5"])
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("more than one obsid (1065880128, 1065880248)"),
+            "{}",
+            output
+        );
+        // Nothing is written; the RTS mwaf files would be different, as the
+        // third band has changed.
+        assert_eq!(read_outputs(), before);
+
+        // With --observation, the files are grouped by their obsid, not their
+        // names. Channel 3 is bad in both bands of 1065880128, but the third
+        // band is on its own.
+        let output = cmd()
+            .current_dir(&dir)
+            .arg("--observation")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "1065880128: Flagged channels [3] in all 2 coarse bands\n1065880248: Flagged channels [] in all 1 coarse bands\n"
+        );
+        let rts = |band: usize| dir.path().join(format!("RTS_1065880128_{:02}.mwaf", band));
+        assert_eq!(reflagged_channels(&rts(2)), vec![3]);
+        assert!(reflagged_channels(&rts(3)).is_empty());
+    }
+
+    /// Get the channels that the RTS will flag from the REFLG keys of an RTS
+    /// mwaf file.
+    fn reflagged_channels(rts_mwaf: &Path) -> Vec<i64> {
+        let mut f = FitsFile::open(rts_mwaf).unwrap();
+        let hdu = f.hdu(1).unwrap();
+        let mut channels = vec![];
+        while let Ok(c) = hdu.read_key::<i64>(&mut f, &format!("REFLG_{:02}", channels.len())) {
+            channels.push(c);
+        }
+        channels
+    }
+
+    /// Write 3 coarse bands of mwaf flags for a tiny observation, with a
+    /// GPSTIME of `gps_time`. The files are always named after 1065880128.
+    /// Channel 3 is totally flagged in the first 2 bands, and channel 5 in the
+    /// first. The second timestep is also totally flagged in the first band.
+    fn write_observation(dir: &Path, gps_time: u64) {
+        let mut header = MwafHeader {
+            version: "1.0".to_string(),
+            gps_time,
+            num_channels: 8,
+            num_antennas: 3,
+            num_scans: 2,
+            num_pols: 1,
+            gpubox_number: 1,
+            cotter_version: None,
+            cotter_version_date: None,
+        };
+        for band in 1..=3 {
+            header.gpubox_number = band;
+            let mut flags = Array3::from_elem(header.flags_shape(), false);
+            if band < 3 {
+                flags.slice_mut(ndarray::s![.., .., 3]).fill(true);
+            }
+            if band == 1 {
+                flags.slice_mut(ndarray::s![.., .., 5]).fill(true);
+                flags.slice_mut(ndarray::s![1, .., ..]).fill(true);
+            }
+            let file = dir.join(format!("1065880128_{:02}.mwaf", band));
+            write_mwaf(&file, &header, flags.view()).unwrap();
+        }
+    }
+
+    #[test]
+    fn reflag_observation() {
+        // Independently, each band only flags its own bad channels.
+        let dir = tempfile::tempdir().unwrap();
+        write_observation(dir.path(), 1065880128);
+        cmd()
+            .current_dir(&dir)
+            .args(&["--threshold", "0.8"])
+            .assert()
+            .success();
+        let rts = |band: usize| dir.path().join(format!("RTS_1065880128_{:02}.mwaf", band));
+        assert_eq!(reflagged_channels(&rts(1)), vec![3, 5]);
+        assert_eq!(reflagged_channels(&rts(2)), vec![3]);
+        assert!(reflagged_channels(&rts(3)).is_empty());
+
+        // Together, channel 3 is bad in 2 of 3 bands, so it's flagged
+        // everywhere.
+        let output = cmd()
+            .current_dir(&dir)
+            .arg("--observation")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "1065880128: Flagged channels [3] in all 3 coarse bands\n"
+        );
+        assert_eq!(reflagged_channels(&rts(1)), vec![3, 5]);
+        assert_eq!(reflagged_channels(&rts(2)), vec![3]);
+        assert_eq!(reflagged_channels(&rts(3)), vec![3]);
+
+        // Not if the global threshold is higher.
+        cmd()
+            .current_dir(&dir)
+            .args(&["--observation", "--global-threshold", "0.7"])
+            .assert()
+            .success();
+        assert!(reflagged_channels(&rts(3)).is_empty());
+
+        // Edge and centre channels are always flagged.
+        cmd()
+            .current_dir(&dir)
+            .args(&["--edge-channels", "1", "--centre-channel"])
+            .assert()
+            .success();
+        assert_eq!(reflagged_channels(&rts(1)), vec![0, 3, 4, 5, 7]);
+        assert_eq!(reflagged_channels(&rts(3)), vec![0, 4, 7]);
+    }
+
+    #[test]
+    fn reflag_timesteps() {
+        let dir = tempfile::tempdir().unwrap();
+        write_observation(dir.path(), 1065880128);
+        let rts = |band: usize| dir.path().join(format!("RTS_1065880128_{:02}.mwaf", band));
+        // Is each timestep of an RTS mwaf file totally flagged?
+        let flagged_timesteps = |band: usize| -> Vec<bool> {
+            let mwaf = MwafFile::read(&rts(band)).unwrap();
+            mwaf.flags
+                .outer_iter()
+                .map(|t| t.iter().all(|&f| f))
+                .collect()
+        };
+
+        // Independently, only the first band's second timestep is bad.
+        let output = cmd()
+            .current_dir(&dir)
+            .args(&["--timestep-threshold", "0.5"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(
+            "1065880128_01.mwaf: Flagged 1 timesteps with more than 50% of their data flagged: [1]\n"
+        ));
+        assert_eq!(flagged_timesteps(1), vec![false, true]);
+        assert_eq!(flagged_timesteps(2), vec![false, false]);
+        assert_eq!(flagged_timesteps(3), vec![false, false]);
+        // The channels are still reflagged.
+        assert_eq!(reflagged_channels(&rts(1)), vec![3, 5]);
+
+        // Together, the second timestep is flagged in 37.5% of the data, so
+        // it's flagged in every band.
+        let output = cmd()
+            .current_dir(&dir)
+            .args(&["--observation", "--timestep-threshold", "0.3"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(
+            "1065880128: Flagged 1 timesteps with more than 30% of their data flagged: [1]\n"
+        ));
+        for band in 1..=3 {
+            assert_eq!(flagged_timesteps(band), vec![false, true]);
+        }
+        assert_eq!(reflagged_channels(&rts(3)), vec![3]);
+    }
+
+    #[test]
+    fn bad_args() {
+        let dir = tempfile::tempdir().unwrap();
+        unzip_mwaf(dir.path());
+        cmd()
+            .current_dir(&dir)
+            .args(&["--tile-threshold", "1.5"])
+            .assert()
+            .failure();
+        cmd()
+            .current_dir(&dir)
+            .args(&["--metafits", "1065880128.metafits"])
+            .assert()
+            .failure();
+        cmd()
+            .current_dir(&dir)
+            .args(&["--observation", "--global-threshold", "2"])
+            .assert()
+            .failure();
+        cmd()
+            .current_dir(&dir)
+            .args(&["--timestep-threshold", "2"])
+            .assert()
+            .failure();
+    }
+}
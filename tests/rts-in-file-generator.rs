@@ -11,9 +11,13 @@
 #[cfg(test)]
 mod tests {
     use assert_cmd::Command;
+    use mongoose::rts::parse_flagged_tiles;
 
+    /// The generator, without the settings of the user's config file.
     fn cmd() -> Command {
-        Command::cargo_bin("rts-in-file-generator").unwrap()
+        let mut cmd = Command::cargo_bin("rts-in-file-generator").unwrap();
+        cmd.env("XDG_CONFIG_HOME", "/road/to/no/where");
+        cmd
     }
 
     #[test]
@@ -23,11 +27,9 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
+            .arg("--no-validate")
             .arg("--metafits=tests/1065880128.metafits")
-            // Yeah, Cargo.toml isn't a source list, but we just need this to be
-            // a real file. rts-in-file-generator doesn't verify that this file
-            // is a real source list.
-            .arg("--srclist=Cargo.toml")
+            .arg("--srclist=tests/srclist_patch.txt")
             .assert()
             .success();
 
@@ -36,7 +38,7 @@ mod tests {
             .arg("patch")
             .arg("--base-dir=/road/to/no/where")
             .arg("--metafits=tests/1065880128.metafits")
-            .arg("--srclist=Cargo.toml")
+            .arg("--srclist=tests/srclist_patch.txt")
             .assert()
             .failure();
 
@@ -44,8 +46,9 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
+            .arg("--no-validate")
             .arg("--metafits=tests/1065880128.metafits_asdf")
-            .arg("--srclist=Cargo.toml")
+            .arg("--srclist=tests/srclist_patch.txt")
             .assert()
             .failure();
 
@@ -53,6 +56,7 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
+            .arg("--no-validate")
             .arg("--metafits=tests/1065880128.metafits")
             .arg("--srclist=ultimate-sky-model.txt")
             .assert()
@@ -62,7 +66,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .assert()
             .failure();
     }
@@ -72,14 +77,16 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .assert()
             .failure();
 
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .assert()
             .failure();
@@ -87,7 +94,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .assert()
@@ -96,7 +104,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -106,7 +115,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -117,7 +127,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -129,7 +140,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -142,7 +154,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -156,7 +169,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -172,7 +186,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -191,7 +206,8 @@ mod tests {
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
-            .arg("--srclist=Cargo.toml")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
             .arg("--obsid=1000000000")
             .arg("--corr-dump-time=2")
             .arg("--corr-dumps-per-cadence=32")
@@ -210,46 +226,1068 @@ mod tests {
 
     #[test]
     fn fee_beam() {
-        // No MWA_BEAM_FILE variable.
+        // Enough of an MWA FEE beam file to pass mongoose's check.
+        let write_beam_file = |path: &std::path::Path| {
+            let mut contents = b"\x89HDF\r\n\x1a\n".to_vec();
+            contents.extend_from_slice(
+                b"modes\0X1_51200000\0Y1_51200000\0X16_51200000\0Y16_51200000\0",
+            );
+            std::fs::write(path, contents).unwrap();
+        };
+        let beam_dir = tempfile::tempdir().unwrap();
+        let config_dir = tempfile::tempdir().unwrap();
+        let patch = || {
+            let mut cmd = cmd();
+            cmd.arg("patch")
+                .arg("--base-dir=..")
+                .arg("--no-validate")
+                .arg("--metafits=tests/1065880128.metafits")
+                .arg("--srclist=tests/srclist_patch.txt")
+                .env_remove("MWA_BEAM_FILE")
+                .env("MONGOOSE_FEE_BEAM_DIRS", beam_dir.path())
+                .env("XDG_CONFIG_HOME", config_dir.path());
+            cmd
+        };
+
+        // No MWA_BEAM_FILE variable, user config file or beam file in the
+        // search directories.
+        let output = patch()
+            .arg("--use-fee-beam")
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("MWA_BEAM_FILE isn't set"));
+        assert!(output.contains(&format!(
+            "    {}",
+            beam_dir
+                .path()
+                .join("mwa_full_embedded_element_pattern.h5")
+                .display()
+        )));
+
+        // The file in the search directories.
+        let found = beam_dir.path().join("mwa_full_embedded_element_pattern.h5");
+        write_beam_file(&found);
+        let output = patch()
+            .arg("--use-fee-beam")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("hdf5Filename={}\n", found.display())));
+
+        // The file from the user config file, relative to it, which takes
+        // precedence. Any setting can be in the user config file; top-level
+        // settings that patch doesn't have are skipped.
+        std::fs::create_dir(config_dir.path().join("mongoose")).unwrap();
+        write_beam_file(&config_dir.path().join("mongoose/beam.h5"));
+        std::fs::write(
+            config_dir.path().join("mongoose/config.toml"),
+            "use-fee-beam = true\nfee-beam-file = \"beam.h5\"\nnum-cals = 500\n",
+        )
+        .unwrap();
+        let output = patch().assert().success().get_output().stdout.clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!(
+            "hdf5Filename={}\n",
+            config_dir.path().join("mongoose/beam.h5").display()
+        )));
+
+        // --config and the command line take precedence over the user config
+        // file.
+        let config = config_dir.path().join("eor.toml");
+        std::fs::write(&config, "fee-beam-file = \"beam.h5\"\n").unwrap();
+        write_beam_file(&config_dir.path().join("beam.h5"));
+        let output = patch()
+            .arg("--config")
+            .arg(&config)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!(
+            "hdf5Filename={}\n",
+            config_dir.path().join("beam.h5").display()
+        )));
+        patch().arg("--no-use-fee-beam").assert().success();
+        // Settings in a subcommand's table must be for that subcommand.
+        std::fs::write(
+            config_dir.path().join("mongoose/config.toml"),
+            "[patch]\nnum-cals = 500\n",
+        )
+        .unwrap();
+        patch().assert().failure();
+        std::fs::remove_file(config_dir.path().join("mongoose/config.toml")).unwrap();
+
+        // A file that isn't the FEE beam is rejected, even with --no-validate.
+        let output = patch()
+            .arg("--use-fee-beam")
+            .arg("--fee-beam-file=Cargo.toml")
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Cargo.toml is not an HDF5 file"));
+
+        // Without --use-fee-beam, the file isn't used.
+        patch().arg("--fee-beam-file=Cargo.toml").assert().success();
+
+        // MWA_BEAM_FILE variable used.
+        patch()
+            .arg("--use-fee-beam")
+            .env("MWA_BEAM_FILE", "Cargo.lock")
+            .assert()
+            .failure();
+        patch()
+            .arg("--use-fee-beam")
+            .env("MWA_BEAM_FILE", &found)
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn diff() {
+        let patch = tempfile::NamedTempFile::new().unwrap();
+        let patch2 = tempfile::NamedTempFile::new().unwrap();
+        let peel = tempfile::NamedTempFile::new().unwrap();
+        for (mode, file) in &[("patch", &patch), ("patch", &patch2), ("peel", &peel)] {
+            cmd()
+                .arg(mode)
+                .arg("--base-dir=..")
+                .arg("--no-validate")
+                .arg("--metafits=tests/1065880128.metafits")
+                .arg("--srclist=tests/srclist_patch.txt")
+                .arg(format!("--output-file={}", file.path().display()))
+                .assert()
+                .success();
+        }
+
+        // The same settings at different times don't differ.
         cmd()
+            .arg("diff")
+            .arg(patch.path())
+            .arg(patch2.path())
+            .assert()
+            .success()
+            .stdout("");
+
+        let output = cmd()
+            .arg("diff")
+            .arg(patch.path())
+            .arg(peel.path())
+            .assert()
+            .code(1)
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("-CorrDumpsPerCadence=128\n"));
+        assert!(output.contains("+CorrDumpsPerCadence=16\n"));
+        assert!(output.contains("+NumberOfSourcesToPeel=1000\n"));
+        assert!(!output.contains("FscrunchChan"));
+
+        // Keys that mongoose doesn't know about are compared, and reported
+        // with their line numbers.
+        let mut text = std::fs::read_to_string(peel.path()).unwrap();
+        text.push_str("SomeNewKey=1\n");
+        let line = text.lines().count();
+        std::fs::write(peel.path(), text).unwrap();
+        let output = cmd()
+            .arg("diff")
+            .arg(patch.path())
+            .arg(peel.path())
+            .assert()
+            .code(1)
+            .get_output()
+            .clone();
+        assert!(String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("+SomeNewKey=1\n"));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            stderr,
+            format!(
+                "{}: Line {}: RTS key 'SomeNewKey' is not known to mongoose\n",
+                peel.path().display(),
+                line
+            )
+        );
+
+        // Files that aren't .in files can't be compared.
+        cmd()
+            .arg("diff")
+            .arg(patch.path())
+            .arg("Cargo.toml")
+            .assert()
+            .failure()
+            .stdout("");
+    }
+
+    #[test]
+    fn config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("profile.toml");
+        let srclist = std::env::current_dir()
+            .unwrap()
+            .join("tests/srclist_patch.txt");
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+base-dir = ".."
+srclist = "{}"
+obsid = 1000000000
+corr-dump-time = 2
+corr-dumps-per-cadence = 32
+num-integration-bins = 6
+num-fine-chans = 32
+fine-chan-width = 0.04
+base-freq = 150
+force-ra = 0
+force-dec = -27
+ha-pointing-centre = 0
+dec-pointing-centre = -27
+subband-ids = [1, 2, 3]
+fscrunch = 4
+no-cotter-flags = true
+no-validate = true
+
+[patch]
+num-iterations = 3
+fscrunch = 8
+
+[peel]
+num-cals = 5
+"#,
+                srclist.display()
+            ),
+        )
+        .unwrap();
+
+        let output = cmd()
+            .arg("patch")
+            .arg(format!("--config={}", config.display()))
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        // The [patch] table overrides the top-level settings.
+        assert!(output.contains("FscrunchChan=8\n"));
+        assert!(output.contains("NumberOfIterations=3\n"));
+        assert!(output.contains("SubBandIDs=1,2,3\n"));
+        assert!(output.contains("ImportCotterFlags=0\n"));
+
+        let output = cmd()
+            .arg("peel")
+            .arg(format!("--config={}", config.display()))
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("FscrunchChan=4\n"));
+        assert!(output.contains("NumberOfIonoCalibrators=5\n"));
+
+        // Command-line arguments override the config file.
+        let output = cmd()
+            .arg("patch")
+            .arg(format!("--config={}", config.display()))
+            .arg("--fscrunch=1")
+            .args(&["--subband-ids", "5", "6"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("FscrunchChan=1\n"));
+        assert!(output.contains("SubBandIDs=5,6\n"));
+
+        // Flags set in the config file can be turned off.
+        let output = cmd()
             .arg("patch")
+            .arg(format!("--config={}", config.display()))
+            .arg("--cotter-flags")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("ImportCotterFlags=1\n"));
+
+        // Unknown keys are rejected.
+        std::fs::write(&config, "base-dir = \"..\"\nnot-a-real-key = 1\n").unwrap();
+        cmd()
+            .arg("patch")
+            .arg(format!("--config={}", config.display()))
+            .assert()
+            .failure();
+
+        // Tables must be named after subcommands.
+        std::fs::write(&config, "base-dir = \"..\"\n[pell]\nnum-cals = 5\n").unwrap();
+        cmd()
+            .arg("patch")
+            .arg(format!("--config={}", config.display()))
+            .assert()
+            .failure();
+
+        // The config file must exist.
+        cmd()
+            .arg("patch")
+            .arg("--config=/road/to/no/where.toml")
             .arg("--base-dir=..")
+            .arg("--no-validate")
             .arg("--metafits=tests/1065880128.metafits")
-            .arg("--srclist=Cargo.toml")
-            .arg("--use-fee-beam")
-            .env_remove("MWA_BEAM_FILE")
+            .arg("--srclist=tests/srclist_patch.txt")
             .assert()
             .failure();
+    }
+
+    #[test]
+    fn magic() {
+        let output = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("MaxFrequency=200\n"));
+        // These come from the metafits.
+        assert!(output.contains("ArrayNumberOfStations=128\n"));
+        assert!(output.contains("ArrayPositionLat=-26.7033194"));
+        assert!(output.contains("ArrayPositionLong=116.6708152"));
+        assert!(output.contains("calBaselineMin=20\n"));
+        assert!(output.contains("ImageOversampling=3\n"));
+        assert!(output.contains("UsePacketInput=0\n"));
+        assert!(output.contains("StorePixelMatrices=0\n"));
+
+        let output = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--max-frequency=300")
+            .arg("--array-num-stations=256")
+            .arg("--array-position-lat=-30.5")
+            .arg("--array-position-long=120")
+            .arg("--cal-baseline-min=10")
+            .arg("--cal-short-baseline-taper=25.5")
+            .arg("--image-oversampling=2")
+            .arg("--start-processing-at=4")
+            .arg("--use-packet-input")
+            .arg("--use-threaded-vi")
+            .arg("--store-pixel-matrices")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("MaxFrequency=300\n"));
+        assert!(output.contains("ArrayNumberOfStations=256\n"));
+        assert!(output.contains("ArrayPositionLat=-30.5\n"));
+        assert!(output.contains("ArrayPositionLong=120\n"));
+        assert!(output.contains("calBaselineMin=10\n"));
+        assert!(output.contains("calShortBaselineTaper=25.5\n"));
+        assert!(output.contains("ImageOversampling=2\n"));
+        assert!(output.contains("StartProcessingAt=4\n"));
+        assert!(output.contains("UsePacketInput=1\n"));
+        assert!(output.contains("UseThreadedVI=1\n"));
+        assert!(output.contains("StorePixelMatrices=1\n"));
+    }
+
+    #[test]
+    fn start_processing_at() {
+        // The test metafits has a QUACKTIM of 0.5s and 0.5s integrations.
+        let output = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("StartProcessingAt=1\n"));
 
-        // File manually specified. The file doesn't get checked for validity.
+        // There are no gpubox files in the tests directory.
         cmd()
             .arg("patch")
+            .arg("--base-dir=tests")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--read-gpubox-times")
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn num_iterations() {
+        // The test metafits has 112s of data, and the first 0.5s is quacked.
+        // This leaves enough data for one 64s patch cadence and thirteen 8s
+        // peel cadences; the leftover data should be warned about.
+        for (mode, expected) in &[("patch", 1), ("peel", 13)] {
+            let assert = cmd()
+                .arg(mode)
+                .arg("--base-dir=..")
+                .arg("--no-validate")
+                .arg("--metafits=tests/1065880128.metafits")
+                .arg("--srclist=tests/srclist_patch.txt")
+                .assert()
+                .success();
+            let output = assert.get_output();
+            let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+            assert!(stdout.contains(&format!("NumberOfIterations={}\n", expected)));
+            let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+            assert!(stderr.contains("is not a multiple of the"));
+        }
+
+        // Skipping 16s of data leaves exactly 12 peel cadences.
+        let assert = cmd()
+            .arg("peel")
             .arg("--base-dir=..")
+            .arg("--no-validate")
             .arg("--metafits=tests/1065880128.metafits")
-            .arg("--srclist=Cargo.toml")
-            .arg("--use-fee-beam")
-            .arg("--fee-beam-file=Cargo.toml")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--start-processing-at=32")
+            // Don't ask for more sources than the source list has.
+            .arg("--num-cals=3")
+            .arg("--num-peel=3")
             .assert()
             .success();
+        let output = assert.get_output();
+        let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+        assert!(stdout.contains("NumberOfIterations=12\n"));
+        assert!(output.stderr.is_empty());
+
+        let output = cmd()
+            .arg("peel")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--num-iterations=3")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("NumberOfIterations=3\n"));
+    }
+
+    #[test]
+    fn cadence() {
+        let output = cmd()
+            .arg("peel")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--cadence=16")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("CorrDumpTime=0.5\n"));
+        assert!(output.contains("CorrDumpsPerCadence=32\n"));
+        assert!(output.contains("NumberOfIntegrationBins=5\n"));
 
+        // The cadence must be a multiple of the integration time.
         cmd()
+            .arg("peel")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--cadence=7.3")
+            .assert()
+            .failure();
+
+        // An MWAX-like integration time.
+        let output = cmd()
             .arg("patch")
             .arg("--base-dir=..")
+            .arg("--no-validate")
             .arg("--metafits=tests/1065880128.metafits")
-            .arg("--srclist=Cargo.toml")
-            .arg("--fee-beam-file=Cargo.toml")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--corr-dump-time=0.2")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("CorrDumpTime=0.2\n"));
+        assert!(output.contains("CorrDumpsPerCadence=320\n"));
+        assert!(output.contains("NumberOfIntegrationBins=7\n"));
+    }
+
+    #[test]
+    fn fine_channels() {
+        let run = |fine_chan_width: &str| {
+            cmd()
+                .arg("patch")
+                .arg("--base-dir=..")
+                .arg("--no-validate")
+                .arg("--srclist=tests/srclist_patch.txt")
+                .arg("--obsid=1000000000")
+                .arg("--corr-dump-time=2")
+                .arg(format!("--fine-chan-width={}", fine_chan_width))
+                .arg("--base-freq=150")
+                .arg("--force-ra=0")
+                .arg("--force-dec=-27")
+                .arg("--ha-pointing-centre=0")
+                .arg("--dec-pointing-centre=-27")
+                .args(&["--subband-ids", "1", "2", "3"])
+                .assert()
+        };
+
+        // MWAX channelisations.
+        for (width, num_chans) in &[("0.08", 16), ("0.16", 8), ("0.01", 128)] {
+            let output = run(width).success().get_output().stdout.clone();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains(&format!("NumberOfChannels={}\n", num_chans)));
+        }
+
+        // 30 kHz doesn't divide 1.28 MHz.
+        run("0.03").failure();
+    }
+
+    #[test]
+    fn non_contiguous_subbands() {
+        // Selecting non-contiguous subbands should produce a .in file per
+        // group of contiguous subbands.
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("rts_patch.in");
+        cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .args(&["--subband-ids", "1", "2", "5", "6"])
+            .arg("-o")
+            .arg(&output)
             .assert()
             .success();
+        assert!(!output.exists());
+        let first = std::fs::read_to_string(dir.path().join("rts_patch_01.in")).unwrap();
+        assert!(first.contains("SubBandIDs=1,2\n"));
+        assert!(first.contains("ObservationFrequencyBase=167.035\n"));
+        let second = std::fs::read_to_string(dir.path().join("rts_patch_02.in")).unwrap();
+        assert!(second.contains("SubBandIDs=5,6\n"));
+        assert!(second.contains("ObservationFrequencyBase=167.035\n"));
 
-        // MWA_BEAM_FILE variable used.
+        // Leaving out subband 1 doesn't change the base frequency.
+        let output = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .args(&["--subband-ids", "13", "14", "15", "16"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("SubBandIDs=13,14,15,16\n"));
+        assert!(output.contains("ObservationFrequencyBase=167.035\n"));
+
+        // Multiple .in files can't be written to stdout.
         cmd()
             .arg("patch")
             .arg("--base-dir=..")
+            .arg("--no-validate")
             .arg("--metafits=tests/1065880128.metafits")
-            .arg("--srclist=Cargo.toml")
-            .arg("--use-fee-beam")
-            .env("MWA_BEAM_FILE", "Cargo.lock")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .args(&["--subband-ids", "1", "2", "5", "6"])
+            .assert()
+            .failure();
+
+        // Subbands that aren't in the metafits.
+        cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .args(&["--subband-ids", "25"])
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn job() {
+        let dir = tempfile::tempdir().unwrap();
+        let job_dir = dir.path().join("2020-09-29_1307");
+        let template = dir.path().join("template.sh");
+        std::fs::write(
+            &template,
+            "#!/bin/bash -l\n#SBATCH --job-name={job_name}\n#SBATCH --nodes={num_nodes}\n# {obsid}\nmpirun -np {num_nodes} rts_gpu {in_file}\n",
+        )
+        .unwrap();
+        cmd()
+            .arg("job")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--patch-srclist=tests/srclist_patch.txt")
+            .arg("--peel-srclist=tests/srclist_peel.txt")
+            .arg("--num-cals=500")
+            .arg("--scheduler-template")
+            .arg(&template)
+            .arg("--job-dir")
+            .arg(&job_dir)
+            .assert()
+            .success();
+
+        let patch = std::fs::read_to_string(job_dir.join("rts_patch.in")).unwrap();
+        assert!(patch.starts_with("// RTS in file to patch obsid 1065880128"));
+        assert!(patch.contains("srclist_patch.txt\n"));
+        let peel = std::fs::read_to_string(job_dir.join("rts_peel.in")).unwrap();
+        assert!(peel.starts_with("// RTS in file to peel obsid 1065880128"));
+        assert!(peel.contains("srclist_peel.txt\n"));
+        assert!(peel.contains("NumberOfIonoCalibrators=500\n"));
+
+        let setup = std::fs::read_to_string(job_dir.join("rts_setup.sh")).unwrap();
+        assert!(setup.starts_with(
+            "#!/bin/bash -l\n#SBATCH --job-name=se_1065880128\n#SBATCH --nodes=1\n# 1065880128\n"
+        ));
+        // The setup script has the resolved arguments, not the given ones.
+        let cwd = std::env::current_dir().unwrap();
+        assert!(setup.contains(&format!(
+            " --metafits={} ",
+            cwd.join("tests/1065880128.metafits").display()
+        )));
+        assert!(setup.contains(" --num-cals=500 "));
+        assert!(!setup.contains("--base-dir=.. "));
+        assert!(!setup.contains("rts_gpu"));
+        let run = std::fs::read_to_string(job_dir.join("rts_run.sh")).unwrap();
+        assert!(run.starts_with(
+            "#!/bin/bash -l\n#SBATCH --job-name=rts_1065880128\n#SBATCH --nodes=25\n"
+        ));
+        assert!(
+            run.contains("mpirun -np 25 rts_gpu rts_patch.in\nmpirun -np 25 rts_gpu rts_peel.in\n")
+        );
+        assert!(!run.contains("srun"));
+
+        // The setup script re-creates the job from anywhere.
+        std::fs::remove_file(job_dir.join("rts_patch.in")).unwrap();
+        Command::new("bash")
+            .arg(job_dir.join("rts_setup.sh"))
+            .current_dir(dir.path())
+            .env("USER", "nobody")
             .assert()
             .success();
+        let patch = std::fs::read_to_string(job_dir.join("rts_patch.in")).unwrap();
+        assert!(patch.contains(&format!(
+            "MetafitsFilename={}\n",
+            cwd.join("tests/1065880128.metafits").display()
+        )));
+
+        // A template needs a command to run the RTS.
+        std::fs::write(&template, "#!/bin/bash -l\n#SBATCH --nodes={num_nodes}\n").unwrap();
+        let stderr = cmd()
+            .arg("job")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--scheduler-template")
+            .arg(&template)
+            .arg("--job-dir")
+            .arg(dir.path().join("no_command"))
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(String::from_utf8(stderr).unwrap().contains("{in_file}"));
+
+        // Without a template, SLURM directives are used. The patch and peel
+        // source lists default to --srclist.
+        let job_dir = dir.path().join("default");
+        cmd()
+            .arg("job")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--job-dir")
+            .arg(&job_dir)
+            .assert()
+            .success();
+        let run = std::fs::read_to_string(job_dir.join("rts_run.sh")).unwrap();
+        assert!(run.contains("#SBATCH --nodes=25\n"));
+        assert!(run.contains("srun -n 25 rts_gpu rts_patch.in\nsrun -n 25 rts_gpu rts_peel.in\n"));
+        let peel = std::fs::read_to_string(job_dir.join("rts_peel.in")).unwrap();
+        assert!(peel.contains("srclist_patch.txt\n"));
+
+        // A missing source list.
+        cmd()
+            .arg("job")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--patch-srclist=tests/srclist_patch.txt")
+            .arg("--peel-srclist=/road/to/no/where")
+            .arg("--job-dir")
+            .arg(&job_dir)
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn both() {
+        let dir = tempfile::tempdir().unwrap();
+        let patch_output = dir.path().join("rts_patch.in");
+        let peel_output = dir.path().join("rts_peel.in");
+        cmd()
+            .arg("both")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--peel-srclist=tests/srclist_peel.txt")
+            .arg("--num-cals=500")
+            .arg("--peel-num-iterations=3")
+            .arg("--patch-write-vis-to-uvfits")
+            .arg("--patch-output")
+            .arg(&patch_output)
+            .arg("--peel-output")
+            .arg(&peel_output)
+            .assert()
+            .success();
+
+        // The files should be the same as those made separately.
+        let patch = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--write-vis-to-uvfits")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let peel = cmd()
+            .arg("peel")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_peel.txt")
+            .arg("--num-cals=500")
+            .arg("--num-iterations=3")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let separate_patch = dir.path().join("separate_patch.in");
+        let separate_peel = dir.path().join("separate_peel.in");
+        std::fs::write(&separate_patch, patch).unwrap();
+        std::fs::write(&separate_peel, peel).unwrap();
+        for (a, b) in &[
+            (&patch_output, &separate_patch),
+            (&peel_output, &separate_peel),
+        ] {
+            cmd()
+                .arg("diff")
+                .arg(a)
+                .arg(b)
+                .assert()
+                .success()
+                .stdout("");
+        }
+
+        // Only the patch and peel outputs can be used.
+        cmd()
+            .arg("both")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("-o")
+            .arg(dir.path().join("rts.in"))
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        std::fs::create_dir(&data_dir).unwrap();
+        std::fs::copy(
+            "tests/1065880128.metafits",
+            data_dir.join("1065880128.metafits"),
+        )
+        .unwrap();
+        let patch_template = format!("{}/{{obsid}}/rts_patch.in", dir.path().display());
+        let peel_template = format!("{}/{{obsid}}_peel.in", dir.path().display());
+        let batch = || {
+            let mut cmd = cmd();
+            cmd.arg("batch")
+                .arg("--srclist=tests/srclist_patch.txt")
+                .arg("--patch-template")
+                .arg(&patch_template)
+                .arg("--peel-template")
+                .arg(&peel_template);
+            cmd
+        };
+
+        // There are no mwaf files next to the metafits.
+        let output = batch()
+            .arg(&data_dir)
+            .arg("/road/to/no/where.metafits")
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("No metafits files found matching /road/to/no/where.metafits"));
+
+        let output = batch()
+            .arg(&data_dir)
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("0 of 1 observations succeeded"));
+        assert!(output.contains("RTS_1065880128_01.mwaf"));
+        assert!(!dir.path().join("1065880128").exists());
+
+        // Add the gpubox and mwaf files, and use a glob.
+        for i in 1..=24 {
+            for f in &[
+                format!("1065880128_20131015134830_gpubox{:02}_00.fits", i),
+                format!("RTS_1065880128_{:02}.mwaf", i),
+            ] {
+                std::fs::File::create(data_dir.join(f)).unwrap();
+            }
+        }
+        let output = batch()
+            .arg(data_dir.join("*.metafits"))
+            .assert()
+            .success()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("1 of 1 observations succeeded"));
+        let patch = std::fs::read_to_string(dir.path().join("1065880128/rts_patch.in")).unwrap();
+        assert!(patch.starts_with("// RTS in file to patch obsid 1065880128"));
+        assert!(patch.contains(&format!(
+            "BaseFilename={}/*_gpubox\n",
+            data_dir.canonicalize().unwrap().display()
+        )));
+        let peel = std::fs::read_to_string(dir.path().join("1065880128_peel.in")).unwrap();
+        assert!(peel.starts_with("// RTS in file to peel obsid 1065880128"));
+    }
+
+    #[test]
+    fn validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let run = || {
+            let mut cmd = cmd();
+            cmd.arg("patch")
+                .arg("--base-dir")
+                .arg(dir.path())
+                .arg("--metafits=tests/1065880128.metafits")
+                .arg("--srclist=tests/srclist_patch.txt")
+                .args(&["--subband-ids", "1", "2"]);
+            cmd
+        };
+
+        // All of the problems should be reported at once.
+        std::fs::File::create(dir.path().join("RTS_1065880128_01.mwaf")).unwrap();
+        let output = run().assert().failure().get_output().stderr.clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("*_gpubox01_*.fits"));
+        assert!(output.contains("*_gpubox02_*.fits"));
+        assert!(!output.contains("RTS_1065880128_01.mwaf"));
+        assert!(output.contains("RTS_1065880128_02.mwaf"));
+
+        // The FEE beam file must exist too.
+        let output = run()
+            .arg("--use-fee-beam")
+            .arg("--fee-beam-file=/road/to/no/where.h5")
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("/road/to/no/where.h5"));
+
+        // ... and really be an MWA FEE beam file.
+        let output = run()
+            .arg("--use-fee-beam")
+            .arg("--fee-beam-file=Cargo.toml")
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Cargo.toml is not an HDF5 file"));
+
+        for f in &[
+            "1065880128_20131015134830_gpubox01_00.fits",
+            "1065880128_20131015134830_gpubox02_00.fits",
+            "RTS_1065880128_02.mwaf",
+        ] {
+            std::fs::File::create(dir.path().join(f)).unwrap();
+        }
+        run().assert().success();
+
+        // gpubox files from another observation are a problem.
+        std::fs::File::create(
+            dir.path()
+                .join("1065880248_20131015135030_gpubox02_00.fits"),
+        )
+        .unwrap();
+        run().assert().failure();
+        run().arg("--no-validate").assert().success();
+    }
+
+    #[test]
+    fn dead_dipoles() {
+        // None of the tiles of 1065880128 have dead dipoles, so the tile flags
+        // file is empty.
+        let dir = tempfile::tempdir().unwrap();
+        let output = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--dead-dipole-tile-flags")
+            .arg(dir.path().join("{obsid}/flagged_tiles.txt"))
+            .assert()
+            .success()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("dead dipoles"), "{}", output);
+        let flags =
+            std::fs::read_to_string(dir.path().join("1065880128/flagged_tiles.txt")).unwrap();
+        assert_eq!(parse_flagged_tiles(&flags).unwrap(), Vec::<u32>::new());
+
+        // Without a metafits, there are no dipole delays to look at.
+        let output = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--obsid=1065880128")
+            .arg("--corr-dump-time=0.5")
+            .arg("--fine-chan-width=0.04")
+            .arg("--subband-ids=1")
+            .arg("--base-freq=167.035")
+            .arg("--force-ra=0")
+            .arg("--force-dec=-27")
+            .arg("--ha-pointing-centre=0")
+            .arg("--dec-pointing-centre=-27")
+            .arg("--dead-dipole-tile-flags")
+            .arg(dir.path().join("flagged_tiles.txt"))
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("--dead-dipole-tile-flags was specified without --metafits"));
+        assert!(!dir.path().join("flagged_tiles.txt").exists());
+    }
+
+    #[test]
+    fn srclist() {
+        // Files that aren't RTS source lists are rejected.
+        let output = cmd()
+            .arg("patch")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=Cargo.toml")
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Cargo.toml is not a valid RTS source list"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let unterminated = dir.path().join("unterminated.txt");
+        std::fs::write(&unterminated, "SOURCE a 0.0 -27.0\nFREQ 180e6 1 0 0 0\n").unwrap();
+        cmd()
+            .arg("both")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_patch.txt")
+            .arg("--peel-srclist")
+            .arg(&unterminated)
+            .arg("--patch-output")
+            .arg(dir.path().join("rts_patch.in"))
+            .arg("--peel-output")
+            .arg(dir.path().join("rts_peel.in"))
+            .assert()
+            .failure();
+
+        // Asking for more calibrators than there are sources is only a
+        // warning.
+        let output = cmd()
+            .arg("peel")
+            .arg("--base-dir=..")
+            .arg("--no-validate")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--srclist=tests/srclist_peel.txt")
+            .arg("--num-cals=5")
+            .arg("--num-peel=2")
+            .assert()
+            .success()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output
+            .contains("WARNING: --num-cals is 5, but tests/srclist_peel.txt only has 2 sources"));
+        assert!(!output.contains("--num-peel"));
     }
 }
@@ -0,0 +1,114 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * This module tests the srclist-by-beam command-line interface.
+ */
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use assert_cmd::Command;
+    use mongoose::srclist::{rts::parse_source_list, Source};
+
+    fn cmd() -> Command {
+        Command::cargo_bin("srclist-by-beam").unwrap()
+    }
+
+    fn read(path: &Path) -> Vec<Source> {
+        parse_source_list(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let output = cmd()
+            .current_dir(dir.path())
+            .arg("--srclist")
+            .arg(cwd.join("tests/srclist_patch.txt"))
+            .arg("--metafits")
+            .arg(cwd.join("tests/1065880128.metafits"))
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "srclist_patch_patch1000.txt\nsrclist_patch_peel2000.txt\n"
+        );
+
+        // All of the sources are combined into one for patching, named after
+        // the brightest.
+        let patch = read(&dir.path().join("srclist_patch_patch1000.txt"));
+        assert_eq!(patch.len(), 1);
+        assert_eq!(patch[0].name, "J000000-270000");
+        assert_eq!(patch[0].components.len(), 4);
+
+        let peel = read(&dir.path().join("srclist_patch_peel2000.txt"));
+        assert_eq!(peel.len(), 3);
+        assert_eq!(peel[0].name, "J000000-270000");
+        assert_eq!(peel[1].name, "J000400-263000");
+        assert_eq!(peel[1].components.len(), 2);
+    }
+
+    #[test]
+    fn num_sources_and_cutoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let patch_output = dir.path().join("patch.txt");
+        let peel_output = dir.path().join("peel.txt");
+        let run = || {
+            let mut cmd = cmd();
+            cmd.arg("--srclist=tests/srclist_patch.txt")
+                .arg("--metafits=tests/1065880128.metafits")
+                .arg("--patch-output")
+                .arg(&patch_output)
+                .arg("--peel-output")
+                .arg(&peel_output);
+            cmd
+        };
+
+        run()
+            .args(&["-n", "1", "--num-peel", "2"])
+            .assert()
+            .success();
+        let patch = read(&patch_output);
+        assert_eq!(patch[0].components.len(), 1);
+        assert_eq!(read(&peel_output).len(), 2);
+
+        // J235600-280000 is more than 4 degrees from the pointing centre.
+        let output = run()
+            .arg("--cutoff=4")
+            .assert()
+            .success()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("but only 2 are within 4 degrees"));
+        let peel = read(&peel_output);
+        assert_eq!(peel.len(), 2);
+        assert!(peel.iter().all(|s| s.name != "J235600-280000"));
+
+        run().arg("--cutoff=0.5").assert().failure();
+    }
+
+    #[test]
+    fn bad_srclist() {
+        let dir = tempfile::tempdir().unwrap();
+        cmd()
+            .arg("--srclist=Cargo.toml")
+            .arg("--metafits=tests/1065880128.metafits")
+            .arg("--patch-output")
+            .arg(dir.path().join("patch.txt"))
+            .arg("--peel-output")
+            .arg(dir.path().join("peel.txt"))
+            .assert()
+            .failure();
+        assert!(!dir.path().join("patch.txt").exists());
+    }
+}
@@ -0,0 +1,116 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/*!
+ * This module tests the srclist-convert command-line interface.
+ */
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use assert_cmd::Command;
+    use mongoose::srclist::{parse_source_list, Source, SourceListType};
+
+    fn cmd() -> Command {
+        Command::cargo_bin("srclist-convert").unwrap()
+    }
+
+    fn read(path: &Path, sl_type: SourceListType) -> Vec<Source> {
+        parse_source_list(&std::fs::read_to_string(path).unwrap(), sl_type).unwrap()
+    }
+
+    #[test]
+    fn rts_to_hyperdrive_and_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = dir.path().join("srclist.yaml");
+        let output = cmd()
+            .arg("tests/srclist_patch.txt")
+            .arg(&yaml)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Converted 3 sources to "));
+
+        // The output type can be specified instead of guessed.
+        let rts = dir.path().join("srclist.rts");
+        cmd()
+            .arg(&yaml)
+            .arg(&rts)
+            .args(&["--output-type", "rts"])
+            .assert()
+            .success();
+        let original = read(Path::new("tests/srclist_patch.txt"), SourceListType::Rts);
+        assert_eq!(read(&yaml, SourceListType::Hyperdrive), original);
+        assert_eq!(read(&rts, SourceListType::Rts).len(), original.len());
+    }
+
+    #[test]
+    fn to_ao() {
+        let dir = tempfile::tempdir().unwrap();
+        let ao = dir.path().join("srclist.skymodel");
+        cmd()
+            .arg("tests/srclist_peel.txt")
+            .arg(&ao)
+            .assert()
+            .success();
+        let sources = read(&ao, SourceListType::Ao);
+        assert_eq!(
+            sources.len(),
+            read(Path::new("tests/srclist_peel.txt"), SourceListType::Rts).len()
+        );
+
+        // srclist_patch.txt has a shapelet, which AO source lists can't have.
+        // Nothing should be written.
+        let ao = dir.path().join("shapelet.skymodel");
+        let output = cmd()
+            .arg("tests/srclist_patch.txt")
+            .arg(&ao)
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("J235600-280000"), "{}", output);
+        assert!(!ao.exists());
+    }
+
+    #[test]
+    fn unknown_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = cmd()
+            .arg("tests/srclist_patch.txt")
+            .arg(dir.path().join("srclist.out"))
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("please specify --output-type"));
+
+        cmd()
+            .arg("tests/srclist_patch.txt")
+            .arg(dir.path().join("srclist.out"))
+            .args(&["--output-type", "woden"])
+            .assert()
+            .failure();
+
+        let output = cmd()
+            .arg("Cargo.toml")
+            .arg(dir.path().join("srclist.yaml"))
+            .args(&["--input-type", "rts"])
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Cargo.toml is not a valid RTS source list"));
+    }
+}